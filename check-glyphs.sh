@@ -0,0 +1,6 @@
+#!/bin/sh
+# Checks that the default reading of the sample eye glyphs reproduces the first
+# 52 units of east-1 and west-1 in all-original.csv
+./target/release/glyphs data/ciphertext/sample.eyes -o target/sample.csv > /dev/null || exit 1
+head -n 2 data/ciphertext/all-original.csv | cut -d, -f1-53 > target/sample-expected.csv
+{ cat target/sample.csv; echo; } | diff target/sample-expected.csv - && echo "Default reading matches all-original.csv"
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Distributed search on localhost: a coordinator and WORKERS workers (3 by
+# default) with 2 worklets each. Workers must be given the same message data,
+# condition, cipher and cipher configuration as the coordinator
+WORKERS=${WORKERS:-3}
+ADDRESS=127.0.0.1:7300
+SEARCH="./target/release/search data/ciphertext/all-original.csv out(0,0)==out(1,0) vigenere"
+CONFIG="(modulus: 83, max_period: 4)"
+
+$SEARCH --coordinator $ADDRESS --ranges 16 -- "$CONFIG" &
+COORDINATOR=$!
+sleep 1
+
+for i in $(seq $WORKERS); do
+    $SEARCH --worker $ADDRESS -m 2 -- "$CONFIG" > worker-$i.log 2>&1 &
+done
+
+wait $COORDINATOR
+wait
@@ -0,0 +1,39 @@
+#!/bin/sh
+# Validates the vigenere cipher against the gctak practice text. The text has
+# no J or V, so it's read with the merged I/J and U/V alphabet (modulus 24).
+# Keys are ranked by the bigram score of their plaintexts, with a model built
+# from CORPUS (any English text). First, a known sentence is encrypted with
+# KNOWN_KEY, and the search must find KNOWN_KEY again. Then the practice text
+# is searched and decrypted with the best key, which must be EXPECTED_KEY if
+# passed. MODE is Vigenere by default, or Beaufort or VariantBeaufort
+set -e
+CORPUS=${CORPUS:?"pass the path of an English text in CORPUS"}
+MODE=${MODE:-Vigenere}
+ALPHABET=data/alphabets/english_ij_uv.csv
+DATA=data/ciphertext/gctak-24-practice-1.txt
+CONFIG="(modulus: 24, max_period: 5, mode: $MODE)"
+MODE_NAME=$(echo $MODE | sed 's/\([a-z]\)\([A-Z]\)/\1-\2/' | tr 'A-Z' 'a-z')
+KNOWN_KEY=${KNOWN_KEY:-"[$MODE_NAME mod 24: 3,14,7,1,20]"}
+
+./target/release/corpus "$CORPUS" target/practice-alphabet.csv -n target/practice-ngrams.csv -a $ALPHABET -u -f J=I -f V=U --ngram-len 2 > /dev/null
+
+best_key() {
+    ./target/release/search "$1" 'out_ngram_score(0, 2)' vigenere -a $ALPHABET --ngram-model target/practice-ngrams.csv --top 1 -- "$CONFIG" | grep -o '\[[a-z-]* mod 24: [0-9,]*\]' | tail -n 1
+}
+
+echo "IT WAS THE BEST OF TIMES IT WAS THE WORST OF TIMES IT WAS THE AGE OF WISDOM IT WAS THE AGE OF FOOLISHNESS IT WAS THE EPOCH OF BELIEF IT WAS THE EPOCH OF INCREDULITY" > target/practice-known.txt
+./target/release/decrypt target/practice-known.txt vigenere "$KNOWN_KEY" -e -a $ALPHABET -o target/practice-known.csv -- "$CONFIG" > /dev/null
+FOUND_KEY=$(best_key target/practice-known.csv)
+if [ "$FOUND_KEY" != "$KNOWN_KEY" ]; then
+    echo "Expected the search to find $KNOWN_KEY, but it found $FOUND_KEY"
+    exit 1
+fi
+
+echo "Found the known key $KNOWN_KEY"
+
+KEY=$(best_key $DATA)
+./target/release/decrypt $DATA vigenere "$KEY" -a $ALPHABET -- "$CONFIG"
+if [ -n "$EXPECTED_KEY" ] && [ "$KEY" != "$EXPECTED_KEY" ]; then
+    echo "Expected the practice text to be decrypted with $EXPECTED_KEY, but the best key is $KEY"
+    exit 1
+fi
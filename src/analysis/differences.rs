@@ -0,0 +1,76 @@
+use crate::data::message::{Message, MessageList};
+
+use super::{periodicity::get_index_of_coincidence, unit_totals::UnitTotals};
+
+/*
+ * Differences between consecutive units, modulo the alphabet size. Progressive
+ * ciphers (where the key advances by a constant amount after each unit) turn a
+ * constant drift into a repeated difference, so the differences of their
+ * ciphertext have a much less uniform distribution than the ciphertext itself.
+ * K-th differences are the differences of the (k-1)-th differences, for keys
+ * that advance in more complex ways.
+ */
+
+/**
+ * K-th differences of some units, modulo the given modulo, which must be in the
+ * range 1..=256. The result has k less units than the input, or none if the
+ * input is too short. 0-th differences are the units themselves, also modulo
+ * the given modulo
+ */
+pub fn get_differences(units: &[u8], modulo: usize, order: usize) -> Vec<u8> {
+    debug_assert!((1..=256).contains(&modulo));
+
+    let mut differences: Vec<u8> = units.iter().map(|u| (*u as usize % modulo) as u8).collect();
+    for _ in 0..order {
+        differences = differences.windows(2)
+            .map(|pair| ((pair[1] as usize + modulo - pair[0] as usize) % modulo) as u8)
+            .collect();
+    }
+
+    differences
+}
+
+/// K-th differences of each message, as messages with the same names
+pub fn get_message_differences(messages: &MessageList, modulo: usize, order: usize) -> MessageList {
+    let mut difference_messages = MessageList::default();
+    for message in messages {
+        let mut difference_message = Message::from_name(message.name.clone());
+        difference_message.data.extend_from_slice(&get_differences(&message.data, modulo, order));
+        difference_messages.push(difference_message);
+    }
+
+    difference_messages
+}
+
+pub struct DifferenceStats {
+    /// occurrences of each difference, in all messages
+    pub totals: UnitTotals,
+    /// index of coincidence of the differences of each message
+    pub message_iocs: Vec<f64>,
+    /// index of coincidence of the differences of all messages
+    pub ioc: f64,
+}
+
+impl DifferenceStats {
+    pub fn from_messages(messages: &MessageList, modulo: usize, order: usize) -> Self {
+        let difference_messages = get_message_differences(messages, modulo, order);
+        let totals = UnitTotals::from_messages(&difference_messages);
+        let message_iocs = difference_messages.iter()
+            .map(|message| get_index_of_coincidence(&UnitTotals::from_units(message.data.iter().copied())))
+            .collect();
+
+        Self { ioc: get_index_of_coincidence(&totals), totals, message_iocs }
+    }
+
+    /// The most common differences and their occurrences, most common first
+    pub fn get_most_common(&self, count: usize) -> Vec<(u8, usize)> {
+        let mut differences: Vec<(u8, usize)> = self.totals.data.iter().enumerate()
+            .filter(|(_, total)| **total > 0)
+            .map(|(difference, total)| (difference as u8, *total))
+            .collect();
+
+        differences.sort_by(|(a, a_total), (b, b_total)| b_total.cmp(a_total).then(a.cmp(b)));
+        differences.truncate(count);
+        differences
+    }
+}
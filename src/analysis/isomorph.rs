@@ -0,0 +1,225 @@
+use std::collections::HashSet;
+
+use crate::data::message::MessageList;
+
+use super::alphabet::MAX_UNITS;
+
+/*
+ * Finds substrings that repeat within and across messages, either exactly, or
+ * as isomorphs. Two substrings are isomorphic if they have the same pattern of
+ * repeated units, for example, ABCAB and XYZXY, which is what a plaintext
+ * encrypted twice with different monoalphabetic substitutions looks like.
+ * Substrings are compared along "diagonals": pairs of starting positions
+ * that are advanced together, so matches never have insertions or deletions.
+ */
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    Exact,
+    Isomorphic,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubstringMatch {
+    pub kind: MatchKind,
+    pub message_a: usize,
+    pub start_a: usize,
+    pub message_b: usize,
+    pub start_b: usize,
+    pub len: usize,
+    /// units within the match that differ (exact matches) or that break the
+    /// pattern (isomorphs), if gaps are allowed
+    pub mismatches: usize,
+}
+
+/**
+ * Calls the callback with the units of each diagonal, for all pairs of
+ * messages (including each message with itself, at different positions), and
+ * the starting positions of the diagonal
+ */
+fn for_each_diagonal<F: FnMut(usize, usize, usize, usize, &[u8], &[u8])>(messages: &MessageList, mut callback: F) {
+    for ma in 0..messages.len() {
+        let a = &messages[ma].data;
+
+        for mb in ma..messages.len() {
+            let b = &messages[mb].data;
+
+            if ma == mb {
+                for shift in 1..a.len() {
+                    callback(ma, 0, mb, shift, &a[..a.len() - shift], &b[shift..]);
+                }
+            } else {
+                for sb in 0..b.len() {
+                    let len = a.len().min(b.len() - sb);
+                    callback(ma, 0, mb, sb, &a[..len], &b[sb..sb + len]);
+                }
+
+                for sa in 1..a.len() {
+                    let len = b.len().min(a.len() - sa);
+                    callback(ma, sa, mb, 0, &a[sa..sa + len], &b[..len]);
+                }
+            }
+        }
+    }
+}
+
+fn sort_matches(matches: &mut [SubstringMatch]) {
+    matches.sort_by(|a, b| {
+        b.len.cmp(&a.len)
+            .then(a.message_a.cmp(&b.message_a))
+            .then(a.start_a.cmp(&b.start_a))
+            .then(a.message_b.cmp(&b.message_b))
+            .then(a.start_b.cmp(&b.start_b))
+    });
+}
+
+/**
+ * Finds maximal runs of identical units that occur at least twice, with at
+ * least min_len matching units. Runs separated by up to max_gap differing
+ * units are merged into a single match, which is useful for messages that
+ * share long prefixes with a few differences. Longest matches first
+ */
+pub fn find_shared_runs(messages: &MessageList, min_len: usize, max_gap: usize) -> Vec<SubstringMatch> {
+    let mut matches = Vec::new();
+
+    for_each_diagonal(messages, |message_a, sa, message_b, sb, a, b| {
+        // (start, end, mismatches) of the current run, relative to the
+        // diagonal
+        let mut run: Option<(usize, usize, usize)> = None;
+        let mut gap = 0usize;
+
+        let mut emit = |run: (usize, usize, usize)| {
+            let (start, end, mismatches) = run;
+            if end - start - mismatches >= min_len.max(1) {
+                matches.push(SubstringMatch { kind: MatchKind::Exact, message_a, start_a: sa + start, message_b, start_b: sb + start, len: end - start, mismatches });
+            }
+        };
+
+        for t in 0..a.len() {
+            if a[t] == b[t] {
+                match &mut run {
+                    Some((_, end, mismatches)) => {
+                        *mismatches += gap;
+                        *end = t + 1;
+                    },
+                    None => run = Some((t, t + 1, 0)),
+                }
+
+                gap = 0;
+            } else if let Some(current) = run {
+                gap += 1;
+                if gap > max_gap {
+                    emit(current);
+                    run = None;
+                    gap = 0;
+                }
+            }
+        }
+
+        if let Some(current) = run {
+            emit(current);
+        }
+    });
+
+    sort_matches(&mut matches);
+    matches
+}
+
+/**
+ * Finds pairs of isomorphic substrings with at least min_len units that follow
+ * the pattern. Up to max_gap units that would break the pattern are skipped,
+ * and aren't used for the pattern. Matches are trimmed so that they start and
+ * end with a repeated unit, since units that only appear once match any other
+ * unique unit, and exact matches are skipped, since those are found by
+ * find_shared_runs. Longest matches first
+ */
+pub fn find_isomorphs(messages: &MessageList, min_len: usize, max_gap: usize) -> Vec<SubstringMatch> {
+    let mut matches = Vec::new();
+    let mut found = HashSet::new();
+    // positions of the units skipped for the current start
+    let mut skipped = Vec::<usize>::new();
+
+    // unit mappings in both directions, stamped with the start they belong to
+    // so that they don't need to be cleared for every start
+    let mut stamp = 0usize;
+    let mut a_to_b = [(usize::MAX, 0u8); MAX_UNITS];
+    let mut b_to_a = [(usize::MAX, 0u8); MAX_UNITS];
+    let mut counts = [(usize::MAX, 0usize); MAX_UNITS];
+
+    for_each_diagonal(messages, |message_a, sa, message_b, sb, a, b| {
+        let mut prev_end = 0usize;
+
+        for start in 0..a.len() {
+            stamp += 1;
+            skipped.clear();
+
+            // extend as far as the mapping stays a bijection, skipping up to
+            // max_gap units that don't fit it
+            let mut end = start;
+            while end < a.len() {
+                let (x, y) = (a[end], b[end]);
+                let (x_stamp, x_to) = a_to_b[x as usize];
+                let (y_stamp, y_to) = b_to_a[y as usize];
+                if (x_stamp == stamp && x_to != y) || (y_stamp == stamp && y_to != x) {
+                    if skipped.len() == max_gap { break }
+                    skipped.push(end);
+                } else {
+                    a_to_b[x as usize] = (stamp, y);
+                    b_to_a[y as usize] = (stamp, x);
+                }
+
+                end += 1;
+            }
+
+            // removing a unit from the start never makes the mapping
+            // inconsistent, so if this ends where the previous start ended,
+            // it's contained in the previous start's match. With gaps, the
+            // skipped units may differ, so this is only an approximation
+            let left_maximal = start == 0 || prev_end < end;
+            prev_end = end;
+            if !left_maximal || end - start < min_len { continue }
+
+            for t in start..end {
+                if skipped.contains(&t) { continue }
+                let count = &mut counts[a[t] as usize];
+                *count = if count.0 == stamp { (stamp, count.1 + 1) } else { (stamp, 1) };
+            }
+
+            let repeated = |t: &usize| !skipped.contains(t) && counts[a[*t] as usize].1 > 1;
+            let (Some(first), Some(last)) = ((start..end).find(repeated), (start..end).rfind(repeated)) else { continue };
+            let len = last + 1 - first;
+            let mismatches = skipped.iter().filter(|t| (first..=last).contains(*t)).count();
+            if len - mismatches < min_len.max(1) || (first..=last).all(|t| a[t] == b[t] || skipped.contains(&t)) { continue }
+
+            if found.insert((message_a, sa + first, message_b, sb + first, len)) {
+                matches.push(SubstringMatch { kind: MatchKind::Isomorphic, message_a, start_a: sa + first, message_b, start_b: sb + first, len, mismatches });
+            }
+        }
+    });
+
+    sort_matches(&mut matches);
+    matches
+}
+
+/**
+ * Pattern of repeated units, for example, "AB.AB" for 3,7,1,3,7. Repeated
+ * units are lettered in order of first appearance, and units that only appear
+ * once are shown as dots
+ */
+pub fn get_isomorph_pattern(units: &[u8]) -> String {
+    let mut letters = [None::<char>; MAX_UNITS];
+    let mut next_letter = 0u32;
+    let mut pattern = String::new();
+
+    for (i, &u) in units.iter().enumerate() {
+        if letters[u as usize].is_none() && units[i + 1..].contains(&u) {
+            // run out of letters after Z, which is unlikely to matter
+            letters[u as usize] = Some(char::from_u32('A' as u32 + next_letter).filter(|c| c.is_ascii_uppercase()).unwrap_or('#'));
+            next_letter += 1;
+        }
+
+        pattern.push(letters[u as usize].unwrap_or('.'));
+    }
+
+    pattern
+}
@@ -1,4 +1,8 @@
 pub mod alphabet;
+pub mod differences;
+pub mod isomorph;
+pub mod ngram;
+pub mod periodicity;
 pub mod unit_totals;
 pub mod unit_freq;
 pub mod plot;
\ No newline at end of file
@@ -0,0 +1,225 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use super::alphabet::MAX_UNITS;
+
+/// Longest supported n-grams (quadgrams)
+pub const MAX_NGRAM_LEN: usize = 4;
+
+/// Entries in the biggest table a model can have. Tables are dense, so a model
+/// with 83 distinct units would need 47M entries for quadgrams, which is fine,
+/// but a model with all 256 units would need 4G entries, which isn't
+const MAX_TABLE_LEN: usize = 1 << 26;
+
+const NO_INDEX: u16 = u16::MAX;
+
+#[derive(Debug)]
+pub enum NgramModelError {
+    BadLength,
+    TableTooBig { n: usize },
+}
+
+impl fmt::Display for NgramModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadLength => write!(f, "N-gram length must be in the range 1..={}", MAX_NGRAM_LEN),
+            Self::TableTooBig { n } => write!(f, "Too many distinct units for a table of {}-grams", n),
+        }
+    }
+}
+
+impl Error for NgramModelError {}
+
+struct NgramTable {
+    log_probs: Box<[f32]>,
+    /// log-probability of n-grams that aren't in the model
+    floor: f32,
+}
+
+/**
+ * Log-probabilities (base 10) of the n-grams of a language, for scoring how
+ * much a text looks like that language. Unlike UnitFrequency, this depends on
+ * which unit is which, so the n-grams must be mapped to units with the same
+ * alphabet as the messages being scored. Each n-gram length has its own table,
+ * and n-grams that aren't in the model (including any n-gram with a unit that
+ * never appears in the model) are scored one order of magnitude below the
+ * rarest n-gram of the same length
+ */
+pub struct NgramModel {
+    pub name: Box<str>,
+    /// dense index of each unit that appears in the model, or NO_INDEX
+    unit_indices: [u16; MAX_UNITS],
+    unit_count: usize,
+    /// table of n-grams of length n is at index n - 1
+    tables: [Option<NgramTable>; MAX_NGRAM_LEN],
+}
+
+impl NgramModel {
+    /**
+     * Creates a model from n-grams (as units) and their log-probabilities.
+     * N-grams of different lengths can be mixed. Repeated n-grams overwrite
+     * previous ones
+     */
+    pub fn new(name: Box<str>, ngrams: &[(Box<[u8]>, f64)]) -> Result<Self, NgramModelError> {
+        let mut unit_indices = [NO_INDEX; MAX_UNITS];
+        let mut unit_count = 0usize;
+
+        for (units, _) in ngrams {
+            if units.is_empty() || units.len() > MAX_NGRAM_LEN {
+                return Err(NgramModelError::BadLength);
+            }
+
+            for &u in units {
+                if unit_indices[u as usize] == NO_INDEX {
+                    unit_indices[u as usize] = unit_count as u16;
+                    unit_count += 1;
+                }
+            }
+        }
+
+        let mut model = Self { name, unit_indices, unit_count, tables: Default::default() };
+
+        for n in 1..=MAX_NGRAM_LEN {
+            let mut floor = f64::INFINITY;
+            for (units, log_prob) in ngrams {
+                if units.len() == n {
+                    floor = floor.min(*log_prob);
+                }
+            }
+
+            // no n-grams of this length
+            if floor == f64::INFINITY { continue }
+
+            let table_len = unit_count.checked_pow(n as u32).filter(|len| *len <= MAX_TABLE_LEN).ok_or(NgramModelError::TableTooBig { n })?;
+            let floor = (floor - 1.0) as f32;
+            let mut log_probs = vec![floor; table_len].into_boxed_slice();
+
+            for (units, log_prob) in ngrams {
+                if units.len() == n {
+                    log_probs[model.get_table_index(units)] = *log_prob as f32;
+                }
+            }
+
+            model.tables[n - 1] = Some(NgramTable { log_probs, floor });
+        }
+
+        Ok(model)
+    }
+
+    /// Index of an n-gram whose units are all in the model
+    fn get_table_index(&self, units: &[u8]) -> usize {
+        let mut idx = 0usize;
+        for &u in units {
+            idx = idx * self.unit_count + self.unit_indices[u as usize] as usize;
+        }
+
+        idx
+    }
+
+    /// True if the model has n-grams of length n
+    pub fn has_len(&self, n: usize) -> bool {
+        (1..=MAX_NGRAM_LEN).contains(&n) && self.tables[n - 1].is_some()
+    }
+
+    /**
+     * Mean log-probability of all n-grams of length n in the given messages.
+     * N-grams don't span across messages. The mean is used instead of the sum
+     * so that scores of texts with different lengths can be compared. Returns
+     * NaN if the model has no n-grams of length n, or if the messages are too
+     * short to have any
+     */
+    pub fn get_mean_log_prob<M, I>(&self, n: usize, messages: M) -> f64
+    where
+        M: IntoIterator<Item = I>,
+        I: IntoIterator<Item = u8>,
+    {
+        if !self.has_len(n) { return f64::NAN }
+        let Some(table) = &self.tables[n - 1] else { unreachable!() };
+        // unit_count^n was checked when creating the table, so it can't
+        // overflow
+        let modulo = self.unit_count.pow(n as u32);
+
+        let mut total = 0f64;
+        let mut count = 0usize;
+
+        for message in messages {
+            // rolling index of the last n units, and how many of the last
+            // units are in the model. an n-gram is only in the table if all of
+            // its units are in the model
+            let mut idx = 0usize;
+            let mut known = 0usize;
+            let mut seen = 0usize;
+
+            for u in message {
+                let unit_idx = self.unit_indices[u as usize];
+                if unit_idx == NO_INDEX {
+                    known = 0;
+                } else {
+                    idx = (idx * self.unit_count + unit_idx as usize) % modulo;
+                    known += 1;
+                }
+
+                seen += 1;
+                if seen < n { continue }
+
+                total += if known >= n { table.log_probs[idx] } else { table.floor } as f64;
+                count += 1;
+            }
+        }
+
+        if count == 0 { f64::NAN } else { total / count as f64 }
+    }
+}
+
+/**
+ * Occurrences of all n-grams of a single length, for building n-gram language
+ * models from a corpus
+ */
+pub struct NgramCounts {
+    n: usize,
+    counts: HashMap<Box<[u8]>, u64>,
+    total: u64,
+}
+
+impl NgramCounts {
+    pub fn new(n: usize) -> Self {
+        Self { n, counts: HashMap::new(), total: 0 }
+    }
+
+    pub fn get_len(&self) -> usize {
+        self.n
+    }
+
+    pub fn get_total(&self) -> u64 {
+        self.total
+    }
+
+    /// Counts all n-grams in a contiguous run of units
+    pub fn add_run(&mut self, units: &[u8]) {
+        for ngram in units.windows(self.n) {
+            match self.counts.get_mut(ngram) {
+                Some(count) => *count += 1,
+                None => { self.counts.insert(ngram.into(), 1); },
+            }
+
+            self.total += 1;
+        }
+    }
+
+    /**
+     * N-grams that occurred at least min_count times, and their
+     * log-probabilities (base 10), most common first. Probabilities are
+     * relative to all counted n-grams, including the omitted ones
+     */
+    pub fn get_log_probs(&self, min_count: u64) -> Vec<(&[u8], f64)> {
+        let mut ngrams: Vec<(&[u8], u64)> = self.counts.iter()
+            .filter(|(_, count)| **count >= min_count)
+            .map(|(ngram, count)| (&**ngram, *count))
+            .collect();
+
+        // ties are sorted by n-gram so that the output is reproducible
+        ngrams.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        let total = self.total as f64;
+        ngrams.into_iter().map(|(ngram, count)| (ngram, (count as f64 / total).log10())).collect()
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use crate::data::message::MessageList;
+
+use super::{unit_freq::UnitFrequency, unit_totals::UnitTotals};
+
+/*
+ * Statistics for estimating the period of polyalphabetic ciphers (for example,
+ * the key length of a Vigenère cipher). Each message is assumed to start at the
+ * beginning of the key, so columns are aligned across messages, and repeats
+ * are only searched within each message.
+ */
+
+/**
+ * Probability that two units drawn without replacement are the same unit.
+ * Returns NaN if there are less than 2 units
+ */
+pub fn get_index_of_coincidence(totals: &UnitTotals) -> f64 {
+    let total = totals.get_total();
+    if total < 2 { return f64::NAN }
+
+    let mut coincidences = 0usize;
+    for n in totals.data {
+        coincidences += n * n.saturating_sub(1);
+    }
+
+    coincidences as f64 / (total * (total - 1)) as f64
+}
+
+/**
+ * Index of coincidence expected for a long text in a language, which is the
+ * probability that two units drawn with replacement are the same unit
+ */
+pub fn get_language_index_of_coincidence(freq: &UnitFrequency) -> f64 {
+    freq.data.iter().map(|p| p * p).sum()
+}
+
+/**
+ * Mean index of coincidence of the columns that the messages are split into if
+ * they're written in rows of the given period. Text encrypted with a
+ * polyalphabetic cipher has a high column IoC (close to the IoC of the
+ * plaintext's language) when the period is a multiple of the key length, since
+ * each column is then encrypted with a single alphabet. Columns with less than
+ * 2 units are ignored. Returns NaN if all columns are ignored
+ */
+pub fn get_column_index_of_coincidence(messages: &MessageList, period: usize) -> f64 {
+    let mut ioc_total = 0f64;
+    let mut columns = 0usize;
+
+    for column in 0..period {
+        let totals = UnitTotals::from_units(messages.iter().flat_map(|message| {
+            message.data.iter().skip(column).step_by(period).copied()
+        }));
+
+        let ioc = get_index_of_coincidence(&totals);
+        if !ioc.is_nan() {
+            ioc_total += ioc;
+            columns += 1;
+        }
+    }
+
+    if columns == 0 { f64::NAN } else { ioc_total / columns as f64 }
+}
+
+/**
+ * Distances between all pairs of occurrences of each repeated substring with
+ * the given length, within each message. In a polyalphabetic cipher, repeats
+ * that come from the same plaintext encrypted with the same part of the key
+ * are separated by multiples of the key length
+ */
+pub fn get_kasiski_distances(messages: &MessageList, repeat_len: usize) -> Vec<usize> {
+    let mut distances = Vec::new();
+    if repeat_len == 0 { return distances }
+
+    for message in messages {
+        let mut positions: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (i, substring) in message.data.windows(repeat_len).enumerate() {
+            positions.entry(substring).or_default().push(i);
+        }
+
+        for occurrences in positions.values() {
+            for (a, first) in occurrences.iter().enumerate() {
+                for second in &occurrences[a + 1..] {
+                    distances.push(second - first);
+                }
+            }
+        }
+    }
+
+    distances
+}
+
+/**
+ * Friedman's estimate of the period, given the index of coincidence of the
+ * ciphertext, the index of coincidence of the plaintext's language, the index
+ * of coincidence of uniformly random text (1 / number of units), and the
+ * amount of units. Not very precise, but a good hint of the order of magnitude
+ * of the period. Returns NaN or a negative value if the ciphertext's IoC is
+ * outside of the range that the estimate works with
+ */
+pub fn get_friedman_period(ioc: f64, language_ioc: f64, random_ioc: f64, total: usize) -> f64 {
+    let n = total as f64;
+    n * (language_ioc - random_ioc) / ((n - 1.0) * ioc - n * random_ioc + language_ioc)
+}
+
+pub struct PeriodCandidate {
+    pub period: usize,
+    /// see get_column_index_of_coincidence
+    pub column_ioc: f64,
+    /// how many Kasiski distances are multiples of the period
+    pub kasiski_multiples: usize,
+}
+
+/**
+ * Periods 1..=max_period, ranked by their column index of coincidence (best
+ * first). Multiples of the key length rank about as high as the key length
+ * itself, so the smallest of the best periods is usually the one to try.
+ * Kasiski distances are counted for each period, but aren't used for ranking,
+ * since small periods are factors of more distances
+ */
+pub fn rank_periods(messages: &MessageList, max_period: usize, kasiski_distances: &[usize]) -> Vec<PeriodCandidate> {
+    let mut candidates: Vec<PeriodCandidate> = (1..=max_period).map(|period| PeriodCandidate {
+        period,
+        column_ioc: get_column_index_of_coincidence(messages, period),
+        kasiski_multiples: kasiski_distances.iter().filter(|distance| *distance % period == 0).count(),
+    }).collect();
+
+    // NaN is lowest, since those periods have too few units to say anything
+    candidates.sort_by(|a, b| {
+        let a_ioc = if a.column_ioc.is_nan() { f64::NEG_INFINITY } else { a.column_ioc };
+        let b_ioc = if b.column_ioc.is_nan() { f64::NEG_INFINITY } else { b.column_ioc };
+        b_ioc.total_cmp(&a_ioc).then(a.period.cmp(&b.period))
+    });
+
+    candidates
+}
@@ -93,6 +93,4 @@ impl UnitFrequency {
     pub fn sort(&mut self) {
         self.data.sort_by(|a, b| b.partial_cmp(a).unwrap());
     }
-}
-
-// TODO compare character at index i with character at index i - 1; basically subtract but take possible modulo into account, to measure if there's a consistent "drift"
\ No newline at end of file
+}
\ No newline at end of file
@@ -45,4 +45,23 @@ impl UnitTotals {
 
         counter
     }
+
+    pub fn from_units<I: IntoIterator<Item = u8>>(units: I) -> UnitTotals {
+        let mut counter = UnitTotals { data: [0; MAX_UNITS] };
+        for c in units {
+            counter.data[c as usize] += 1;
+        }
+
+        counter
+    }
+
+    /// Total occurrences of all units
+    pub fn get_total(&self) -> usize {
+        self.data.iter().sum()
+    }
+
+    /// Number of units that occur at least once
+    pub fn get_distinct(&self) -> usize {
+        self.data.iter().filter(|x| **x > 0).count()
+    }
 }
@@ -1,5 +1,5 @@
 use clap::Parser;
-use noita_eye_messages::{analysis::{plot::{bar_chart, freq_bar_chart}, unit_freq::UnitFrequency, unit_totals::UnitTotals}, data::{alphabet_io::import_csv_alphabet_or_default, language_io::import_csv_languages, message_io::import_messages}, main_error_wrap, utils::{print::{MessagesPrintConfig, print_messages}, threading::AsyncTaskList}};
+use noita_eye_messages::{analysis::{alphabet::Alphabet, differences::DifferenceStats, isomorph::{MatchKind, SubstringMatch, find_isomorphs, find_shared_runs, get_isomorph_pattern}, periodicity::{get_friedman_period, get_index_of_coincidence, get_kasiski_distances, get_language_index_of_coincidence, rank_periods}, plot::{bar_chart, freq_bar_chart}, unit_freq::UnitFrequency, unit_totals::UnitTotals}, data::{alphabet_io::import_csv_alphabet_or_default, language_io::import_csv_languages, message::{Message, MessageList}, message_io::import_messages, render_message::{MessageRenderMap, RenderMessageBuilder}}, main_error_wrap, utils::{print::{MessagesPrintConfig, get_presentation_offset, print_messages}, threading::AsyncTaskList}};
 
 #[cfg(not(target_env = "msvc"))]
 #[global_allocator]
@@ -7,7 +7,7 @@ static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 #[derive(Parser)]
 struct Args {
-    /// Path to CSV or TXT file containing message data
+    /// Path to CSV, TXT or EYES (eye glyph) file containing message data
     data_path: std::path::PathBuf,
     /// Path to CSV file containing an alphabet with letter frequency distribution
     #[clap(short, long)]
@@ -15,6 +15,132 @@ struct Args {
     /// Path to alphabet file for interpreting the units in the message data. Any character not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
     #[arg(short, long)]
     alphabet: Option<std::path::PathBuf>,
+    /// Longest period to rank in the periodicity analysis
+    #[arg(long, default_value_t = 20)]
+    max_period: usize,
+    /// Length of the repeated substrings used in the Kasiski analysis
+    #[arg(long, default_value_t = 3)]
+    repeat_len: usize,
+    /// Minimum length of the shared runs and isomorphs to find
+    #[arg(long, default_value_t = 4)]
+    min_match_len: usize,
+    /// Maximum number of differing units between shared runs that are merged into a single run, and of units that break the pattern of an isomorph
+    #[arg(long, default_value_t = 0)]
+    max_gap: usize,
+    /// Maximum number of shared runs and isomorphs to print (each). Longest first
+    #[arg(long, default_value_t = 20)]
+    max_matches: usize,
+    /// Modulo of the differences between units (usually the alphabet size). Defaults to the highest unit in the messages plus 1
+    #[arg(long)]
+    difference_modulo: Option<usize>,
+    /// Order of the differences between units. 1 is the difference between each unit and the previous unit, 2 is the difference between each of those differences and the previous one, and so on
+    #[arg(long, default_value_t = 1)]
+    difference_order: usize,
+    /// Print the input messages stacked in aligned columns, highlighting the columns where all messages agree
+    #[arg(long)]
+    aligned: bool,
+}
+
+fn print_periodicity(args: &Args, messages: &MessageList, unit_totals: &UnitTotals, languages: &Vec<UnitFrequency>) {
+    // units that never occur aren't counted, since the alphabet may have more
+    // units than the messages actually use
+    let random_ioc = 1.0 / unit_totals.get_distinct() as f64;
+    let normalise = |ioc: f64| ioc / random_ioc;
+
+    println!("Index of coincidence (normalised, where 1 is uniformly random text with {} units):", unit_totals.get_distinct());
+    for message in messages {
+        let ioc = get_index_of_coincidence(&UnitTotals::from_units(message.data.iter().copied()));
+        println!("    {}: {:.5} ({:.3})", message.name, ioc, normalise(ioc));
+    }
+
+    let ioc = get_index_of_coincidence(unit_totals);
+    println!("    All messages: {:.5} ({:.3})", ioc, normalise(ioc));
+
+    for language in languages {
+        let language_ioc = get_language_index_of_coincidence(language);
+        println!("Friedman period estimate for {} (IoC {:.5}): {:.2}", language.name, language_ioc, get_friedman_period(ioc, language_ioc, random_ioc, unit_totals.get_total()));
+    }
+
+    let kasiski_distances = get_kasiski_distances(messages, args.repeat_len);
+    println!("Kasiski analysis found {} distances between repeated substrings of {} units", kasiski_distances.len(), args.repeat_len);
+
+    println!("Candidate periods (best first):");
+    println!("    Period  Column IoC  Normalised  Kasiski multiples");
+    for candidate in rank_periods(messages, args.max_period, &kasiski_distances) {
+        println!("    {:>6}  {:>10.5}  {:>10.3}  {:>17}", candidate.period, candidate.column_ioc, normalise(candidate.column_ioc), candidate.kasiski_multiples);
+    }
+}
+
+/// Prints both substrings of a match, one above the other
+fn print_substring_match(substring_match: &SubstringMatch, messages: &MessageList, alphabet: &Alphabet, print_config: &MessagesPrintConfig) {
+    let len = substring_match.len;
+    let mut match_messages = MessageList::default();
+    let mut render_messages = Vec::new();
+
+    for (m, start) in [(substring_match.message_a, substring_match.start_a), (substring_match.message_b, substring_match.start_b)] {
+        let mut message = Message::from_name(format!("{} [{}..{}]", messages[m].name, start, start + len).into());
+        message.data.extend_from_slice(&messages[m].data[start..start + len]);
+        match_messages.push(message);
+
+        let mut builder = RenderMessageBuilder::new();
+        for u in 0..len {
+            builder.push_unit(u);
+        }
+
+        render_messages.push(builder.done());
+    }
+
+    let title = match substring_match.kind {
+        MatchKind::Exact if substring_match.mismatches > 0 => format!("Shared run of {} units ({} differ)", len, substring_match.mismatches),
+        MatchKind::Exact => format!("Shared run of {} units", len),
+        MatchKind::Isomorphic if substring_match.mismatches > 0 => format!("Isomorph of {} units ({}, {} break the pattern)", len, get_isomorph_pattern(&match_messages[0].data), substring_match.mismatches),
+        MatchKind::Isomorphic => format!("Isomorph of {} units ({})", len, get_isomorph_pattern(&match_messages[0].data)),
+    };
+
+    print_messages(&title, &MessageRenderMap::new(match_messages, render_messages), alphabet, print_config);
+}
+
+fn print_substring_matches(args: &Args, messages: &MessageList, alphabet: &Alphabet) {
+    let shared_runs = find_shared_runs(messages, args.min_match_len, args.max_gap);
+    let isomorphs = find_isomorphs(messages, args.min_match_len, args.max_gap);
+    // printed in the same way as the whole messages, so that units look the
+    // same in all matches
+    let print_config = MessagesPrintConfig { presentation_offset: get_presentation_offset(messages, alphabet), ..Default::default() };
+
+    println!("Found {} shared runs and {} isomorphs of at least {} units", shared_runs.len(), isomorphs.len(), args.min_match_len);
+
+    for substring_match in shared_runs.iter().take(args.max_matches).chain(isomorphs.iter().take(args.max_matches)) {
+        println!();
+        print_substring_match(substring_match, messages, alphabet, &print_config);
+    }
+}
+
+fn print_differences(args: &Args, messages: &MessageList, unit_totals: &UnitTotals) -> Result<DifferenceStats, Box<dyn std::error::Error>> {
+    let modulo = match args.difference_modulo {
+        Some(modulo) if modulo == 0 || modulo > 256 => return Err("Difference modulo must be in the range 1..=256".into()),
+        Some(modulo) => modulo,
+        None => unit_totals.data.iter().rposition(|total| *total > 0).unwrap_or(0) + 1,
+    };
+
+    let stats = DifferenceStats::from_messages(messages, modulo, args.difference_order);
+    if stats.totals.get_total() == 0 {
+        println!("No differences of order {}, since all messages have at most {} units", args.difference_order, args.difference_order);
+        return Ok(stats);
+    }
+
+    let normalise = |ioc: f64| ioc * modulo as f64;
+
+    println!("Index of coincidence of differences (order {}, modulo {}; normalised, where 1 is uniformly random):", args.difference_order, modulo);
+    for (message, ioc) in messages.iter().zip(&stats.message_iocs) {
+        println!("    {}: {:.5} ({:.3})", message.name, ioc, normalise(*ioc));
+    }
+
+    println!("    All messages: {:.5} ({:.3})", stats.ioc, normalise(stats.ioc));
+
+    let most_common: Vec<String> = stats.get_most_common(5).iter().map(|(difference, total)| format!("{} ({})", difference, total)).collect();
+    println!("Most common differences: {}", most_common.join(", "));
+
+    Ok(stats)
 }
 
 fn main() { main_error_wrap!({
@@ -24,7 +150,7 @@ fn main() { main_error_wrap!({
     let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
     let messages_render_map = import_messages(&args.data_path, &alphabet)?;
 
-    print_messages("Input", &messages_render_map, &alphabet, &MessagesPrintConfig::default());
+    print_messages("Input", &messages_render_map, &alphabet, &MessagesPrintConfig { aligned: args.aligned, ..Default::default() });
     println!();
 
     let unit_totals = UnitTotals::from_messages(messages_render_map.get_messages());
@@ -34,9 +160,21 @@ fn main() { main_error_wrap!({
         println!("Frequency distribution error for {} and {}: {}", freq.name, other.name, freq.get_error(other));
     }
 
+    println!();
+    print_periodicity(&args, messages_render_map.get_messages(), &unit_totals, &freqs);
+    println!();
+    print_substring_matches(&args, messages_render_map.get_messages(), &alphabet);
+    println!();
+    let difference_stats = print_differences(&args, messages_render_map.get_messages(), &unit_totals)?;
+
     freqs.push(freq);
 
     let mut task_list = AsyncTaskList::new();
     bar_chart(&mut task_list, "Unit totals", "Unit", "Total", &unit_totals);
+    // the chart would have no bars
+    if difference_stats.totals.get_total() > 0 {
+        bar_chart(&mut task_list, "Unit difference totals", "Difference", "Total", &difference_stats.totals);
+    }
+
     freq_bar_chart(&mut task_list, "Unit frequency", "Unit", "Frequency", freqs);
 }) }
\ No newline at end of file
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use noita_eye_messages::{analysis::{alphabet::{Alphabet, MAX_UNITS}, ngram::{MAX_NGRAM_LEN, NgramCounts}}, data::{alphabet_io::{export_csv_alphabet, import_csv_alphabet_or_default}, language_io::export_csv_ngram_model}, main_error_wrap};
+use clap::Parser;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Parser)]
+struct Args {
+    /// Path to UTF-8 text file containing the corpus
+    corpus_path: std::path::PathBuf,
+    /// Path where a CSV file with the alphabet, weighted by how often each unit occurs in the corpus, will be stored. Can be used as a language
+    alphabet_out_path: std::path::PathBuf,
+    /// Path where a CSV file with the n-gram language model will be stored. If not passed, only the alphabet is stored
+    #[arg(short, long)]
+    ngram_out_path: Option<std::path::PathBuf>,
+    /// Name of the language. Defaults to the file name of the corpus, without the extension
+    #[arg(long)]
+    name: Option<Box<str>>,
+    /// Path to alphabet file with the graphemes to count. Any grapheme of the corpus not present in the alphabet is not counted. Units are renumbered from 0 in the stored alphabet, so that folded units don't leave gaps. If not passed, then an ASCII alphabet will be used by default
+    #[arg(short, long)]
+    alphabet: Option<std::path::PathBuf>,
+    /// Fold a grapheme into another, in the format FROM=TO (for example, J=I). TO must be in the alphabet, and FROM is counted as TO and removed from the stored alphabet. Can be passed multiple times
+    #[arg(short, long)]
+    fold: Vec<Box<str>>,
+    /// Convert the corpus to uppercase before mapping graphemes to units
+    #[arg(short, long)]
+    uppercase: bool,
+    /// Treat graphemes that aren't in the alphabet (such as spaces and punctuation) as boundaries that n-grams can't span across. By default they're skipped, so n-grams span across words, like in messages without word separators
+    #[arg(long)]
+    split_on_unknown: bool,
+    /// Lengths of the n-grams in the language model, separated by commas
+    #[arg(long, value_delimiter = ',', default_values_t = [2, 3, 4])]
+    ngram_len: Vec<usize>,
+    /// Minimum occurrences of an n-gram for it to be included in the language model. Useful for keeping quadgram models small
+    #[arg(long, default_value_t = 1)]
+    min_count: u64,
+}
+
+/// Parses FROM=TO folds into a map of graphemes to the graphemes they're
+/// counted as
+fn parse_folds(folds: &Vec<Box<str>>, alphabet: &Alphabet) -> Result<HashMap<Box<str>, Box<str>>, Box<dyn std::error::Error>> {
+    let mut fold_map = HashMap::new();
+
+    for fold in folds {
+        let Some((from, to)) = fold.split_once('=') else {
+            return Err(format!("Invalid fold \"{}\"; expected FROM=TO", fold).into());
+        };
+
+        if from.graphemes(true).count() != 1 || to.graphemes(true).count() != 1 {
+            return Err(format!("Invalid fold \"{}\"; FROM and TO must be single graphemes", fold).into());
+        }
+
+        let to: Box<str> = to.into();
+        if alphabet.get_unit_idx(&to).is_none() {
+            return Err(format!("Invalid fold \"{}\"; {} is not in the alphabet", fold, to).into());
+        }
+
+        fold_map.insert(from.into(), to);
+    }
+
+    for to in fold_map.values() {
+        if fold_map.contains_key(to) {
+            return Err(format!("Invalid folds; {} is folded, so other graphemes can't be folded into it", to).into());
+        }
+    }
+
+    Ok(fold_map)
+}
+
+fn main() { main_error_wrap!({
+    let args = Args::parse();
+
+    for n in &args.ngram_len {
+        if *n == 0 || *n > MAX_NGRAM_LEN {
+            return Err(format!("N-gram lengths must be in the range 1..={}", MAX_NGRAM_LEN).into());
+        }
+    }
+
+    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+    let fold_map = parse_folds(&args.fold, &alphabet)?;
+    let name: Box<str> = match &args.name {
+        Some(name) => name.clone(),
+        None => args.corpus_path.file_stem().map(|x| x.to_string_lossy().into()).ok_or("Corpus path has no file name")?,
+    };
+
+    // printable units that aren't folded away are renumbered in order
+    let mut renumbered = [None::<u8>; MAX_UNITS];
+    let mut graphemes: Vec<Box<str>> = Vec::new();
+    for (u, alpha_unit) in alphabet.iter_units() {
+        if !alpha_unit.is_printable() || fold_map.contains_key(&alpha_unit.grapheme) { continue }
+        if alpha_unit.grapheme.contains(',') {
+            println!("Warning: skipping unit {}, since its grapheme can't be stored in CSV files", u);
+            continue;
+        }
+
+        renumbered[*u as usize] = Some(graphemes.len() as u8);
+        graphemes.push(alpha_unit.grapheme.clone());
+    }
+
+    let map_grapheme = |grapheme: &Box<str>| -> Option<u8> {
+        let grapheme = fold_map.get(grapheme).unwrap_or(grapheme);
+        renumbered[alphabet.get_unit_idx(grapheme)? as usize]
+    };
+
+    let corpus = std::fs::read_to_string(&args.corpus_path)?;
+    let corpus = if args.uppercase { corpus.to_uppercase() } else { corpus };
+
+    let mut unit_counts = vec![0u64; graphemes.len()];
+    let mut ngram_counts: Vec<NgramCounts> = args.ngram_len.iter().map(|n| NgramCounts::new(*n)).collect();
+    let mut run: Vec<u8> = Vec::new();
+    let mut grapheme_total = 0u64;
+    let mut unit_total = 0u64;
+
+    for grapheme in corpus.graphemes(true) {
+        grapheme_total += 1;
+
+        match map_grapheme(&grapheme.into()) {
+            Some(u) => {
+                unit_counts[u as usize] += 1;
+                unit_total += 1;
+                run.push(u);
+            },
+            None if args.split_on_unknown && run.len() > 0 => {
+                for counts in &mut ngram_counts {
+                    counts.add_run(&run);
+                }
+
+                run.clear();
+            },
+            None => {},
+        }
+    }
+
+    for counts in &mut ngram_counts {
+        counts.add_run(&run);
+    }
+
+    if unit_total == 0 {
+        return Err("No graphemes of the corpus are in the alphabet. Did you forget to pass --uppercase?".into());
+    }
+
+    println!("Counted {} of {} graphemes in the corpus", unit_total, grapheme_total);
+
+    let mut out_alphabet = Alphabet::new(name.clone());
+    for (u, grapheme) in graphemes.into_iter().enumerate() {
+        out_alphabet.add_unit(u as u8, grapheme, unit_counts[u] as f64 / unit_total as f64)?;
+    }
+
+    export_csv_alphabet(&args.alphabet_out_path, &out_alphabet)?;
+    println!("Stored alphabet \"{}\" with {} units", name, out_alphabet.len());
+
+    if let Some(ngram_out_path) = &args.ngram_out_path {
+        export_csv_ngram_model(ngram_out_path, &name, &out_alphabet, &ngram_counts, args.min_count)?;
+
+        for counts in &ngram_counts {
+            println!("Stored {}-grams ({} counted)", counts.get_len(), counts.get_total());
+        }
+    }
+}) }
@@ -0,0 +1,52 @@
+use clap::Parser;
+use noita_eye_messages::{ciphers::deserialise_dyn_cipher, data::{alphabet_io::import_csv_alphabet_or_default, message::AcceleratedMessageList, message_io::{export_csv_messages, import_messages}}, main_error_wrap, utils::print::{MessagesPrintConfig, print_messages}};
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Parser)]
+struct Args {
+    /// Path to CSV, TXT or EYES (eye glyph) file containing message data
+    data_path: std::path::PathBuf,
+    /// Cipher to use
+    cipher: Box<str>,
+    /// Key to use, in the same format that search and keydump print keys in. For example, "[a12->r3->x200]" for the arx cipher
+    key: Box<str>,
+    /// Cipher configuration, in Rusty Object Notation. Format is cipher-specific (see --list-ciphers in the search binary). It's recommended to add this as the last argument after a "--"
+    config: Option<Box<str>>,
+    /// Encrypt input message instead of decrypting (disabled by default)
+    #[arg(short, long)]
+    encrypt: bool,
+    /// Path where a CSV file with the output messages will be stored. Only units are stored, so any character not present in the alphabet is lost
+    #[arg(short, long)]
+    out_data_path: Option<std::path::PathBuf>,
+    /// Path to alphabet file for interpreting the units in the message data. Any character not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
+    #[arg(short, long)]
+    alphabet: Option<std::path::PathBuf>,
+}
+
+fn main() { main_error_wrap!({
+    let args = Args::parse();
+
+    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+    let messages_render_map = import_messages(&args.data_path, &alphabet)?;
+    let cipher = deserialise_dyn_cipher(&args.cipher, args.config.as_deref())?;
+    let net_key = cipher.str_key_to_net_key(&args.key)?;
+    let decrypt = !args.encrypt;
+
+    let messages = AcceleratedMessageList::from_messages(messages_render_map.get_messages());
+    let output = cipher.net_key_to_output_messages(&net_key, &messages.data, decrypt)?;
+    let output_render_map = messages_render_map.with_message_data(output);
+
+    let (in_title, out_title) = if decrypt { ("Ciphertexts", "Plaintexts") } else { ("Plaintexts", "Ciphertexts") };
+    println!("Key: {}", cipher.net_key_to_boxed_str(&net_key)?);
+    println!();
+    print_messages(in_title, &messages_render_map, &alphabet, &MessagesPrintConfig::default());
+    println!();
+    print_messages(out_title, &output_render_map, &alphabet, &MessagesPrintConfig::default());
+
+    if let Some(out_data_path) = &args.out_data_path {
+        export_csv_messages(out_data_path, output_render_map.get_messages())?;
+    }
+}) }
@@ -11,7 +11,7 @@ static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 struct Args {
     /// Stride to deinterlace with
     stride: usize,
-    /// Path to CSV or TXT file containing message data
+    /// Path to CSV, TXT or EYES (eye glyph) file containing message data
     in_data_path: std::path::PathBuf,
     /// Path where CSV files with deinterlaced contents will be stored. A "-0" to "-3" suffix will be added to the file name if, for example, you are deinterlacing with a stride of 4
     out_data_path: std::path::PathBuf,
@@ -0,0 +1,109 @@
+use std::collections::BTreeMap;
+
+use noita_eye_messages::{analysis::unit_totals::UnitTotals, data::{alphabet_io::import_csv_alphabet_or_default, glyph::{GLYPH_DIRECTIONS, GlyphReading}, message_io::{export_csv_messages, import_glyph_grids, read_glyph_grids}}, main_error_wrap, utils::{print::{MessagesPrintConfig, print_messages}, run::AnyErrorResult}};
+use clap::Parser;
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Parser)]
+struct Args {
+    /// Path to eye glyph file. Each message starts with a line with its name, followed by lines with the eye directions (digits 0 to 4), one line per row of eyes. Names must not start with a digit, since lines starting with a digit are rows of eyes. See data/ciphertext/sample.eyes for an example
+    data_path: std::path::PathBuf,
+    /// Path to alphabet file for interpreting the units in the message data. Any unit not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
+    #[arg(short, long)]
+    alphabet: Option<std::path::PathBuf>,
+    /// Order in which the eyes of each trigram are read, where the eyes are numbered 0 to 2, top row first, left to right. For example, 210 reads the eyes backwards
+    #[arg(long, default_value = "012")]
+    order: Box<str>,
+    /// Show the messages with every read order, instead of only the one passed with --order
+    #[arg(long)]
+    all_orders: bool,
+    /// Mirror each pair of rows horizontally, reading its triangles from right to left and swapping the left and right eyes of each triangle
+    #[arg(long)]
+    mirror: bool,
+    /// Flip each pair of rows vertically, so that triangles pointing down point up, and vice versa
+    #[arg(long)]
+    flip: bool,
+    /// Value of each eye direction, for directions 0 to 4. For example, 12340 rotates the directions by one step
+    #[arg(long, default_value = "01234")]
+    values: Box<str>,
+    /// Base of the trigram encoding. The unit of a trigram is v0 * base^2 + v1 * base + v2, where v0 to v2 are the values of its eyes in the read order
+    #[arg(long, default_value_t = 5)]
+    base: u8,
+    /// Print a table of the trigrams that occur in the messages, with their occurrences and the unit they're read as
+    #[arg(short, long)]
+    trigrams: bool,
+    /// Path where a CSV file with the messages, read with the reading passed with --order, will be stored
+    #[arg(short, long)]
+    out_data_path: Option<std::path::PathBuf>,
+}
+
+/// Parses a string of single digit values, such as "012"
+fn parse_digits<const N: usize>(digits: &str, arg_name: &str) -> AnyErrorResult<[u8; N]> {
+    let values: Vec<u8> = digits.chars().map(|c| c.to_digit(10).map(|d| d as u8)).collect::<Option<_>>()
+        .ok_or_else(|| format!("Invalid --{} \"{}\"; expected digits", arg_name, digits))?;
+
+    values.try_into().map_err(|_| format!("Invalid --{} \"{}\"; expected {} digits", arg_name, digits, N).into())
+}
+
+fn main() { main_error_wrap!({
+    let args = Args::parse();
+    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+    let grids = import_glyph_grids(&args.data_path)?;
+
+    let reading = GlyphReading {
+        order: parse_digits(&args.order, "order")?,
+        mirror: args.mirror,
+        flip: args.flip,
+        direction_values: parse_digits::<GLYPH_DIRECTIONS>(&args.values, "values")?,
+        base: args.base,
+    };
+
+    reading.validate()?;
+    let readings = if args.all_orders { reading.with_all_orders() } else { vec![reading.clone()] };
+
+    for reading in &readings {
+        let messages_render_map = read_glyph_grids(&grids, &alphabet, reading)?;
+        let unit_totals = UnitTotals::from_messages(messages_render_map.get_messages());
+        let present_units: Vec<usize> = unit_totals.data.iter().enumerate().filter(|(_, total)| **total > 0).map(|(u, _)| u).collect();
+
+        print_messages(&format!("Reading: {}", reading), &messages_render_map, &alphabet, &MessagesPrintConfig::default());
+        println!("{} units, {} distinct, in the range {}..={}", unit_totals.get_total(), unit_totals.get_distinct(), present_units.first().unwrap(), present_units.last().unwrap());
+    }
+
+    if args.trigrams {
+        // geometry is the same for all readings, so the trigrams are too
+        let mut trigram_totals = BTreeMap::<[u8; 3], usize>::new();
+        for grid in &grids {
+            for eyes in reading.get_trigrams(grid) {
+                *trigram_totals.entry(eyes).or_default() += 1;
+            }
+        }
+
+        println!("Trigrams ({} distinct), with the unit of each reading:", trigram_totals.len());
+        print!("    Eyes  Occurrences");
+        for reading in &readings {
+            let [o0, o1, o2] = reading.order;
+            print!("  {}{}{}", o0, o1, o2);
+        }
+
+        println!();
+        for (eyes, total) in &trigram_totals {
+            let [e0, e1, e2] = eyes;
+            print!("    {}{}{}   {:>11}", e0, e1, e2, total);
+            for reading in &readings {
+                print!("  {:>3}", reading.get_trigram_unit(*eyes));
+            }
+
+            println!();
+        }
+    }
+
+    if let Some(out_data_path) = &args.out_data_path {
+        let messages_render_map = read_glyph_grids(&grids, &alphabet, &reading)?;
+        export_csv_messages(out_data_path, messages_render_map.get_messages())?;
+        println!("Stored messages read with {}", reading);
+    }
+}) }
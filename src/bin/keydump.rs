@@ -0,0 +1,87 @@
+use clap::Parser;
+use noita_eye_messages::{ciphers::deserialise_dyn_cipher, data::{alphabet_io::import_csv_alphabet_or_default, key_dump::KeyDumpReader, message::{AcceleratedMessageList, hash_message_list}, message_io::import_messages}, main_error_wrap, utils::print::{MessagesPrintConfig, print_messages}};
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Parser)]
+struct Args {
+    /// Path to key dump file
+    key_dump_path: std::path::PathBuf,
+    /// Path to CSV, TXT or EYES (eye glyph) file containing the message data that was searched. If passed, the output of each key is printed. Otherwise, only the keys are listed
+    data_path: Option<std::path::PathBuf>,
+    /// Read the key dump even if it was created with a different build. Key encoding may have changed between builds, so keys may be wrong or fail to be parsed
+    #[arg(short, long)]
+    ignore_build_hash: bool,
+    /// Path to alphabet file for interpreting the units in the message data. Any character not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
+    #[arg(short, long)]
+    alphabet: Option<std::path::PathBuf>,
+}
+
+fn format_key(k: usize, key: &str, score: Option<f64>) -> String {
+    match score {
+        Some(score) => format!("Key {k} {key} (score {score})"),
+        None => format!("Key {k} {key}"),
+    }
+}
+
+fn main() { main_error_wrap!({
+    let args = Args::parse();
+
+    let mut reader = KeyDumpReader::open(&args.key_dump_path)?;
+    let records = reader.read_all_records()?;
+    let meta = reader.get_meta();
+    let build_hash = env!("GIT_HASH");
+    if meta.build_hash != build_hash {
+        if args.ignore_build_hash {
+            println!("Warning: key dump was created with a different build ({}). Continuing anyway", meta.build_hash.trim());
+        } else {
+            return Err(format!("Key dump was created with a different build ({}, current is {}). Pass --ignore-build-hash to read it anyway", meta.build_hash.trim(), build_hash.trim()).into());
+        }
+    }
+
+    let cipher = deserialise_dyn_cipher(&meta.cipher_name, meta.cipher_config.as_deref())?;
+    let decrypt = meta.decrypt;
+
+    println!("Cipher: {}", meta.cipher_name);
+    println!("Cipher configuration: {}", meta.cipher_config.as_deref().unwrap_or("(none)"));
+    println!("Condition: {}", meta.condition);
+    println!("Mode: {}", if decrypt { "decrypt" } else { "encrypt" });
+    println!("Alphabet: {}", meta.alphabet_name);
+    println!("Created at: {} (seconds since unix epoch)", meta.timestamp);
+    println!("{} keys", records.len());
+    println!();
+
+    match &args.data_path {
+        Some(data_path) => {
+            let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+            let messages_render_map = import_messages(data_path, &alphabet)?;
+
+            if hash_message_list(messages_render_map.get_messages()) != meta.data_hash {
+                println!("Warning: message data differs from the message data that was searched. Outputs will not be the same as in the search");
+            }
+
+            if **alphabet.get_name() != *meta.alphabet_name {
+                println!("Warning: alphabet differs from the alphabet used in the search ({})", meta.alphabet_name);
+            }
+
+            println!();
+
+            let messages = AcceleratedMessageList::from_messages(messages_render_map.get_messages());
+            for (k, record) in records.iter().enumerate() {
+                let net_key = &record.net_key.clone().into_boxed_slice();
+                let output = cipher.net_key_to_output_messages(net_key, &messages.data, decrypt)?;
+                let title = format_key(k, &*cipher.net_key_to_boxed_str(net_key)?, record.score);
+                print_messages(&title, &messages_render_map.with_message_data(output), &alphabet, &MessagesPrintConfig::default());
+                println!();
+            }
+        },
+        None => {
+            for (k, record) in records.iter().enumerate() {
+                let net_key = &record.net_key.clone().into_boxed_slice();
+                println!("{}", format_key(k, &*cipher.net_key_to_boxed_str(net_key)?, record.score));
+            }
+        },
+    }
+}) }
@@ -0,0 +1,339 @@
+use clap::Parser;
+use hot_eval::codegen::compiled_expression::CompiledExpression;
+use hot_eval::codegen::jit_context::JITContext;
+use hot_eval::common::table::Table;
+use noita_eye_messages::analysis::alphabet::Alphabet;
+use noita_eye_messages::analysis::ngram::NgramModel;
+use noita_eye_messages::analysis::unit_freq::UnitFrequency;
+use noita_eye_messages::ciphers::base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext};
+use noita_eye_messages::ciphers::metaheuristic::{MetaheuristicParameters, run_metaheuristic};
+use noita_eye_messages::ciphers::{CipherVisitor, deserialise_cipher};
+use noita_eye_messages::data::alphabet_io::import_csv_alphabet_or_default;
+use noita_eye_messages::data::key_dump::{KeyDumpMeta, KeyDumpWriter};
+use noita_eye_messages::data::language_io::{import_csv_languages, import_csv_ngram_models};
+use noita_eye_messages::data::message::{AcceleratedMessageList, InterleavedMessageData, hash_message_list};
+use noita_eye_messages::data::message_io::import_messages;
+use noita_eye_messages::data::render_message::MessageRenderMap;
+use noita_eye_messages::expression::bindings::CodecBindings;
+use noita_eye_messages::main_error_wrap;
+use noita_eye_messages::utils::print::{MessagesPrintConfig, print_messages};
+use noita_eye_messages::utils::run::UnitResult;
+use noita_eye_messages::utils::threading::get_parallelism;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::error::Error;
+use std::fmt;
+use std::num::NonZeroU32;
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(not(target_env = "msvc"))]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum Method {
+    /// Hill climbing, restarting from a random key when stuck (see --patience)
+    HillClimbing,
+    /// Simulated annealing (see --temperature)
+    Annealing,
+    /// Genetic algorithm (see --population). Needs a cipher that supports crossover
+    Genetic,
+}
+
+#[derive(clap::Parser)]
+struct Args {
+    /// Path to CSV, TXT or EYES (eye glyph) file containing message data
+    data_path: std::path::PathBuf,
+    /// Fitness expression to maximise. Has the same bindings as search conditions, but must evaluate to a number instead of a boolean
+    fitness: Box<str>,
+    /// Cipher to use (see --list-ciphers in the search binary). The cipher must support metaheuristic searches, otherwise all runs fail
+    cipher: Box<str>,
+    /// Cipher configuration, in Rusty Object Notation. Format is cipher-specific (see --list-ciphers in the search binary). It's recommended to add this as the last argument after a "--"
+    config: Option<Box<str>>,
+    /// Encrypt input message instead of decrypting (disabled by default)
+    #[arg(short, long)]
+    encrypt: bool,
+    /// Disable parallelism (optimise using only the main thread). Equivalent to setting max parallelism to 1, but takes priority over max parallelism
+    #[arg(short, long)]
+    sequential: bool,
+    /// Maximum number of local worklets. Runs are split between worklets
+    #[arg(short, long)]
+    max_parallelism: Option<NonZeroU32>,
+    /// Path to CSV file containing an alphabet with letter frequency distribution. Used to register languages for doing analysis. Refer to a language by its index (0-based) in the order specified in the terminal
+    #[clap(short, long)]
+    language: Vec<std::path::PathBuf>,
+    /// Path to CSV file containing an n-gram language model (log-probabilities of bigrams, trigrams, etc. keyed by grapheme). Graphemes are mapped to units with the alphabet. Used to register language models for scoring outputs with out_ngram_score. Refer to a language model by its index (0-based) in the order specified in the terminal
+    #[arg(long)]
+    ngram_model: Vec<std::path::PathBuf>,
+    /// Path to key dump file, if you want to store the best key of each run in a file, with its fitness as the score
+    #[arg(short, long)]
+    key_dump_path: Option<std::path::PathBuf>,
+    /// Path to alphabet file for interpreting the units in the message data. Any character not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
+    #[arg(short, long)]
+    alphabet: Option<std::path::PathBuf>,
+    /// Metaheuristic to optimise keys with
+    #[arg(long, value_enum, default_value_t = Method::Annealing)]
+    method: Method,
+    /// Number of independent runs, each starting from different random keys
+    #[arg(long, default_value_t = 16)]
+    runs: u32,
+    /// Number of mutated keys tried per run, when hill climbing or annealing
+    #[arg(long, default_value_t = 100000)]
+    iterations: u64,
+    /// Mutations in a row without an improvement before a hill climb restarts from a new random key
+    #[arg(long, default_value_t = 1000)]
+    patience: u64,
+    /// Starting temperature for simulated annealing, which cools down to 0 by the end of each run. Higher temperatures accept worse keys more often, which helps escaping local maxima. Should be in the same scale as fitness differences
+    #[arg(long, default_value_t = 1.0)]
+    temperature: f64,
+    /// Number of generations per run of the genetic algorithm
+    #[arg(long, default_value_t = 1000)]
+    generations: u64,
+    /// Number of keys in each generation of the genetic algorithm
+    #[arg(long, default_value_t = 100)]
+    population: usize,
+    /// Number of keys competing to be picked as each parent in the genetic algorithm. Bigger tournaments favour fitter keys more
+    #[arg(long, default_value_t = 3)]
+    tournament_size: usize,
+    /// Probability of mutating each child in the genetic algorithm, from 0 to 1
+    #[arg(long, default_value_t = 0.5)]
+    mutation_rate: f64,
+    /// Number of the fittest keys of each generation that are kept as-is in the next generation of the genetic algorithm
+    #[arg(long, default_value_t = 2)]
+    elitism: usize,
+    /// Seed for the random number generator. Runs with the same seed, data and arguments find the same keys, regardless of parallelism. Random if not passed
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// Inputs shared by all runs, regardless of the cipher's concrete type
+struct OptimiseInputs {
+    args: Args,
+    languages: Vec<UnitFrequency>,
+    ngram_models: Vec<NgramModel>,
+    alphabet: Alphabet,
+    messages_render_map: MessageRenderMap,
+    decrypt: bool,
+    seed: u64,
+}
+
+#[derive(Debug)]
+pub enum FitnessError {
+    BadExpressionType,
+}
+
+impl fmt::Display for FitnessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::BadExpressionType => "Bad expression type; expected a number (f64)",
+        })
+    }
+}
+
+impl Error for FitnessError {}
+
+enum OptimisePacket {
+    Finished {
+        run: u32,
+        /// See CipherKey::encode_to_buffer
+        net_key: Box<[u8]>,
+        fitness: f64,
+    },
+    Error {
+        worklet_id: u32,
+        message: Box<str>,
+    },
+}
+
+fn get_parameters(args: &Args) -> MetaheuristicParameters {
+    match args.method {
+        Method::HillClimbing => MetaheuristicParameters::HillClimbing { iterations: args.iterations, patience: args.patience },
+        Method::Annealing => MetaheuristicParameters::Annealing { iterations: args.iterations, temperature: args.temperature },
+        Method::Genetic => MetaheuristicParameters::Genetic {
+            generations: args.generations,
+            population: args.population,
+            tournament_size: args.tournament_size,
+            mutation_rate: args.mutation_rate,
+            elitism: args.elitism,
+        },
+    }
+}
+
+fn optimise_task<'inputs, 'src, const DECRYPT: bool, C: Cipher>(worklet_id: u32, worklet_total: u32, cipher: &C, messages: &'inputs InterleavedMessageData, args: &'src Args, languages: &'inputs Vec<UnitFrequency>, ngram_models: &'inputs Vec<NgramModel>, seed: u64, tx: &SyncSender<OptimisePacket>) -> Result<(), Box<dyn Error + 'src>> {
+    let mut jit_ctx = JITContext::new();
+    let mut comp_ctx = jit_ctx.make_compilation_context()?;
+    let mut fitness_table = Table::new();
+    let mut bindings = CodecBindings::<DECRYPT, C::Key, C::Context>::add_to_table(&mut fitness_table, messages, languages, ngram_models)?;
+
+    let (mut slab, jit_fn) = match comp_ctx.compile_str(&args.fitness, &fitness_table)? {
+        CompiledExpression::F64 { slab, jit_fn } => (slab, jit_fn),
+        _ => return Err(FitnessError::BadExpressionType.into()),
+    };
+
+    let params = get_parameters(args);
+
+    for run in (worklet_id..args.runs).step_by(worklet_total as usize) {
+        // each run has its own generator, so that results don't depend on
+        // which worklet did the run
+        let mut rng = StdRng::seed_from_u64(seed.wrapping_add(run as u64));
+
+        let (key, fitness) = run_metaheuristic(cipher, &params, &mut rng, |key| {
+            let codec_ctx = <C::Context as CipherWorkletContext<C::Key>>::CodecContext::<'_, DECRYPT>::new(messages, key);
+            // SAFETY: the slab belongs to an expression compiled with the table
+            //         the bindings were added to, and codec_ctx outlives the call
+            unsafe { bindings.set_codec_context(&mut slab, &codec_ctx); }
+
+            // SAFETY: see search_task in the search binary
+            unsafe { jit_fn.call() }
+        })?;
+
+        if tx.send(OptimisePacket::Finished { run, net_key: key.encode_to_buffer(), fitness }).is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_optimise<C: Cipher + Sync>(inputs: OptimiseInputs, cipher: C) -> UnitResult {
+    let args = &inputs.args;
+    let decrypt = inputs.decrypt;
+    let seed = inputs.seed;
+
+    // created after the cipher is deserialised, so that bad configurations
+    // don't leave an empty key dump behind
+    let mut key_dump_writer = match &args.key_dump_path {
+        Some(path) => Some(KeyDumpWriter::create(path, &KeyDumpMeta {
+            build_hash: String::from(env!("GIT_HASH")),
+            cipher_name: args.cipher.clone().into(),
+            cipher_config: args.config.clone().map(|x| x.into_string()),
+            condition: args.fitness.clone().into(),
+            data_hash: hash_message_list(inputs.messages_render_map.get_messages()),
+            alphabet_name: inputs.alphabet.get_name().clone().into(),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            decrypt,
+        })?),
+        None => None,
+    };
+
+    let worklet_total = if args.sequential {
+        1u32
+    } else {
+        let max_parallelism: u32 = args.max_parallelism.unwrap_or(NonZeroU32::new(u32::MAX).unwrap()).into();
+        get_parallelism().min(max_parallelism).min(args.runs.max(1))
+    };
+
+    let method_name = match args.method {
+        Method::HillClimbing => "hill climbing",
+        Method::Annealing => "simulated annealing",
+        Method::Genetic => "a genetic algorithm",
+    };
+
+    println!("Optimising {} times with {}, using {} worklets (seed {})", args.runs, method_name, worklet_total, seed);
+    let title = if decrypt { "Ciphertexts" } else { "Plaintexts" };
+    print_messages(title, &inputs.messages_render_map, &inputs.alphabet, &MessagesPrintConfig::default());
+    println!();
+
+    let messages = AcceleratedMessageList::from_messages(inputs.messages_render_map.get_messages());
+    let (tx, rx) = sync_channel::<OptimisePacket>(64);
+    let mut best: Option<(Box<[u8]>, f64)> = None;
+    // the other worklets keep running, but the first error is returned once
+    // the results that did finish are stored
+    let mut worklet_error: Option<String> = None;
+
+    std::thread::scope(|scope| -> UnitResult {
+        for worklet_id in 0..worklet_total {
+            let tx = tx.clone();
+            let (cipher, messages, args, languages, ngram_models) = (&cipher, &messages.data, args, &inputs.languages, &inputs.ngram_models);
+
+            scope.spawn(move || {
+                let task_res = if decrypt {
+                    optimise_task::<true, C>(worklet_id, worklet_total, cipher, messages, args, languages, ngram_models, seed, &tx)
+                } else {
+                    optimise_task::<false, C>(worklet_id, worklet_total, cipher, messages, args, languages, ngram_models, seed, &tx)
+                };
+
+                if let Err(err) = task_res {
+                    let _ = tx.send(OptimisePacket::Error { worklet_id, message: err.to_string().into_boxed_str() });
+                }
+            });
+        }
+
+        drop(tx);
+
+        // ends when all worklets are done and their senders are dropped
+        for packet in rx.iter() {
+            match packet {
+                OptimisePacket::Finished { run, net_key, fitness } => {
+                    println!("Run {run} reached fitness {fitness} with key {}", cipher.net_key_to_boxed_str(&net_key)?);
+
+                    if let Some(writer) = &mut key_dump_writer {
+                        writer.write_scored_key(&net_key, fitness)?;
+                    }
+
+                    if best.as_ref().is_none_or(|(_, best_fitness)| fitness > *best_fitness) {
+                        best = Some((net_key, fitness));
+                    }
+                },
+                OptimisePacket::Error { worklet_id, message } => {
+                    println!("Worklet {worklet_id} errored: {message}");
+                    worklet_error.get_or_insert(format!("Worklet {worklet_id} errored: {message}"));
+                },
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if let Some(writer) = &mut key_dump_writer {
+        writer.sync()?;
+    }
+
+    if let Some((net_key, fitness)) = best {
+        let output = cipher.net_key_to_output_messages(&net_key, &messages.data, decrypt)?;
+        println!();
+        println!("Best key (fitness {fitness}): {}", cipher.net_key_to_boxed_str(&net_key)?);
+        println!();
+        print_messages(if decrypt { "Plaintexts" } else { "Ciphertexts" }, &inputs.messages_render_map.with_message_data(output), &inputs.alphabet, &MessagesPrintConfig::default());
+    }
+
+    if let Some(worklet_error) = worklet_error {
+        return Err(worklet_error.into());
+    }
+
+    Ok(())
+}
+
+struct OptimiseVisitor {
+    inputs: OptimiseInputs,
+}
+
+impl CipherVisitor for OptimiseVisitor {
+    type Output = UnitResult;
+
+    fn visit<C: Cipher + Send + Sync + 'static>(self, cipher: C) -> UnitResult {
+        run_optimise(self.inputs, cipher)
+    }
+}
+
+fn main() { main_error_wrap!({
+    let args = Args::parse();
+
+    let languages = import_csv_languages(&args.language)?;
+    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+    let ngram_models = import_csv_ngram_models(&args.ngram_model, &alphabet)?;
+    let messages_render_map = import_messages(&args.data_path, &alphabet)?;
+    let decrypt = !args.encrypt;
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+
+    let (cipher_name, cipher_config) = (args.cipher.clone(), args.config.clone());
+    let visitor = OptimiseVisitor {
+        inputs: OptimiseInputs { args, languages, ngram_models, alphabet, messages_render_map, decrypt, seed },
+    };
+
+    deserialise_cipher(&cipher_name, cipher_config.as_deref(), visitor)??;
+}) }
@@ -1,34 +1,39 @@
 use hot_eval::codegen::compiled_expression::CompiledExpression;
 use hot_eval::codegen::jit_context::JITContext;
-use hot_eval::common::binding::{Binding, FnPointer, FnSpecCallArg, FnSpecChoice};
-use hot_eval::common::ir_const::IRConst;
 use hot_eval::common::table::Table;
-use hot_eval::common::value::Value;
-use hot_eval::common::value_type::ValueType;
 use noita_eye_messages::analysis::alphabet::Alphabet;
+use noita_eye_messages::analysis::ngram::NgramModel;
 use noita_eye_messages::data::alphabet_io::import_csv_alphabet_or_default;
-use noita_eye_messages::data::language_io::import_csv_languages;
+use noita_eye_messages::data::language_io::{import_csv_languages, import_csv_ngram_models};
 use noita_eye_messages::data::message_io::import_messages;
 use noita_eye_messages::data::render_message::MessageRenderMap;
+use noita_eye_messages::data::search_state::{SearchState, WorkletState, export_search_state, import_search_state};
 use noita_eye_messages::main_error_wrap;
 use noita_eye_messages::utils::run::UnitResult;
-use prost::Message;
 use clap::Parser;
 use noita_eye_messages::analysis::unit_freq::UnitFrequency;
 use noita_eye_messages::ciphers::base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext};
-use noita_eye_messages::ciphers::deserialise_cipher;
-use noita_eye_messages::data::key_dump::KeyDumpMeta;
+use noita_eye_messages::ciphers::{CipherVisitor, deserialise_cipher, get_cipher_infos};
+use noita_eye_messages::ciphers::key_list::KeyListCipher;
+use noita_eye_messages::expression::bindings::CodecBindings;
+use noita_eye_messages::data::key_dump::{KeyDumpMeta, KeyDumpReader, KeyDumpWriter};
 use rug::{Integer, Rational};
-use std::cell::OnceCell;
+use noita_eye_messages::data::search_protocol::{Assign, CoordinatorPacketKind, Finished, Progress, Rejected, Signal, WorkError, WorkerHello, WorkerPacketKind, receive_coordinator_packet, receive_worker_packet, send_coordinator_packet, send_worker_packet};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error;
 use std::fmt;
-use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, ErrorKind};
+use std::net::{Shutdown, TcpListener, TcpStream};
 use std::num::NonZeroU32;
-use std::sync::mpsc::{RecvTimeoutError, SyncSender, sync_channel};
-use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::sync::mpsc::{RecvTimeoutError, SyncSender, channel, sync_channel};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use noita_eye_messages::utils::threading::get_parallelism;
-use noita_eye_messages::data::message::{AcceleratedMessageList, InterleavedMessageData};
+use noita_eye_messages::utils::top_k::TopK;
+use noita_eye_messages::data::message::{AcceleratedMessageList, InterleavedMessageData, hash_message_list};
 use noita_eye_messages::utils::print::{MessagesPrintConfig, format_big_float, format_big_uint, format_seconds_left, print_messages};
 
 #[cfg(not(target_env = "msvc"))]
@@ -37,13 +42,15 @@ static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
 
 #[derive(clap::Parser)]
 struct Args {
-    /// Path to CSV or TXT file containing message data
-    data_path: std::path::PathBuf,
-    /// Condition to match. Values greater than 0 are treated as true, which should make it easy to use heuristics with thresholds as conditions (simply subtract the threshold value from the heuristic)
-    condition: Box<str>,
-    /// Cipher to use
-    cipher: Box<str>,
-    /// Cipher configuration. Format is cipher-specific, but generally expected to be Rusty Object Notation. It's recommended to add this as the last argument after a "--"
+    /// Path to CSV, TXT or EYES (eye glyph) file containing message data
+    #[arg(required_unless_present = "list_ciphers")]
+    data_path: Option<std::path::PathBuf>,
+    /// Condition to match. Values greater than 0 are treated as true, which should make it easy to use heuristics with thresholds as conditions (simply subtract the threshold value from the heuristic). When passing --top, this is instead a score (f64) to rank keys by
+    #[arg(required_unless_present = "list_ciphers")]
+    condition: Option<Box<str>>,
+    /// Cipher to use. Can be omitted when refining a key dump, in which case the cipher and cipher configuration of the key dump are used
+    cipher: Option<Box<str>>,
+    /// Cipher configuration, in Rusty Object Notation. Format is cipher-specific (see --list-ciphers). It's recommended to add this as the last argument after a "--"
     config: Option<Box<str>>,
     /// Encrypt input message instead of decrypting (disabled by default)
     #[arg(short, long)]
@@ -51,70 +58,214 @@ struct Args {
     /// Disable parallelism (search messages using only the main thread). Equivalent to setting max parallelism to 1, but takes priority over max parallelism
     #[arg(short, long)]
     sequential: bool,
-    // TODO replace this with a more general solution later, since there will be multiple workers, in separate machines
     /// Maximum number of local worklets. Using all available cores has diminishing returns, so tweaking this value is recommended
     #[arg(short, long)]
     max_parallelism: Option<NonZeroU32>,
     /// Path to CSV file containing an alphabet with letter frequency distribution. Used to register languages for doing analysis. Refer to a language by its index (0-based) in the order specified in the terminal
     #[clap(short, long)]
     language: Vec<std::path::PathBuf>,
+    /// Path to CSV file containing an n-gram language model (log-probabilities of bigrams, trigrams, etc. keyed by grapheme). Graphemes are mapped to units with the alphabet. Used to register language models for scoring outputs with out_ngram_score. Refer to a language model by its index (0-based) in the order specified in the terminal
+    #[arg(long)]
+    ngram_model: Vec<std::path::PathBuf>,
     /// Path to key dump file, if you want to store matches in a file instead of logging to the console
     #[arg(short, long)]
     key_dump_path: Option<std::path::PathBuf>,
     /// Path to alphabet file for interpreting the units in the message data. Any character not present in the alphabet will not be included in the message. If not passed, then an ASCII alphabet which includes all units will be used by default
     #[arg(short, long)]
     alphabet: Option<std::path::PathBuf>,
+    /// Path to search state file. If passed, the progress of each worklet is periodically saved to this file, so that an interrupted search can be continued later with --resume
+    #[arg(long)]
+    state_path: Option<std::path::PathBuf>,
+    /// Continue the search saved in the search state file. Message data, condition, cipher, cipher configuration and key dump path must be the same as in the interrupted search
+    #[arg(long, requires = "state_path")]
+    resume: bool,
+    /// Seconds between each save of the search state file
+    #[arg(long, default_value_t = 60)]
+    checkpoint_interval: u64,
+    /// Run as the coordinator of a distributed search, listening for workers on the given address (for example, 0.0.0.0:7300). The key space is split into ranges, which are handed out to workers. Ranges of lost workers are reassigned. Matches, key dumps and search state files are handled by the coordinator, and no keys are checked locally. See distributed.sh for running several workers on localhost
+    #[arg(long, conflicts_with = "worker")]
+    coordinator: Option<Box<str>>,
+    /// Run as a worker of a distributed search, connecting to the coordinator at the given address. Message data, condition, cipher and cipher configuration must be the same as the coordinator's, otherwise the worker is rejected. Each local worklet searches a separate range
+    #[arg(long, conflicts_with_all = ["key_dump_path", "state_path"])]
+    worker: Option<Box<str>>,
+    /// Amount of ranges the key space is split into when coordinating a distributed search. More ranges means less work is redone when a worker is lost, but there must be at least as many ranges as worklets in all workers, otherwise some worklets will be idle. Defaults to 256
+    #[arg(long, requires = "coordinator")]
+    ranges: Option<NonZeroU32>,
+    /// Seconds without packets from a worker before the coordinator considers it lost and reassigns its range. Workers send heartbeats every 5 seconds
+    #[arg(long, default_value_t = 60)]
+    worker_timeout: u64,
+    /// Path to key dump file to refine. If passed, only the keys in the key dump are checked instead of the whole key space of the cipher, so that matches of a previous search can be narrowed down with a stricter condition
+    #[arg(short, long)]
+    refine: Option<std::path::PathBuf>,
+    /// List the available ciphers, with their descriptions and configuration formats, and exit
+    #[arg(long, exclusive = true)]
+    list_ciphers: bool,
+    /// Check this many uniformly random keys instead of the whole key space, and estimate how many keys match, with a 95% confidence interval. Useful for tuning conditions on key spaces that are too big to be searched exhaustively. Keys are drawn with replacement, so the same key may be checked (and matched) more than once
+    #[arg(long, conflicts_with_all = ["coordinator", "worker", "state_path"])]
+    sample: Option<u64>,
+    /// Seed for the random number generator used when sampling. Samples with the same seed, data, arguments and parallelism check the same keys. Random if not passed
+    #[arg(long, requires = "sample")]
+    seed: Option<u64>,
+    /// Rank keys by a score instead of matching a condition, and keep the keys with the highest scores, up to this many. The best keys are printed with their scores when the search finishes, or stored in the key dump (best first) if a key dump path is passed
+    #[arg(long, conflicts_with_all = ["coordinator", "worker", "state_path", "sample"])]
+    top: Option<NonZeroU32>,
+}
+
+/// Inputs shared by all searches, regardless of the cipher's concrete type
+struct SearchInputs {
+    args: Args,
+    condition: Box<str>,
+    cipher_name: Box<str>,
+    cipher_config: Option<Box<str>>,
+    languages: Vec<UnitFrequency>,
+    ngram_models: Vec<NgramModel>,
+    alphabet: Alphabet,
+    messages_render_map: MessageRenderMap,
+    decrypt: bool,
+    data_hash: u64,
 }
 
 enum TaskPacket {
     Finished {
         worklet_id: u32,
     },
+    /// Sent when a chunk is fully checked. Matches are sent together with the
+    /// chunk, so that a saved search state never includes matches of
+    /// partially checked chunks
     Progress {
+        worklet_id: u32,
         keys: u32,
-    },
-    Match {
-        // XXX it doesn't really make sense to be passing around protobuf
-        //     messages like this, but the project is still in a weird
-        //     transition state where it doesn't support distributed computing
-        //     yet, but will
-        net_key: Box<[u8]>,
+        /// Matches in the chunk. See CipherKey::encode_to_buffer
+        net_keys: Vec<Box<[u8]>>,
     },
     Error {
+        worklet_id: u32,
         message: Box<str>,
-    }
+    },
+    /// Best keys of a worklet in a top-K search, sent once all of its keys are
+    /// checked, right before Finished
+    Top {
+        /// See CipherKey::encode_to_buffer
+        top_keys: TopK<Box<[u8]>>,
+    },
 }
 
 #[derive(Debug)]
 pub enum PredicateError {
     BadExpressionType,
+    BadScoreType,
 }
 
 impl fmt::Display for PredicateError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", match self {
             Self::BadExpressionType => "Bad expression type; expected a predicate (boolean)",
+            Self::BadScoreType => "Bad expression type; expected a score (f64) when ranking keys with --top",
         })
     }
 }
 
 impl Error for PredicateError {}
 
-const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+#[derive(Debug)]
+pub enum ResumeError {
+    CipherMismatch,
+    ConditionMismatch,
+    ModeMismatch,
+    DataMismatch,
+    KeyDumpMismatch,
+    RefineMismatch,
+}
+
+impl fmt::Display for ResumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::CipherMismatch => "Can't resume search; cipher or cipher configuration differs from the saved search",
+            Self::ConditionMismatch => "Can't resume search; condition differs from the saved search",
+            Self::ModeMismatch => "Can't resume search; saved search was encrypting instead of decrypting, or vice-versa",
+            Self::DataMismatch => "Can't resume search; message data differs from the saved search",
+            Self::KeyDumpMismatch => "Can't resume search; a key dump path must be passed if and only if the saved search also had one",
+            Self::RefineMismatch => "Can't resume search; refined key dump path differs from the saved search",
+        })
+    }
+}
+
+impl Error for ResumeError {}
 
-// TODO suspend to/resume from file
-// TODO bin to read key dumps
-// TODO bin to decrypt with individual key
-// TODO bin to refine a search via key dump files
+#[derive(Debug)]
+pub enum DistributedError {
+    UnexpectedPacket,
+    BadAssignment,
+}
 
-fn preamble(messages_render_map: &MessageRenderMap, alphabet: &Alphabet, worklet_total: u32, keys_total: &Integer, decrypt: bool) {
-    println!("Searching {} keys with {} worklets", format_big_uint(keys_total), worklet_total);
+impl fmt::Display for DistributedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", match self {
+            Self::UnexpectedPacket => "Unexpected packet; coordinator and worker may be using different protocol versions",
+            Self::BadAssignment => "Coordinator assigned a range that doesn't exist",
+        })
+    }
+}
+
+impl Error for DistributedError {}
+
+enum CoordinatorEvent {
+    /// Sent once the worker passed the handshake. The stream is used for
+    /// writing packets to the worker
+    Connected {
+        connection_id: u64,
+        stream: TcpStream,
+    },
+    RequestWork {
+        connection_id: u64,
+    },
+    Task {
+        connection_id: u64,
+        packet: TaskPacket,
+    },
+    Disconnected {
+        connection_id: u64,
+        reason: Box<str>,
+    },
+}
+
+struct WorkerConnection {
+    stream: TcpStream,
+    /// Range being searched by the worker
+    worklet_id: Option<u32>,
+    /// Whether the worker requested work and is still waiting for a range
+    waiting: bool,
+}
+
+struct WorkletProgress {
+    chunks_done: Integer,
+    keys_checked: Integer,
+    finished: bool,
+}
+
+/// Random keys to check instead of permuting a worklet's keys
+#[derive(Clone, Copy)]
+struct SampleTask {
+    samples: u64,
+    seed: u64,
+}
+
+const RECV_TIMEOUT: Duration = Duration::from_secs(1);
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_COORDINATOR_RANGES: u32 = 256;
+/// Samples checked between each progress packet, like a permutation chunk
+const SAMPLES_PER_CHUNK: u32 = 65536;
+/// Two-sided 95% quantile of the standard normal distribution
+const CONFIDENCE_Z: f64 = 1.96;
+
+fn preamble(messages_render_map: &MessageRenderMap, alphabet: &Alphabet, headline: &str, decrypt: bool) {
+    println!("{headline}");
     let title = if decrypt { "Ciphertexts" } else { "Plaintexts" };
     print_messages(title, messages_render_map, alphabet, &MessagesPrintConfig::default());
     println!();
 }
 
-fn print_progress(time_range: Option<(&Instant, &Instant)>, secs_since_last: f64, keys_total: &Integer, keys_checked: &Integer, keys_checked_since_last_print: &Integer) {
+fn print_progress(time_range: Option<(&Instant, &Instant)>, secs_since_last: f64, keys_total: &Integer, keys_checked: &Integer, keys_checked_before_start: &Integer, keys_checked_since_last_print: &Integer) {
     let percent = if *keys_total == 0 {
         100.0
     } else {
@@ -124,362 +275,453 @@ fn print_progress(time_range: Option<(&Instant, &Instant)>, secs_since_last: f64
     let kps = keys_checked_since_last_print.to_f64() / secs_since_last;
     let print_begin = format!("Progress: {percent:.2}% checked ({}/{} keys), {} keys/sec", format_big_uint(&keys_checked), format_big_uint(&keys_total), format_big_float(kps));
 
+    // keys checked before resuming a search are not included in the time
+    // estimate, since they weren't checked since start_time
+    let keys_checked_since_start = Integer::from(keys_checked - keys_checked_before_start);
+
     match time_range {
-        Some((start_time, now)) => {
-            let secs_left = Rational::from((&*keys_total - &*keys_checked, &*keys_checked)).to_f64() * now.duration_since(*start_time).as_secs_f64();
+        Some((start_time, now)) if keys_checked_since_start > 0 => {
+            let secs_left = Rational::from((&*keys_total - &*keys_checked, &keys_checked_since_start)).to_f64() * now.duration_since(*start_time).as_secs_f64();
             println!("{} ({})", print_begin, format_seconds_left(secs_left));
         },
-        None => {
+        _ => {
             println!("{}", print_begin);
         }
     }
 }
 
-fn eval_in(messages: &InterleavedMessageData, m: usize, u: usize) -> u8 {
-    messages[(m, u)]
+/**
+ * Wilson score interval of a binomial proportion. Unlike the normal
+ * approximation, it's still meaningful when there are no (or only) matches,
+ * which is the usual case when sampling a condition
+ */
+fn wilson_interval(successes: u64, trials: u64, z: f64) -> (f64, f64) {
+    if trials == 0 {
+        return (0.0, 1.0);
+    }
+
+    let n = trials as f64;
+    let p = successes as f64 / n;
+    let z2 = z * z;
+    let denom = 1.0 + z2 / n;
+    let center = (p + z2 / (2.0 * n)) / denom;
+    let half_width = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denom;
+    ((center - half_width).max(0.0), (center + half_width).min(1.0))
 }
 
-fn eval_in_freq_dist_error(in_freq_dist_errors: &Box<[f64]>, l: usize) -> f64 {
-    in_freq_dist_errors[l]
+fn scale_key_count(rate: f64, keys_total: &Integer) -> Integer {
+    match Rational::from_f64(rate) {
+        Some(rate) => Integer::from((rate * keys_total).round_ref()),
+        None => Integer::new(),
+    }
 }
 
-fn eval_out<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, m: usize, u: usize) -> u8
-where
-    K: CipherKey,
-    W: CipherWorkletContext<K>,
-{
-    codec_ctx.get_output(m, u)
+fn print_sample_estimate(samples: u64, matches: u64, sampled_keys_total: &Integer) {
+    let rate = if samples == 0 { 0.0 } else { matches as f64 / samples as f64 };
+    let (low, high) = wilson_interval(matches, samples, CONFIDENCE_Z);
+
+    println!("Sampled {samples} keys, {matches} matched");
+    println!("Match rate: {:.6}% (95% confidence interval: {:.6}% to {:.6}%)", rate * 100.0, low * 100.0, high * 100.0);
+    println!(
+        "Estimated matching keys: {} of {} (95% confidence interval: {} to {})",
+        format_big_uint(&scale_key_count(rate, sampled_keys_total)),
+        format_big_uint(sampled_keys_total),
+        format_big_uint(&scale_key_count(low, sampled_keys_total)),
+        format_big_uint(&scale_key_count(high, sampled_keys_total)),
+    );
 }
 
-unsafe fn eval_out_unchecked<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, m: usize, u: usize) -> u8
-where
-    K: CipherKey,
-    W: CipherWorkletContext<K>,
-{
-    // SAFETY: caller must verify bounds
-    unsafe { codec_ctx.get_output_unchecked(m, u) }
+fn check_resume_state(state: &SearchState, inputs: &SearchInputs) -> UnitResult {
+    let args = &inputs.args;
+
+    if state.build_hash != env!("GIT_HASH") {
+        println!("Warning: saved search was started with a different build ({}). Continuing, but some keys may be skipped or checked twice if the cipher's key order changed", state.build_hash.trim());
+    }
+
+    if state.cipher_name.as_str() != &*inputs.cipher_name || state.cipher_config.as_deref() != inputs.cipher_config.as_deref() {
+        Err(ResumeError::CipherMismatch)?
+    } else if state.condition.as_str() != &*inputs.condition {
+        Err(ResumeError::ConditionMismatch)?
+    } else if state.decrypt != inputs.decrypt {
+        Err(ResumeError::ModeMismatch)?
+    } else if state.data_hash != inputs.data_hash {
+        Err(ResumeError::DataMismatch)?
+    } else if state.key_dump_len.is_some() != args.key_dump_path.is_some() {
+        Err(ResumeError::KeyDumpMismatch)?
+    } else if state.refine_key_dump_path.as_deref() != args.refine.as_ref().map(|x| x.to_string_lossy()).as_deref() {
+        Err(ResumeError::RefineMismatch)?
+    }
+
+    Ok(())
 }
 
-fn eval_out_freq_dist_error_specific<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, out_freq_dist: &OnceCell<UnitFrequency>, language: &UnitFrequency) -> f64
-where
-    K: CipherKey,
-    W: CipherWorkletContext<K>,
-{
-    language.get_error(out_freq_dist.get_or_init(|| {
-        UnitFrequency::from_message_data_list(&codec_ctx.get_output_messages())
-    }))
+fn save_state(path: &std::path::PathBuf, inputs: &SearchInputs, key_dump_writer: &mut Option<KeyDumpWriter>, worklets: &Vec<WorkletProgress>) -> UnitResult {
+    let key_dump_len = match key_dump_writer {
+        Some(writer) => Some(writer.sync()?),
+        None => None,
+    };
+
+    export_search_state(path, &SearchState {
+        build_hash: String::from(env!("GIT_HASH")),
+        cipher_name: inputs.cipher_name.clone().into(),
+        cipher_config: inputs.cipher_config.clone().map(|x| x.into_string()),
+        condition: inputs.condition.clone().into(),
+        data_hash: inputs.data_hash,
+        decrypt: inputs.decrypt,
+        key_dump_len,
+        refine_key_dump_path: inputs.args.refine.as_ref().map(|x| x.to_string_lossy().into_owned()),
+        worklets: worklets.iter().map(|worklet| WorkletState {
+            chunks_done: worklet.chunks_done.to_string(),
+            keys_checked: worklet.keys_checked.to_string(),
+            finished: worklet.finished,
+        }).collect(),
+    })
 }
 
-fn eval_out_freq_dist_error<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, out_freq_dist: &OnceCell<UnitFrequency>, languages: &Vec<UnitFrequency>, l: usize) -> f64
+/**
+ * Calls key_callback for the given amount of random keys of the worklet, in
+ * chunks of SAMPLES_PER_CHUNK, with the same callback semantics as permuting.
+ * Drawn keys that are skipped when permuting still count as checked, so that
+ * the match rate is relative to the whole key space
+ */
+fn sample_keys<K, W, KC, CC>(worklet_id: u32, worklet_ctx: &W, sample: SampleTask, mut key_callback: KC, mut chunk_callback: CC)
 where
     K: CipherKey,
     W: CipherWorkletContext<K>,
+    KC: FnMut(&K),
+    CC: FnMut(u32) -> bool,
 {
-    eval_out_freq_dist_error_specific::<DECRYPT, K, W>(codec_ctx, out_freq_dist, &languages[l])
+    let mut rng = StdRng::seed_from_u64(sample.seed.wrapping_add(worklet_id as u64));
+    let mut samples_left = sample.samples;
+
+    while samples_left > 0 {
+        let chunk_samples = samples_left.min(SAMPLES_PER_CHUNK as u64) as u32;
+        for _ in 0..chunk_samples {
+            if let Some(key) = worklet_ctx.random_key(&mut rng) {
+                key_callback(&key);
+            }
+        }
+
+        samples_left -= chunk_samples as u64;
+        if !chunk_callback(chunk_samples) { break }
+    }
 }
 
-fn search_task<'inputs, 'src, const DECRYPT: bool, K, W>(_worklet_id: u32, messages: &'inputs InterleavedMessageData, worklet_ctx: W, cond_src: &'src str, languages: &'inputs Vec<UnitFrequency>, tx: &SyncSender<TaskPacket>) -> Result<(), Box<dyn Error + 'src>>
+fn search_task<'inputs, 'src, const DECRYPT: bool, K, W>(worklet_id: u32, messages: &'inputs InterleavedMessageData, worklet_ctx: W, start_chunk: &Integer, sample: Option<SampleTask>, top: Option<usize>, cond_src: &'src str, languages: &'inputs Vec<UnitFrequency>, ngram_models: &'inputs Vec<NgramModel>, tx: &SyncSender<TaskPacket>) -> Result<(), Box<dyn Error + 'src>>
 where
     K: CipherKey,
     W: CipherWorkletContext<K>,
 {
     let mut jit_ctx = JITContext::new();
     let mut comp_ctx = jit_ctx.make_compilation_context()?;
-    let mut out_freq_dist = OnceCell::<UnitFrequency>::new();
     let mut cond_table = Table::new();
-    let out_freq_dist_ptr = &out_freq_dist as *const OnceCell<UnitFrequency>;
-    let languages_ptr = languages as *const Vec<UnitFrequency>;
-    let codec_ctx_hsi = cond_table.add_hidden_state(ValueType::USize);
-
-    let in_freq_dist_errors: Box<[f64]> = {
-        let mut errors = Vec::<f64>::new();
-        for language in languages {
-            errors.push(language.get_error(
-                &UnitFrequency::from_interleaved_message_data(messages)
-            ));
-        }
+    let mut bindings = CodecBindings::<DECRYPT, K, W>::add_to_table(&mut cond_table, messages, languages, ngram_models)?;
+    let compiled = comp_ctx.compile_str(&cond_src, &cond_table)?;
 
-        errors.into()
-    };
+    // clone messages to keep them closer in memory with other working values
+    let messages = &(*messages).clone();
 
-    // SAFETY: all specialization closures only return an unchecked function's
-    //         pointer if it can prove the inputs are always in-bounds, and have
-    //         correctly mapped parameters
-
-    unsafe { cond_table.add_binding("in".into(), Binding::Function {
-        ret_type: ValueType::U8,
-        params: [
-            // param 0: usize
-            ValueType::USize,
-            // param 1: usize
-            ValueType::USize,
-        ].into(),
-        fn_spec: Box::new(move |hints| {
-            let args = [
-                // messages: &InterleavedMessageData
-                FnSpecCallArg::from((messages as *const InterleavedMessageData).addr()),
-                // m: usize (param 0)
-                FnSpecCallArg::MappedArgument { param_idx: 0 },
-                // u: usize (param 1)
-                FnSpecCallArg::MappedArgument { param_idx: 1 },
-            ].into();
-
-            if let [Some(IRConst::Uint { inner: m }), Some(IRConst::Uint { inner: u })] = *hints.consts {
-                let m = m as usize;
-                let u = u as usize;
-                if m < messages.get_message_count() && u < messages.get_unit_count(m) {
-                    Ok(FnSpecChoice::Const { value: Value::U8 { inner: messages[(m, u)] } })
-                } else {
-                    Err("in() call in expression is always out of bounds".into())
-                }
-            } else {
-                Ok(FnSpecChoice::Call { fn_ptr: eval_in as FnPointer, args })
-            }
-        }),
-    })? };
-
-    unsafe { cond_table.add_binding("in_freq_dist_error".into(), Binding::Function {
-        ret_type: ValueType::F64,
-        params: [
-            // param 0: usize
-            ValueType::USize,
-        ].into(),
-        fn_spec: Box::new(move |hints| {
-            if let [Some(IRConst::Uint { inner: l })] = *hints.consts {
-                let l = l as usize;
-                if l < languages.len() {
-                    Ok(FnSpecChoice::Const { value: Value::F64 { inner: in_freq_dist_errors[l] } })
-                } else {
-                    Err("in_freq_dist_error() call in expression is always out of bounds".into())
+    match (compiled, top) {
+        (CompiledExpression::Bool { mut slab, jit_fn }, None) => {
+            let matches = RefCell::new(Vec::<Box<[u8]>>::new());
+
+            let key_callback = |key: &K| {
+                let codec_ctx = W::CodecContext::<'_, DECRYPT>::new(messages, key);
+                // SAFETY: the slab belongs to an expression compiled with the
+                //         table the bindings were added to, and codec_ctx
+                //         outlives the call
+                unsafe { bindings.set_codec_context(&mut slab, &codec_ctx); }
+
+                // SAFETY: we're assuming that LLVM generated a valid function,
+                //         that the slab has valid data, and that hot-eval is
+                //         not broken (no bad codegen, sane types, etc...). not
+                //         a very strong guarantee...
+                if unsafe { jit_fn.call() } {
+                    matches.borrow_mut().push(key.encode_to_buffer());
                 }
-            } else {
-                Ok(FnSpecChoice::Call {
-                    fn_ptr: eval_in_freq_dist_error as FnPointer,
-                    args: [
-                        // in_freq_dist_errors: &Box<[f64]>
-                        FnSpecCallArg::from((&in_freq_dist_errors as *const Box<[f64]>).addr()),
-                        // l: usize (param 0)
-                        FnSpecCallArg::MappedArgument { param_idx: 0 },
-                    ].into(),
-                })
+            };
+
+            let chunk_callback = |keys| {
+                // stop if no one is listening anymore
+                tx.send(TaskPacket::Progress { worklet_id, keys, net_keys: matches.take() }).is_ok()
+            };
+
+            match sample {
+                Some(sample) => sample_keys(worklet_id, &worklet_ctx, sample, key_callback, chunk_callback),
+                None => worklet_ctx.permute_keys_interruptible_from(start_chunk, key_callback, chunk_callback),
             }
-        }),
-    })? };
-
-    unsafe { cond_table.add_binding("out".into(), Binding::Function {
-        ret_type: ValueType::U8,
-        params: [
-            // param 0: usize
-            ValueType::USize,
-            // param 1: usize
-            ValueType::USize,
-        ].into(),
-        fn_spec: Box::new(move |hints| {
-            let args = [
-                // codec_ctx: &W::CodecContext<'_, DECRYPT>
-                FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
-                // m: usize (param 0)
-                FnSpecCallArg::MappedArgument { param_idx: 0 },
-                // u: usize (param 1)
-                FnSpecCallArg::MappedArgument { param_idx: 1 },
-            ].into();
-
-            if let [Some(IRConst::Uint { inner: m }), Some(IRConst::Uint { inner: u })] = *hints.consts {
-                let m = m as usize;
-                if m < messages.get_message_count() && (u as usize) < messages.get_unit_count(m) {
-                    Ok(FnSpecChoice::Call { fn_ptr: eval_out_unchecked::<DECRYPT, K, W> as FnPointer, args })
-                } else {
-                    Err("out() call in expression is always out of bounds".into())
+        },
+        (CompiledExpression::F64 { mut slab, jit_fn }, Some(top)) => {
+            let mut top_keys = TopK::new(top);
+            let mut stopped = false;
+
+            worklet_ctx.permute_keys_interruptible_from(start_chunk, |key| {
+                let codec_ctx = W::CodecContext::<'_, DECRYPT>::new(messages, key);
+                // SAFETY: see the predicate case above
+                unsafe { bindings.set_codec_context(&mut slab, &codec_ctx); }
+
+                // SAFETY: see the predicate case above
+                let score = unsafe { jit_fn.call() };
+                // only encode keys that are kept, since most keys aren't
+                if top_keys.accepts(score) {
+                    top_keys.push(score, key.encode_to_buffer());
                 }
-            } else {
-                Ok(FnSpecChoice::Call { fn_ptr: eval_out::<DECRYPT, K, W> as FnPointer, args })
+            }, |keys| {
+                stopped = tx.send(TaskPacket::Progress { worklet_id, keys, net_keys: Vec::new() }).is_err();
+                !stopped
+            });
+
+            if !stopped {
+                let _ = tx.send(TaskPacket::Top { top_keys });
             }
-        }),
-    })? };
-
-    unsafe { cond_table.add_binding("out_freq_dist_error".into(), Binding::Function {
-        ret_type: ValueType::F64,
-        params: [
-            // param 0: usize
-            ValueType::USize,
-        ].into(),
-        fn_spec: Box::new(move |hints| {
-            if let [Some(IRConst::Uint { inner: l })] = *hints.consts {
-                let l = l as usize;
-                if l < languages.len() {
-                    Ok(FnSpecChoice::Call {
-                        fn_ptr: eval_out_freq_dist_error_specific::<DECRYPT, K, W> as FnPointer,
-                        args: [
-                            // codec_ctx: &W::CodecContext<'_, DECRYPT>
-                            FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
-                            // out_freq_dist: &OnceCell<UnitFrequency>
-                            FnSpecCallArg::from(out_freq_dist_ptr.addr()),
-                            // language: &UnitFrequency
-                            FnSpecCallArg::from((&languages[l] as *const UnitFrequency).addr()),
-                        ].into(),
-                    })
-                } else {
-                    Err("out_freq_dist_error() call in expression is always out of bounds".into())
-                }
-            } else {
-                Ok(FnSpecChoice::Call {
-                    fn_ptr: eval_out_freq_dist_error::<DECRYPT, K, W> as FnPointer,
-                    args: [
-                        // codec_ctx: &W::CodecContext<'_, DECRYPT>
-                        FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
-                        // out_freq_dist: &OnceCell<UnitFrequency>
-                        FnSpecCallArg::from(out_freq_dist_ptr.addr()),
-                        // languages: &Vec<UnitFrequency>
-                        FnSpecCallArg::from(languages_ptr.addr()),
-                        // l: usize (param 0)
-                        FnSpecCallArg::MappedArgument { param_idx: 0 },
-                    ].into(),
-                })
+        },
+        (_, None) => return Err(PredicateError::BadExpressionType.into()),
+        (_, Some(_)) => return Err(PredicateError::BadScoreType.into()),
+    }
+
+    Ok(())
+}
+
+/// Tracks the progress of all worklets, writes matches, prints progress and
+/// saves checkpoints. Shared by local and distributed searches
+struct SearchMonitor<'inputs> {
+    inputs: &'inputs SearchInputs,
+    key_dump_writer: Option<KeyDumpWriter>,
+    worklet_progress: Vec<WorkletProgress>,
+    keys_total: Integer,
+    keys_checked: Integer,
+    keys_checked_before_start: Integer,
+    keys_checked_since_last_print: Integer,
+    start_time: Instant,
+    last_print: Instant,
+    last_checkpoint: Instant,
+    /// Size of the key space, if sampling instead of searching it. In that
+    /// case, keys_total is the amount of samples
+    sampled_keys_total: Option<Integer>,
+    matches: u64,
+    /// Best keys of all worklets so far, if ranking keys instead of matching
+    top_keys: Option<TopK<Box<[u8]>>>,
+}
+
+impl<'inputs> SearchMonitor<'inputs> {
+    fn new(inputs: &'inputs SearchInputs, key_dump_writer: Option<KeyDumpWriter>, worklet_progress: Vec<WorkletProgress>, keys_total: Integer, sampled_keys_total: Option<Integer>) -> Self {
+        let mut keys_checked_before_start = Integer::new();
+        for progress in worklet_progress.iter() {
+            keys_checked_before_start += &progress.keys_checked;
+        }
+
+        let start_time = Instant::now();
+
+        Self {
+            inputs,
+            key_dump_writer,
+            worklet_progress,
+            keys_total,
+            keys_checked: keys_checked_before_start.clone(),
+            keys_checked_before_start,
+            keys_checked_since_last_print: Integer::new(),
+            start_time,
+            last_print: start_time.clone(),
+            last_checkpoint: start_time.clone(),
+            sampled_keys_total,
+            matches: 0,
+            top_keys: inputs.args.top.map(|top| TopK::new(top.get() as usize)),
+        }
+    }
+
+    fn on_progress<C: Cipher>(&mut self, cipher: &C, worklet_id: u32, keys: u32, net_keys: Vec<Box<[u8]>>) -> UnitResult {
+        self.matches += net_keys.len() as u64;
+
+        for net_key in net_keys {
+            match self.key_dump_writer {
+                Some(ref mut writer) => {
+                    writer.write_key(&net_key)?;
+                },
+                None => {
+                    println!("Matched key {}", cipher.net_key_to_boxed_str(&net_key)?);
+                },
             }
-        }),
-    })? };
+        }
 
-    let (mut slab, jit_fn) = match comp_ctx.compile_str(&cond_src, &cond_table)? {
-        CompiledExpression::Bool { slab, jit_fn } => (slab, jit_fn),
-        _ => return Err(PredicateError::BadExpressionType.into()),
-    };
+        self.keys_checked_since_last_print += keys;
+        let progress = &mut self.worklet_progress[worklet_id as usize];
+        progress.chunks_done += 1;
+        progress.keys_checked += keys;
+        Ok(())
+    }
 
-    // clone messages to keep them closer in memory with other working values
-    let messages = &(*messages).clone();
+    fn on_top(&mut self, top_keys: TopK<Box<[u8]>>) {
+        if let Some(all_top_keys) = &mut self.top_keys {
+            all_top_keys.merge(top_keys);
+        }
+    }
+
+    fn on_finished(&mut self, worklet_id: u32) {
+        self.worklet_progress[worklet_id as usize].finished = true;
+    }
 
-    worklet_ctx.permute_keys_interruptible(|key| {
-        // TODO clearing the cache results in a 5% slowdown. hot-eval should
-        //      support pure functions, so that it reuses outputs when possible,
-        //      otherwise we have to unnecessarily clear a cache and manage our
-        //      own lazy cell, even when there's only a single call in the
-        //      expression
-        out_freq_dist.take(); // clear cache
-
-        let codec_ctx = W::CodecContext::<'_, DECRYPT>::new(messages, key);
-        // SAFETY: &codec_ctx is only used during expression evaluation, it's
-        //         replaced before every expression evaluation, and codec_ctx
-        //         outlives the call
-        unsafe { slab.set_ptr_value_unchecked(codec_ctx_hsi, &codec_ctx); }
-
-        // SAFETY: we're assuming that LLVM generated a valid function, that the
-        //         slab has valid data, and that hot-eval is not broken (no bad
-        //         codegen, sane types, etc...). not a very strong guarantee...
-        if unsafe { jit_fn.call() } {
-            tx.send(TaskPacket::Match { net_key: key.encode_to_buffer() }).unwrap();
+    /// Prints progress and saves a checkpoint if enough time has passed since
+    /// the last time they were done. Should be called frequently
+    fn tick(&mut self) -> UnitResult {
+        let now = Instant::now();
+        let secs_since_last = now.duration_since(self.last_print).as_secs_f64();
+        if secs_since_last >= 5f64 {
+            self.keys_checked += &self.keys_checked_since_last_print;
+
+            print_progress(
+                Some((&self.start_time, &now)),
+                secs_since_last,
+                &self.keys_total,
+                &self.keys_checked,
+                &self.keys_checked_before_start,
+                &self.keys_checked_since_last_print,
+            );
+
+            self.last_print = now;
+            self.keys_checked_since_last_print = Integer::new();
         }
-    }, |keys| {
-        tx.send(TaskPacket::Progress { keys }).unwrap();
-        true
-    });
 
-    Ok(())
-}
+        if let Some(path) = &self.inputs.args.state_path && now.duration_since(self.last_checkpoint) >= Duration::from_secs(self.inputs.args.checkpoint_interval) {
+            save_state(path, self.inputs, &mut self.key_dump_writer, &self.worklet_progress)?;
+            self.last_checkpoint = now;
+        }
 
-fn main() { main_error_wrap!({
-    let args = Args::parse();
+        Ok(())
+    }
 
-    let languages = import_csv_languages(&args.language)?;
-    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
-    let messages_render_map = import_messages(&args.data_path, &alphabet)?;
-    let cipher = deserialise_cipher(&args.cipher, args.config.as_deref())?;
+    fn finish<C: Cipher>(mut self, cipher: &C) -> UnitResult {
+        self.keys_checked += &self.keys_checked_since_last_print;
 
-    let mut key_dump_file: Option<File> = match &args.key_dump_path {
-        Some(path) => {
-            let mut file = File::create_new(path)?;
-            file.write(KeyDumpMeta {
-                build_hash: String::from(env!("GIT_HASH")),
-                cipher_name: args.cipher.clone().into(),
-                cipher_config: args.config.clone().map(|x| x.into_string()),
-            }.encode_to_vec().as_slice())?;
+        print_progress(
+            None,
+            Instant::now().duration_since(self.last_print).as_secs_f64(),
+            &self.keys_total,
+            &self.keys_checked,
+            &self.keys_checked_before_start,
+            &self.keys_checked_since_last_print,
+        );
 
-            Some(file)
-        },
-        None => None,
+        if let Some(path) = &self.inputs.args.state_path {
+            save_state(path, self.inputs, &mut self.key_dump_writer, &self.worklet_progress)?;
+        }
+
+        if let Some(sampled_keys_total) = &self.sampled_keys_total {
+            print_sample_estimate(self.keys_checked.to_u64_wrapping(), self.matches, sampled_keys_total);
+        }
+
+        if let Some(top_keys) = self.top_keys.take() {
+            let top_keys = top_keys.into_sorted_vec();
+
+            match &mut self.key_dump_writer {
+                Some(writer) => {
+                    for (score, net_key) in top_keys.iter() {
+                        writer.write_scored_key(net_key, *score)?;
+                    }
+
+                    writer.sync()?;
+                    println!("Stored the {} best keys in the key dump", top_keys.len());
+                },
+                None => {
+                    println!("Best {} keys:", top_keys.len());
+                    for (rank, (score, net_key)) in top_keys.iter().enumerate() {
+                        println!("#{} (score {score}): {}", rank + 1, cipher.net_key_to_boxed_str(net_key)?);
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs search_task and reports how it ended. Shared by local searches and
+/// workers of distributed searches
+fn run_search_task<K, W>(worklet_id: u32, messages: &InterleavedMessageData, worklet_ctx: W, start_chunk: &Integer, sample: Option<SampleTask>, inputs: &SearchInputs, tx: &SyncSender<TaskPacket>)
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    let cond_src = &inputs.condition;
+    let languages = &inputs.languages;
+    let ngram_models = &inputs.ngram_models;
+    let top = inputs.args.top.map(|top| top.get() as usize);
+
+    let task_res = if inputs.decrypt {
+        search_task::<true, _, _>(worklet_id, messages, worklet_ctx, start_chunk, sample, top, cond_src, languages, ngram_models, tx)
+    } else {
+        search_task::<false, _, _>(worklet_id, messages, worklet_ctx, start_chunk, sample, top, cond_src, languages, ngram_models, tx)
     };
 
-    let decrypt = !args.encrypt;
-    let worklet_total = if args.sequential {
+    // the receiver may already be gone if a worker lost its connection, in
+    // which case there's no one left to report to
+    let _ = match task_res {
+        Ok(_) => tx.send(TaskPacket::Finished { worklet_id }),
+        Err(err) => tx.send(TaskPacket::Error { worklet_id, message: err.to_string().into_boxed_str() }),
+    };
+}
+
+fn get_local_parallelism(args: &Args) -> u32 {
+    if args.sequential {
         1u32
     } else {
-        let mut max_parallelism: u32 = args.max_parallelism.unwrap_or(NonZeroU32::new(u32::MAX).unwrap()).into();
-        max_parallelism = max_parallelism.min(cipher.get_max_parallelism());
+        let max_parallelism: u32 = args.max_parallelism.unwrap_or(NonZeroU32::new(u32::MAX).unwrap()).into();
         get_parallelism().min(max_parallelism)
-    };
+    }
+}
 
+fn run_local<C: Cipher>(inputs: &SearchInputs, cipher: &C, worklet_ctxs: Vec<C::Context>, sample_tasks: Option<Vec<SampleTask>>, mut monitor: SearchMonitor) -> UnitResult {
     let (tx, rx) = sync_channel::<TaskPacket>(64);
-    let messages = AcceleratedMessageList::from_messages(messages_render_map.get_messages());
+    let messages = AcceleratedMessageList::from_messages(inputs.messages_render_map.get_messages());
 
     std::thread::scope(|scope| -> UnitResult {
-        let mut keys_total = Integer::new();
-        let mut worklet_ctxs = Vec::new();
-
-        for worklet_id in 0..worklet_total {
-            let worklet_ctx = cipher.create_worklet_context_parallel(worklet_id, worklet_total);
-            keys_total += worklet_ctx.get_total_keys();
-            worklet_ctxs.push(worklet_ctx);
-        }
-
-        preamble(&messages_render_map, &alphabet, worklet_total, &keys_total, decrypt);
-
-        let start_time = Instant::now();
+        let mut worklets_waiting = 0;
 
         let mut worklet_id = 0;
         for worklet_ctx in worklet_ctxs {
+            let progress = &monitor.worklet_progress[worklet_id as usize];
+
+            if progress.finished {
+                println!("Worklet {worklet_id} already finished task");
+                worklet_id += 1;
+                continue;
+            }
+
             let worklet_id_clone = worklet_id.clone();
+            let start_chunk = progress.chunks_done.clone();
+            let sample = sample_tasks.as_ref().map(|tasks| tasks[worklet_id as usize]);
             let messages = &messages.data;
-            let cond_src = &args.condition;
-            let languages = &languages;
             let tx = tx.clone();
 
             scope.spawn(move || {
-                let task_res = if decrypt {
-                    search_task::<true, _, _>(worklet_id_clone, messages, worklet_ctx, cond_src, languages, &tx)
-                } else {
-                    search_task::<false, _, _>(worklet_id_clone, messages, worklet_ctx, cond_src, languages, &tx)
-                };
-
-                match task_res {
-                    Ok(_) => tx.send(TaskPacket::Finished { worklet_id }).unwrap(),
-                    Err(err) => tx.send(TaskPacket::Error { message: err.to_string().into_boxed_str() }).unwrap(),
-                }
+                run_search_task(worklet_id_clone, messages, worklet_ctx, &start_chunk, sample, inputs, &tx);
             });
 
+            worklets_waiting += 1;
             worklet_id += 1;
         }
 
         drop(tx);
 
-        let mut keys_checked = Integer::new();
-        let mut keys_checked_since_last_print = Integer::new();
-        let mut last_print = start_time.clone();
-        let mut worklets_waiting = worklet_total;
-
         while worklets_waiting > 0 {
             match rx.recv_timeout(RECV_TIMEOUT) {
                 Ok(packet) => {
                     match packet {
                         TaskPacket::Finished { worklet_id } => {
                             worklets_waiting -= 1;
+                            monitor.on_finished(worklet_id);
                             println!("Worklet {worklet_id} finished task");
                         },
-                        TaskPacket::Progress { keys } => {
-                            keys_checked_since_last_print += keys;
-                        },
-                        TaskPacket::Match { net_key } => {
-                            match key_dump_file {
-                                Some(ref mut file) => {
-                                    file.write(net_key.iter().as_slice())?;
-                                },
-                                None => {
-                                    println!("Matched key {}", cipher.net_key_to_boxed_str(&net_key)?);
-                                },
-                            }
+                        TaskPacket::Progress { worklet_id, keys, net_keys } => {
+                            monitor.on_progress(cipher, worklet_id, keys, net_keys)?;
                         },
-                        TaskPacket::Error { message } => {
+                        TaskPacket::Error { worklet_id, message } => {
                             worklets_waiting -= 1;
                             println!("Worklet {worklet_id} errored: {message}");
                             // TODO kill other worklets?
                         },
+                        TaskPacket::Top { top_keys } => {
+                            monitor.on_top(top_keys);
+                        },
                     }
                 },
                 Err(err) => {
@@ -493,34 +735,589 @@ fn main() { main_error_wrap!({
                 },
             }
 
-            let now = Instant::now();
-            let secs_since_last = now.duration_since(last_print).as_secs_f64();
-            if secs_since_last >= 5f64 {
-                keys_checked += &keys_checked_since_last_print;
-
-                print_progress(
-                    Some((&start_time, &now)),
-                    secs_since_last,
-                    &keys_total,
-                    &keys_checked,
-                    &keys_checked_since_last_print,
-                );
-
-                last_print = now;
-                keys_checked_since_last_print = Integer::new();
+            monitor.tick()?;
+        }
+
+        Ok(())
+    })?;
+
+    monitor.finish(cipher)
+}
+
+fn make_worker_hello<C: Cipher>(inputs: &SearchInputs, cipher: &C) -> WorkerHello {
+    WorkerHello {
+        build_hash: String::from(env!("GIT_HASH")),
+        cipher_name: inputs.cipher_name.clone().into(),
+        cipher_config: inputs.cipher_config.clone().map(|x| x.into_string()),
+        condition: inputs.condition.clone().into(),
+        data_hash: inputs.data_hash,
+        decrypt: inputs.decrypt,
+        keys_total: cipher.create_worklet_context().get_total_keys().to_string(),
+    }
+}
+
+/// Returns the reason for rejecting the worker, if any
+fn check_worker_hello(expected: &WorkerHello, hello: &WorkerHello) -> Option<&'static str> {
+    if hello.build_hash != expected.build_hash {
+        Some("worker has a different build")
+    } else if hello.cipher_name != expected.cipher_name || hello.cipher_config != expected.cipher_config {
+        Some("worker has a different cipher or cipher configuration")
+    } else if hello.condition != expected.condition {
+        Some("worker has a different condition")
+    } else if hello.decrypt != expected.decrypt {
+        Some("worker is encrypting instead of decrypting, or vice-versa")
+    } else if hello.data_hash != expected.data_hash {
+        Some("worker has different message data")
+    } else if hello.keys_total != expected.keys_total {
+        Some("worker has a different key space")
+    } else {
+        None
+    }
+}
+
+/**
+ * Handles a single worker connection in the coordinator. Packets are forwarded
+ * to the coordinator's main loop as events, which is also the only place that
+ * writes to the connection after the handshake
+ */
+fn coordinator_connection(connection_id: u64, stream: TcpStream, expected_hello: &WorkerHello, worker_timeout: Duration, tx: &SyncSender<CoordinatorEvent>) -> UnitResult {
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(worker_timeout))?;
+    stream.set_write_timeout(Some(worker_timeout))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let hello = match receive_worker_packet(&mut reader)? {
+        Some(WorkerPacketKind::Hello(hello)) => hello,
+        _ => return Err(DistributedError::UnexpectedPacket.into()),
+    };
+
+    if let Some(reason) = check_worker_hello(expected_hello, &hello) {
+        send_coordinator_packet(&mut &stream, CoordinatorPacketKind::Rejected(Rejected { reason: reason.into() }))?;
+        return Err(format!("Rejected worker; {reason}").into());
+    }
+
+    send_coordinator_packet(&mut &stream, CoordinatorPacketKind::Welcome(Signal {}))?;
+    tx.send(CoordinatorEvent::Connected { connection_id, stream })?;
+
+    loop {
+        let event = match receive_worker_packet(&mut reader)? {
+            Some(WorkerPacketKind::RequestWork(_)) => CoordinatorEvent::RequestWork { connection_id },
+            Some(WorkerPacketKind::Progress(progress)) => CoordinatorEvent::Task {
+                connection_id,
+                packet: TaskPacket::Progress {
+                    worklet_id: progress.worklet_id,
+                    keys: progress.keys,
+                    net_keys: progress.net_keys.into_iter().map(|x| x.into()).collect(),
+                },
+            },
+            Some(WorkerPacketKind::Finished(finished)) => CoordinatorEvent::Task {
+                connection_id,
+                packet: TaskPacket::Finished { worklet_id: finished.worklet_id },
+            },
+            Some(WorkerPacketKind::Error(error)) => CoordinatorEvent::Task {
+                connection_id,
+                packet: TaskPacket::Error { worklet_id: error.worklet_id, message: error.message.into() },
+            },
+            Some(WorkerPacketKind::Heartbeat(_)) => continue,
+            Some(WorkerPacketKind::Hello(_)) => return Err(DistributedError::UnexpectedPacket.into()),
+            None => return Ok(()),
+        };
+
+        tx.send(event)?;
+    }
+}
+
+/// Hands out pending ranges to connections that are waiting for work
+fn assign_pending_ranges(connections: &mut HashMap<u64, WorkerConnection>, pending: &mut VecDeque<u32>, worklet_progress: &Vec<WorkletProgress>) {
+    let worklet_total = worklet_progress.len() as u32;
+
+    for (connection_id, connection) in connections.iter_mut() {
+        if !connection.waiting { continue }
+        let Some(worklet_id) = pending.pop_front() else { break };
+
+        let assign = Assign {
+            worklet_id,
+            worklet_total,
+            start_chunk: worklet_progress[worklet_id as usize].chunks_done.to_string(),
+        };
+
+        connection.waiting = false;
+        match send_coordinator_packet(&mut &connection.stream, CoordinatorPacketKind::Assign(assign)) {
+            Ok(_) => {
+                connection.worklet_id = Some(worklet_id);
+                println!("Assigned range {worklet_id} to worker {connection_id}");
+            },
+            Err(err) => {
+                // the connection's thread will report the disconnection
+                println!("Failed to assign range {worklet_id} to worker {connection_id}: {err}");
+                let _ = connection.stream.shutdown(Shutdown::Both);
+                pending.push_front(worklet_id);
+            },
+        }
+    }
+}
+
+fn run_coordinator<C: Cipher>(inputs: &SearchInputs, cipher: &C, address: &str, mut monitor: SearchMonitor) -> UnitResult {
+    let listener = TcpListener::bind(address)?;
+    let expected_hello = make_worker_hello(inputs, cipher);
+    let worker_timeout = Duration::from_secs(inputs.args.worker_timeout);
+    let (tx, rx) = sync_channel::<CoordinatorEvent>(64);
+
+    let mut pending = VecDeque::<u32>::new();
+    for (worklet_id, progress) in monitor.worklet_progress.iter().enumerate() {
+        if progress.finished {
+            println!("Range {worklet_id} already finished");
+        } else {
+            pending.push_back(worklet_id as u32);
+        }
+    }
+
+    println!("Waiting for workers on {}", listener.local_addr()?);
+
+    // not scoped; the listener keeps accepting connections until the process
+    // exits, since workers may be added at any time
+    std::thread::spawn(move || {
+        for (connection_id, stream) in (0u64..).zip(listener.incoming()) {
+            match stream {
+                Ok(stream) => {
+                    let expected_hello = expected_hello.clone();
+                    let tx = tx.clone();
+
+                    std::thread::spawn(move || {
+                        let reason = match coordinator_connection(connection_id, stream, &expected_hello, worker_timeout, &tx) {
+                            Ok(_) => "connection closed by worker".into(),
+                            Err(err) => match err.downcast_ref::<std::io::Error>() {
+                                Some(io_err) if matches!(io_err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => "timed out".into(),
+                                _ => err.to_string().into_boxed_str(),
+                            },
+                        };
+
+                        let _ = tx.send(CoordinatorEvent::Disconnected { connection_id, reason });
+                    });
+                },
+                Err(err) => {
+                    println!("Failed to accept worker connection: {err}");
+                },
+            }
+        }
+    });
+
+    let mut connections = HashMap::<u64, WorkerConnection>::new();
+    let mut worklets_waiting = pending.len();
+
+    while worklets_waiting > 0 {
+        match rx.recv_timeout(RECV_TIMEOUT) {
+            Ok(event) => {
+                match event {
+                    CoordinatorEvent::Connected { connection_id, stream } => {
+                        match stream.peer_addr() {
+                            Ok(addr) => println!("Worker {connection_id} connected from {addr}"),
+                            Err(_) => println!("Worker {connection_id} connected"),
+                        }
+
+                        connections.insert(connection_id, WorkerConnection { stream, worklet_id: None, waiting: false });
+                    },
+                    CoordinatorEvent::RequestWork { connection_id } => {
+                        if let Some(connection) = connections.get_mut(&connection_id) {
+                            connection.waiting = true;
+                        }
+                    },
+                    CoordinatorEvent::Task { connection_id, packet } => {
+                        // packets for ranges that aren't assigned to the
+                        // connection are ignored. this only happens with
+                        // misbehaving workers
+                        let Some(connection) = connections.get_mut(&connection_id) else { continue };
+
+                        match packet {
+                            TaskPacket::Progress { worklet_id, keys, net_keys } if connection.worklet_id == Some(worklet_id) => {
+                                monitor.on_progress(cipher, worklet_id, keys, net_keys)?;
+                            },
+                            TaskPacket::Finished { worklet_id } if connection.worklet_id == Some(worklet_id) => {
+                                connection.worklet_id = None;
+                                worklets_waiting -= 1;
+                                monitor.on_finished(worklet_id);
+                                println!("Worker {connection_id} finished range {worklet_id}");
+                            },
+                            TaskPacket::Error { worklet_id, message } if connection.worklet_id == Some(worklet_id) => {
+                                // not reassigned; errors are most likely
+                                // caused by the condition, and would just
+                                // happen again
+                                connection.worklet_id = None;
+                                worklets_waiting -= 1;
+                                println!("Worker {connection_id} errored in range {worklet_id}: {message}");
+                            },
+                            _ => {
+                                println!("Ignored packet from worker {connection_id} for a range that isn't assigned to it");
+                            },
+                        }
+                    },
+                    CoordinatorEvent::Disconnected { connection_id, reason } => {
+                        println!("Worker {connection_id} disconnected: {reason}");
+
+                        if let Some(WorkerConnection { worklet_id: Some(worklet_id), .. }) = connections.remove(&connection_id) {
+                            println!("Range {worklet_id} will be reassigned");
+                            pending.push_front(worklet_id);
+                        }
+                    },
+                }
+            },
+            Err(err) => {
+                match err {
+                    RecvTimeoutError::Timeout => { /* do nothing */ },
+                    RecvTimeoutError::Disconnected => {
+                        println!("Coordinator channel disconnected (listener died?)");
+                        return Err(err)?;
+                    },
+                }
+            },
+        }
+
+        assign_pending_ranges(&mut connections, &mut pending, &monitor.worklet_progress);
+        monitor.tick()?;
+    }
+
+    for connection in connections.values() {
+        let _ = send_coordinator_packet(&mut &connection.stream, CoordinatorPacketKind::Done(Signal {}));
+    }
+
+    monitor.finish(cipher)
+}
+
+/**
+ * Runs a single worklet of a worker, which requests ranges from the
+ * coordinator and searches them until there's nothing left to search
+ */
+fn worker_connection<C: Cipher>(inputs: &SearchInputs, cipher: &C, address: &str, hello: &WorkerHello, messages: &InterleavedMessageData) -> UnitResult {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    // shared with the heartbeat thread
+    let writer = Mutex::new(stream);
+
+    send_worker_packet(&mut *writer.lock().unwrap(), WorkerPacketKind::Hello(hello.clone()))?;
+    match receive_coordinator_packet(&mut reader)? {
+        Some(CoordinatorPacketKind::Welcome(_)) => {},
+        Some(CoordinatorPacketKind::Rejected(rejected)) => return Err(format!("Coordinator rejected worker; {}", rejected.reason).into()),
+        _ => return Err(DistributedError::UnexpectedPacket.into()),
+    }
+
+    let (stop_tx, stop_rx) = channel::<()>();
+
+    std::thread::scope(|scope| -> UnitResult {
+        let writer = &writer;
+
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(HEARTBEAT_INTERVAL) {
+                if send_worker_packet(&mut *writer.lock().unwrap(), WorkerPacketKind::Heartbeat(Signal {})).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let res = (|| -> UnitResult {
+            loop {
+                send_worker_packet(&mut *writer.lock().unwrap(), WorkerPacketKind::RequestWork(Signal {}))?;
+
+                let assign = match receive_coordinator_packet(&mut reader)? {
+                    Some(CoordinatorPacketKind::Assign(assign)) => assign,
+                    Some(CoordinatorPacketKind::Done(_)) | None => return Ok(()),
+                    _ => return Err(DistributedError::UnexpectedPacket.into()),
+                };
+
+                if assign.worklet_id >= assign.worklet_total {
+                    return Err(DistributedError::BadAssignment.into());
+                }
+
+                let worklet_id = assign.worklet_id;
+                let start_chunk: Integer = assign.start_chunk.parse()?;
+                let worklet_ctx = cipher.create_worklet_context_parallel(worklet_id, assign.worklet_total);
+                println!("Searching range {worklet_id} (of {}), starting at chunk {start_chunk}", assign.worklet_total);
+
+                let (tx, rx) = sync_channel::<TaskPacket>(64);
+
+                std::thread::scope(|task_scope| -> UnitResult {
+                    task_scope.spawn(move || {
+                        run_search_task(worklet_id, messages, worklet_ctx, &start_chunk, None, inputs, &tx);
+                    });
+
+                    // if forwarding fails, the receiver is dropped, which
+                    // interrupts the task when it tries to send the next chunk
+                    for packet in rx {
+                        let kind = match packet {
+                            TaskPacket::Progress { worklet_id, keys, net_keys } => WorkerPacketKind::Progress(Progress {
+                                worklet_id,
+                                keys,
+                                net_keys: net_keys.into_iter().map(|x| x.into()).collect(),
+                            }),
+                            TaskPacket::Finished { worklet_id } => WorkerPacketKind::Finished(Finished { worklet_id }),
+                            TaskPacket::Error { worklet_id, message } => WorkerPacketKind::Error(WorkError { worklet_id, message: message.into() }),
+                            // clap doesn't allow ranking keys in workers
+                            TaskPacket::Top { .. } => unreachable!(),
+                        };
+
+                        send_worker_packet(&mut *writer.lock().unwrap(), kind)?;
+                    }
+
+                    Ok(())
+                })?;
+            }
+        })();
+
+        drop(stop_tx);
+        res
+    })
+}
+
+fn run_worker<C: Cipher + Sync>(inputs: &SearchInputs, cipher: &C, address: &str) -> UnitResult {
+    let worklet_total = get_local_parallelism(&inputs.args);
+    let hello = make_worker_hello(inputs, cipher);
+    let messages = AcceleratedMessageList::from_messages(inputs.messages_render_map.get_messages());
+
+    println!("Connecting to coordinator at {address} with {worklet_total} worklets");
+
+    std::thread::scope(|scope| {
+        for worklet_id in 0..worklet_total {
+            let hello = &hello;
+            let messages = &messages.data;
+
+            scope.spawn(move || {
+                match worker_connection(inputs, cipher, address, hello, messages) {
+                    Ok(_) => println!("Worklet {worklet_id} finished; nothing left to search"),
+                    Err(err) => println!("Worklet {worklet_id} errored: {err}"),
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+fn run_search<C: Cipher + Sync>(inputs: SearchInputs, cipher: C) -> UnitResult {
+    let args = &inputs.args;
+
+    if let Some(address) = &args.worker {
+        return run_worker(&inputs, &cipher, address);
+    }
+
+    let resume_state = match &args.state_path {
+        Some(path) if args.resume => {
+            let state = import_search_state(path)?;
+            check_resume_state(&state, &inputs)?;
+            Some(state)
+        },
+        Some(path) => {
+            if path.exists() {
+                return Err(format!("{} already exists. Pass --resume to continue the search saved in it, or delete it to start a new search. Aborted", path.display()).into());
+            }
+
+            None
+        },
+        None => None,
+    };
+
+    let key_dump_writer: Option<KeyDumpWriter> = match &args.key_dump_path {
+        Some(path) => {
+            let meta = KeyDumpMeta {
+                build_hash: String::from(env!("GIT_HASH")),
+                cipher_name: inputs.cipher_name.clone().into(),
+                cipher_config: inputs.cipher_config.clone().map(|x| x.into_string()),
+                condition: inputs.condition.clone().into(),
+                data_hash: inputs.data_hash,
+                alphabet_name: inputs.alphabet.get_name().clone().into(),
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                decrypt: inputs.decrypt,
+            };
+
+            match &resume_state {
+                Some(state) => {
+                    // discard matches of chunks that weren't fully checked
+                    // when the state was saved. they will be checked again
+                    Some(KeyDumpWriter::resume(path, state.key_dump_len.unwrap_or(0), &meta)?)
+                },
+                None => Some(KeyDumpWriter::create(path, &meta)?),
+            }
+        },
+        None => None,
+    };
+
+    let worklet_total = match &resume_state {
+        Some(state) => state.worklets.len() as u32,
+        None => {
+            let wanted_parallelism = match &args.coordinator {
+                Some(_) => args.ranges.map_or(DEFAULT_COORDINATOR_RANGES, |x| x.get()),
+                None => get_local_parallelism(args),
+            };
+
+            wanted_parallelism.min(cipher.get_max_parallelism())
+        },
+    };
+
+    let mut worklet_progress = Vec::<WorkletProgress>::new();
+    match &resume_state {
+        Some(state) => {
+            for worklet_state in state.worklets.iter() {
+                worklet_progress.push(WorkletProgress {
+                    chunks_done: worklet_state.chunks_done.parse()?,
+                    keys_checked: worklet_state.keys_checked.parse()?,
+                    finished: worklet_state.finished,
+                });
+            }
+        },
+        None => {
+            for _ in 0..worklet_total {
+                worklet_progress.push(WorkletProgress { chunks_done: Integer::new(), keys_checked: Integer::new(), finished: false });
             }
+        },
+    }
+
+    let mut keys_total = Integer::new();
+    let mut worklet_ctxs = Vec::new();
+
+    for worklet_id in 0..worklet_total {
+        let worklet_ctx = cipher.create_worklet_context_parallel(worklet_id, worklet_total);
+        keys_total += worklet_ctx.get_total_keys();
+        worklet_ctxs.push(worklet_ctx);
+    }
+
+    let Some(samples) = args.sample else {
+        let headline = match args.top {
+            Some(top) => format!("Ranking {} keys with {} worklets, keeping the {} best", format_big_uint(&keys_total), worklet_total, top),
+            None => format!("Searching {} keys with {} worklets", format_big_uint(&keys_total), worklet_total),
+        };
+
+        preamble(&inputs.messages_render_map, &inputs.alphabet, &headline, inputs.decrypt);
+
+        let monitor = SearchMonitor::new(&inputs, key_dump_writer, worklet_progress, keys_total, None);
+
+        return match &args.coordinator {
+            Some(address) => {
+                // worklet contexts are created by the workers instead
+                drop(worklet_ctxs);
+                run_coordinator(&inputs, &cipher, address, monitor)
+            },
+            None => run_local(&inputs, &cipher, worklet_ctxs, None, monitor),
+        };
+    };
+
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+
+    // each worklet gets a share of the samples proportional to its share of
+    // the key space, so that the samples are uniform over the whole key space
+    let mut sample_tasks = Vec::new();
+    let mut keys_before = Integer::new();
+    let mut samples_before = 0u64;
+
+    for worklet_ctx in worklet_ctxs.iter() {
+        keys_before += worklet_ctx.get_total_keys();
+        let samples_until = if keys_total == 0 {
+            0
+        } else {
+            Integer::from(Integer::from(&keys_before * samples) / &keys_total).to_u64_wrapping()
+        };
+
+        sample_tasks.push(SampleTask { samples: samples_until - samples_before, seed });
+        samples_before = samples_until;
+    }
+
+    preamble(&inputs.messages_render_map, &inputs.alphabet, &format!("Sampling {} of {} keys with {} worklets (seed {})", samples, format_big_uint(&keys_total), worklet_total, seed), inputs.decrypt);
+
+    let monitor = SearchMonitor::new(&inputs, key_dump_writer, worklet_progress, Integer::from(samples), Some(keys_total));
+    run_local(&inputs, &cipher, worklet_ctxs, Some(sample_tasks), monitor)
+}
+
+struct SearchVisitor {
+    inputs: SearchInputs,
+    /// Keys of the key dump being refined, if any
+    refine_net_keys: Option<Vec<Box<[u8]>>>,
+}
+
+impl CipherVisitor for SearchVisitor {
+    type Output = UnitResult;
+
+    fn visit<C: Cipher + Send + Sync + 'static>(self, cipher: C) -> UnitResult {
+        match self.refine_net_keys {
+            Some(net_keys) => run_search(self.inputs, KeyListCipher::new(cipher, net_keys)?),
+            None => run_search(self.inputs, cipher),
         }
+    }
+}
 
-        keys_checked += &keys_checked_since_last_print;
+fn print_cipher_list() {
+    for info in get_cipher_infos() {
+        println!("{}: {}", info.name, info.description);
+        match info.config_schema {
+            Some(schema) => println!("    Configuration: {schema}"),
+            None => println!("    Not configurable"),
+        }
+    }
+}
 
-        print_progress(
-            None,
-            Instant::now().duration_since(last_print).as_secs_f64(),
-            &keys_total,
-            &keys_checked,
-            &keys_checked_since_last_print,
-        );
+fn main() { main_error_wrap!({
+    let args = Args::parse();
 
-        Ok(())
-    })?;
+    if args.list_ciphers {
+        print_cipher_list();
+        return Ok(());
+    }
+
+    // clap requires both unless listing ciphers
+    let (Some(data_path), Some(condition)) = (&args.data_path, args.condition.clone()) else { unreachable!() };
+
+    let languages = import_csv_languages(&args.language)?;
+    let alphabet = import_csv_alphabet_or_default(&args.alphabet)?;
+    let ngram_models = import_csv_ngram_models(&args.ngram_model, &alphabet)?;
+    let messages_render_map = import_messages(data_path, &alphabet)?;
+    let decrypt = !args.encrypt;
+    let data_hash = hash_message_list(messages_render_map.get_messages());
+
+    let (cipher_name, cipher_config, refine_net_keys) = match &args.refine {
+        Some(refine_path) => {
+            if args.key_dump_path.as_ref() == Some(refine_path) {
+                return Err("Can't refine a key dump into itself; pass a different key dump path".into());
+            }
+
+            let mut reader = KeyDumpReader::open(refine_path)?;
+            let net_keys = reader.read_all_keys()?;
+            let meta = reader.get_meta();
+
+            if let Some(cipher_name) = &args.cipher && (**cipher_name != *meta.cipher_name || args.config.as_deref() != meta.cipher_config.as_deref()) {
+                return Err("Cipher or cipher configuration differs from the key dump being refined".into());
+            }
+
+            if meta.build_hash != env!("GIT_HASH") {
+                println!("Warning: refined key dump was created with a different build ({}). Continuing, but keys may be wrong if the key encoding changed", meta.build_hash.trim());
+            }
+
+            if meta.data_hash != data_hash {
+                println!("Warning: message data differs from the message data that was searched for the refined key dump");
+            }
+
+            if meta.decrypt != decrypt {
+                println!("Warning: refined key dump was created with a search that was {}, but this search is {}", if meta.decrypt { "decrypting" } else { "encrypting" }, if decrypt { "decrypting" } else { "encrypting" });
+            }
+
+            println!("Refining {} keys (condition of refined key dump was \"{}\")", net_keys.len(), meta.condition);
+
+            let cipher_name: Box<str> = meta.cipher_name.as_str().into();
+            let cipher_config: Option<Box<str>> = meta.cipher_config.as_deref().map(|x| x.into());
+            (cipher_name, cipher_config, Some(net_keys))
+        },
+        None => {
+            let Some(cipher_name) = args.cipher.clone() else {
+                return Err("A cipher must be passed, unless refining a key dump".into());
+            };
+
+            (cipher_name, args.config.clone(), None)
+        },
+    };
+
+    let visitor = SearchVisitor {
+        inputs: SearchInputs { args, condition, cipher_name: cipher_name.clone(), cipher_config: cipher_config.clone(), languages, ngram_models, alphabet, messages_render_map, decrypt, data_hash },
+        refine_net_keys,
+    };
+
+    deserialise_cipher(&cipher_name, cipher_config.as_deref(), visitor)??;
 }) }
@@ -0,0 +1,255 @@
+use std::{error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::MixedRadixKeySpace}, data::message::InterleavedMessageData, utils::run::AnyErrorResult};
+
+/**
+ * Multiplicative inverse of x modulo modulus, or None if x is not invertible
+ * (not coprime with the modulus)
+ */
+pub(crate) fn invert_mod(x: i64, modulus: u16) -> Option<u16> {
+    let x = Integer::from(x.rem_euclid(modulus as i64));
+    x.invert(&Integer::from(modulus)).ok().map(|inverse| inverse.to_u16().unwrap())
+}
+
+pub(crate) fn validate_modulus(modulus: u16) -> Result<(), StandardCipherError> {
+    if modulus < 2 || modulus > 256 {
+        Err(StandardCipherError::BadKey { msg: "Modulus must be in the range 2..=256".into() })
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AffineConfig {
+    /// Size of the alphabet. Units greater or equal to this are left as-is
+    modulus: u16,
+}
+
+impl CipherConfig for AffineConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("modulus", self.modulus, 2..=256)
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedAffineKey {
+    #[prost(uint32, tag = "1")]
+    pub modulus: u32,
+    #[prost(uint32, tag = "2")]
+    pub a: u32,
+    #[prost(uint32, tag = "3")]
+    pub b: u32,
+}
+
+/**
+ * Encrypts with y = a * x + b (mod modulus). The modulus is part of the key,
+ * since codec contexts only have access to the key
+ */
+#[derive(Clone)]
+pub struct AffineKey {
+    /** range: 2-256 */
+    pub modulus: u16,
+    /** must be coprime with the modulus */
+    pub a: u16,
+    pub b: u16,
+    /** inverse of a, for decryption. Derived from a, so it's not encoded */
+    a_inverse: u16,
+}
+
+impl AffineKey {
+    pub fn new(modulus: u16, a: u16, b: u16) -> Result<Self, Box<dyn Error>> {
+        validate_modulus(modulus)?;
+        if a >= modulus || b >= modulus {
+            return Err(StandardCipherError::BadKey { msg: "a and b must be less than the modulus".into() }.into());
+        }
+
+        let a_inverse = invert_mod(a as i64, modulus).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: format!("a ({a}) is not invertible modulo {modulus}").into() }
+        })?;
+
+        Ok(AffineKey { modulus, a, b, a_inverse })
+    }
+}
+
+impl ToString for AffineKey {
+    fn to_string(&self) -> String {
+        format!("[{}x+{} mod {}]", self.a, self.b, self.modulus)
+    }
+}
+
+impl FromStr for AffineKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_format = || StandardCipherError::BadKey { msg: "Expected a key in the format \"[ax+b mod modulus]\"".into() };
+        let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(bad_format)?;
+        let (expr, modulus) = inner.split_once(" mod ").ok_or_else(bad_format)?;
+        let (a, b) = expr.trim().split_once("x+").ok_or_else(bad_format)?;
+
+        let parse = |x: &str| x.trim().parse::<u16>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid number \"{}\"", x.trim()).into() }));
+        AffineKey::new(parse(modulus)?, parse(a)?, parse(b)?)
+    }
+}
+
+impl CipherKey for AffineKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedAffineKey {
+            modulus: self.modulus as u32,
+            a: self.a as u32,
+            b: self.b as u32,
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedAffineKey::decode(buffer.iter().as_slice())?;
+        AffineKey::new(enc_key.modulus.try_into()?, enc_key.a.try_into()?, enc_key.b.try_into()?)
+    }
+}
+
+pub struct AffineCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec AffineKey,
+    input_messages: &'codec InterleavedMessageData,
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, AffineKey> for AffineCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec AffineKey) -> Self {
+        AffineCodecContext { input_messages, key }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        // SAFETY: bounds must be verified by caller
+        let unit = unsafe { *self.input_messages.get_unchecked(message_index, unit_index) } as u32;
+        let modulus = self.key.modulus as u32;
+        if unit >= modulus { return unit as u8 }
+
+        let output = if DECRYPT {
+            // x = a^-1 * (y - b). adding the modulus before subtracting never
+            // underflows, since b is less than the modulus
+            self.key.a_inverse as u32 * (unit + modulus - self.key.b as u32)
+        } else {
+            self.key.a as u32 * unit + self.key.b as u32
+        };
+
+        (output % modulus) as u8
+    }
+}
+
+pub struct AffineWorkletContext {
+    modulus: u16,
+    /// Invertible values of a, and their inverses
+    a_values: Vec<(u16, u16)>,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<AffineKey> for AffineWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = AffineCodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&AffineKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let mut key = AffineKey { modulus: self.modulus, a: 1, b: 0, a_inverse: 1 };
+
+        // the first digit is an index in the list of invertible values of a,
+        // so that non-invertible keys are never permuted
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            (key.a, key.a_inverse) = self.a_values[digits[0] as usize];
+            key.b = digits[1] as u16;
+            key_callback(&key);
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<AffineKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let (a, a_inverse) = self.a_values[digits[0] as usize];
+        Some(AffineKey { modulus: self.modulus, a, b: digits[1] as u16, a_inverse })
+    }
+}
+
+pub struct AffineCipher {
+    modulus: u16,
+    a_values: Vec<(u16, u16)>,
+    key_space: MixedRadixKeySpace,
+}
+
+impl AffineCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<AffineCipher> {
+        let config: AffineConfig = parse_config(config)?;
+
+        let mut a_values = Vec::new();
+        for a in 1..config.modulus {
+            if let Some(a_inverse) = invert_mod(a as i64, config.modulus) {
+                a_values.push((a, a_inverse));
+            }
+        }
+
+        let key_space = MixedRadixKeySpace::new(vec![[a_values.len() as u32, config.modulus as u32].into()]);
+        Ok(AffineCipher { modulus: config.modulus, a_values, key_space })
+    }
+}
+
+impl Cipher for AffineCipher {
+    type Key = AffineKey;
+    type Context = AffineWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        AffineWorkletContext {
+            modulus: self.modulus,
+            a_values: self.a_values.clone(),
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &AffineKey, rng: &mut R) -> Option<AffineKey> {
+        let mut key = key.clone();
+        if rng.random_bool(0.5) {
+            (key.a, key.a_inverse) = self.a_values[rng.random_range(0..self.a_values.len())];
+        } else {
+            key.b = rng.random_range(0..self.modulus);
+        }
+
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &AffineKey, b: &AffineKey, rng: &mut R) -> Option<AffineKey> {
+        let mut child = a.clone();
+        if rng.random_bool(0.5) {
+            (child.a, child.a_inverse) = (b.a, b.a_inverse);
+        } else {
+            child.b = b.b;
+        }
+
+        Some(child)
+    }
+}
+
+impl CipherFactory for AffineCipher {
+    const NAME: &'static str = "affine";
+    const DESCRIPTION: &'static str = "Affine cipher (y = a * x + b), skipping keys where a is not invertible";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(modulus: 2..=256)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(AffineCipher::new(config)?))
+    }
+}
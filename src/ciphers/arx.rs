@@ -1,9 +1,10 @@
-use std::error::Error;
+use std::{error::Error, str::FromStr};
 
 use prost::Message;
-use rug::{Integer, ops::Pow};
+use rand::Rng;
+use rug::Integer;
 
-use crate::{ciphers::base::{Cipher, CipherCodecContext, CipherWorkletContext, StandardCipherError}, data::message::InterleavedMessageData, utils::{run::AnyErrorResult, stackvec::StackVec, threading::get_worklet_slice}};
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherWorkletContext, StandardCipherError}}, data::message::InterleavedMessageData, utils::{run::AnyErrorResult, stackvec::StackVec, threading::get_worklet_slice}};
 
 use super::base::CipherKey;
 
@@ -14,25 +15,21 @@ use super::base::CipherKey;
  * this to do cryptanalysis
  */
 
-const KEYS_PER_ROUND: u32 = 524288;
 const MAX_ROUNDS: usize = 8;
 
 macro_rules! permute_round {
-    ($round:expr, $add_min:expr, $add_max:expr, $callback:block) => {
-        for add in $add_min as u8..=$add_max as u8 {
-            $round.add = add;
-            for xor in 0..=255 {
-                $round.xor = xor;
-                for rot in 0..=7 {
-                    $round.rot = rot;
+    ($round:expr, $values:expr, $callback:block) => {
+        for add in $values.add.iter() {
+            $round.add = *add;
+            for xor in $values.xor.iter() {
+                $round.xor = *xor;
+                for rot in $values.rot.iter() {
+                    $round.rot = *rot;
                     $callback;
                 }
             }
         }
     };
-    ($round:expr, $callback:block) => {
-        permute_round!($round, 0, 255, $callback)
-    };
 }
 
 #[derive(prost::Message)]
@@ -54,7 +51,7 @@ struct EncodedARXKey {
     pub rounds: Vec<EncodedARXRound>,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ARXRound {
     /** range: 0-255 */
     pub add: u8,
@@ -64,7 +61,7 @@ pub struct ARXRound {
     pub xor: u8,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct ARXKey {
     pub rounds: StackVec<ARXRound, MAX_ROUNDS>,
 }
@@ -86,6 +83,67 @@ impl ToString for ARXKey {
     }
 }
 
+/**
+ * Parses keys in the same format as to_string. Round boundaries aren't
+ * included in that format, so operations are greedily packed into as few
+ * rounds as possible. This is equivalent to the original key, since zero
+ * parameters are no-ops
+ */
+impl FromStr for ARXKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: "Key must be wrapped in square brackets".into() }
+        })?;
+
+        let mut key = ARXKey::default();
+        if inner == "no-op key" {
+            key.rounds.push(ARXRound::default());
+            return Ok(key);
+        }
+
+        // index of the last parameter set in the current round, in order of
+        // application (add, rot, xor)
+        let mut last_param = usize::MAX;
+
+        for part in inner.split("->") {
+            let part = part.trim();
+            let (param, value) = match part.chars().next() {
+                Some('a') => (0, &part[1..]),
+                Some('r') => (1, &part[1..]),
+                Some('x') => (2, &part[1..]),
+                _ => return Err(StandardCipherError::BadKey { msg: format!("Unknown operation \"{part}\"; expected a, r or x").into() }.into()),
+            };
+
+            let value = value.parse::<u8>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid value in operation \"{part}\"").into() }))?;
+            if param == 1 && value > 7 {
+                return Err(StandardCipherError::BadKey { msg: format!("Rotation in operation \"{part}\" must be in the range 0..=7").into() }.into());
+            }
+
+            if last_param == usize::MAX || param <= last_param {
+                if key.rounds.len() >= MAX_ROUNDS {
+                    return Err(StandardCipherError::BadKey { msg: format!("Max round count ({MAX_ROUNDS}) exceeded").into() }.into());
+                }
+
+                key.rounds.push(ARXRound::default());
+            }
+
+            let r = key.rounds.len() - 1;
+            let round = &mut key.rounds[r];
+            match param {
+                0 => round.add = value,
+                1 => round.rot = value,
+                _ => round.xor = value,
+            }
+
+            last_param = param;
+        }
+
+        Ok(key)
+    }
+}
+
 impl CipherKey for ARXKey {
     fn encode_to_buffer(&self) -> Box<[u8]> {
         let mut enc_key = EncodedARXKey::default();
@@ -153,36 +211,56 @@ impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, ARXKey> fo
 }
 
 pub struct ARXWorkletContext {
-    round_count: usize,
-    a_min: u8,
-    a_max: u8,
+    rounds: Vec<ARXRoundValues>,
+    /** range of round indices of the first round permuted by this worklet */
+    first_round_range: (u32, u32),
 }
 
 impl ARXWorkletContext {
-    unsafe fn permute_additional_round<KC: FnMut(&ARXKey), CC: FnMut(u32) -> bool>(&self, r: usize, r_max: usize, key: &mut ARXKey, key_callback: &mut KC, chunk_callback: &mut CC) -> bool {
-        // TODO maybe do macro for this entire pattern, including the part in
-        //      the other method?
+    /**
+     * Range of round indices (see ARXRoundValues::set_round) permuted by this
+     * worklet for round r. Only the first round is sliced between worklets
+     */
+    fn get_round_index_range(&self, r: usize) -> (u32, u32) {
+        if r == 0 {
+            self.first_round_range
+        } else {
+            (0, self.rounds[r].get_key_count())
+        }
+    }
+
+    unsafe fn permute_additional_round<KC: FnMut(&ARXKey), CC: FnMut(u32) -> bool>(&self, r: usize, r_max: usize, key: &mut ARXKey, start_idxs: Option<&[u32; MAX_ROUNDS]>, key_callback: &mut KC, chunk_callback: &mut CC) -> bool {
         if r == r_max {
-            // last round, do occasional callback and don't recurse
+            // last round, do occasional callback and don't recurse. each sweep
+            // of the last round is a chunk, so start_idxs is not needed here
             // SAFETY: the caller must guarantee that r_max < key.rounds.len(),
             //         and that r <= r_max
-            permute_round!(unsafe { key.rounds.get_unchecked_mut(r) }, {
+            permute_round!(unsafe { key.rounds.get_unchecked_mut(r) }, self.rounds[r], {
                 key_callback(key)
             });
 
-            chunk_callback(KEYS_PER_ROUND)
+            chunk_callback(self.rounds[r].get_key_count())
         } else {
-            // middle round, recurse
-            // SAFETY: the caller must guarantee that r_max < key.rounds.len(),
-            //         and that r <= r_max
-            permute_round!(unsafe { key.rounds.get_unchecked_mut(r) }, {
+            // middle round, recurse. only the first iteration of each round
+            // starts at the resumed position, the following ones start at the
+            // beginning of the round
+            let (idx_min, idx_max) = self.get_round_index_range(r);
+            let mut resume_idxs = start_idxs;
+
+            for idx in resume_idxs.map_or(idx_min, |idxs| idxs[r])..idx_max {
+                // SAFETY: the caller must guarantee that r_max < key.rounds.len(),
+                //         and that r <= r_max
+                self.rounds[r].set_round(unsafe { key.rounds.get_unchecked_mut(r) }, idx);
+
                 // SAFETY: r must be < r_max when calling this method, so this
                 //         is only invalid when the caller passes bad arguments
                 //         (hence why this method is unsafe)
-                if !unsafe { self.permute_additional_round(r + 1, r_max, key, key_callback, chunk_callback) } {
+                if !unsafe { self.permute_additional_round(r + 1, r_max, key, resume_idxs, key_callback, chunk_callback) } {
                     return false;
                 }
-            });
+
+                resume_idxs = None;
+            }
 
             true
         }
@@ -193,54 +271,239 @@ impl CipherWorkletContext<ARXKey> for ARXWorkletContext {
     type CodecContext<'codec, const DECRYPT: bool> = ARXCodecContext<'codec, DECRYPT>;
 
     fn get_total_keys(&self) -> Integer {
-        if self.round_count == 0 { return Integer::new(); }
-        let mut total = Integer::from(((self.a_max - self.a_min) as u64 + 1) * 2048);
-        total *= Integer::from(KEYS_PER_ROUND).pow((self.round_count - 1) as u32);
+        if self.rounds.len() == 0 { return Integer::new(); }
+        let mut total = Integer::from(self.first_round_range.1 - self.first_round_range.0);
+        for round in &self.rounds[1..] {
+            total *= round.get_key_count();
+        }
+
         total
     }
 
-    fn permute_keys_interruptible<KC: FnMut(&ARXKey), CC: FnMut(u32) -> bool>(&self, mut key_callback: KC, mut chunk_callback: CC) {
-        let round_count: usize = self.round_count;
+    fn get_total_chunks(&self) -> Option<Integer> {
+        // see permute_keys_interruptible_from
+        Some(match self.rounds.len() {
+            0 => Integer::new(),
+            1 => Integer::from(1),
+            round_count => {
+                let mut total = Integer::from(self.first_round_range.1 - self.first_round_range.0);
+                for round in &self.rounds[1..round_count - 1] {
+                    total *= round.get_key_count();
+                }
+
+                total
+            },
+        })
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&ARXKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, mut chunk_callback: CC) {
+        let round_count: usize = self.rounds.len();
         if round_count == 0 { return }
 
         let mut key = ARXKey { rounds: StackVec::new() };
         key.rounds.resize_with(round_count, ARXRound::default);
 
         if round_count == 1 {
-            permute_round!(key.rounds[0], self.a_min, self.a_max, {
+            // everything is done in a single chunk
+            if *start_chunk != 0 { return }
+
+            let (idx_min, idx_max) = self.first_round_range;
+            for idx in idx_min..idx_max {
+                self.rounds[0].set_round(&mut key.rounds[0], idx);
                 key_callback(&key);
-            });
+            }
 
-            chunk_callback((self.a_max as u32 - self.a_min as u32 + 1) * 256 * 8);
+            chunk_callback(idx_max - idx_min);
         } else {
-            permute_round!(key.rounds[0], self.a_min, self.a_max, {
-                // SAFETY: round_count must be at least 2 to reach this block,
-                //         so 1 is guaranteed to be <= r_max, as r_max is
-                //         round_count - 1, which is 2 - 1 = 1 at minimum
-                if !unsafe { self.permute_additional_round(1, round_count - 1, &mut key, &mut key_callback, &mut chunk_callback) } { return }
-            });
+            // a chunk is a sweep of the last round, so the chunk index is the
+            // index of the permutation of all previous rounds. decompose it
+            // into the round index of each of those rounds
+            let mut start_idxs = [0u32; MAX_ROUNDS];
+            let mut chunks_left = start_chunk.clone();
+            for r in (1..round_count - 1).rev() {
+                let (div, rem) = chunks_left.div_rem_euc(Integer::from(self.rounds[r].get_key_count()));
+                start_idxs[r] = rem.to_u32().unwrap();
+                chunks_left = div;
+            }
+
+            let (idx_min, idx_max) = self.get_round_index_range(0);
+            if chunks_left >= idx_max - idx_min { return } // already finished
+            start_idxs[0] = idx_min + chunks_left.to_u32().unwrap();
+
+            // SAFETY: round_count must be at least 2 to reach this block, so 0
+            //         is guaranteed to be < r_max, as r_max is round_count - 1,
+            //         which is 2 - 1 = 1 at minimum
+            unsafe { self.permute_additional_round(0, round_count - 1, &mut key, Some(&start_idxs), &mut key_callback, &mut chunk_callback) };
+        }
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<ARXKey> {
+        let (idx_min, idx_max) = self.first_round_range;
+        if self.rounds.len() == 0 || idx_min == idx_max { return None }
+
+        let mut key = ARXKey { rounds: StackVec::new() };
+        key.rounds.resize_with(self.rounds.len(), ARXRound::default);
+        for (r, values) in self.rounds.iter().enumerate() {
+            let (idx_min, idx_max) = self.get_round_index_range(r);
+            values.set_round(&mut key.rounds[r], rng.random_range(idx_min..idx_max));
         }
+
+        Some(key)
+    }
+}
+
+/**
+ * Values that a round parameter can take. Parameters without a constraint can
+ * take any value
+ */
+#[derive(serde::Deserialize)]
+enum ARXParameterConstraint {
+    Fixed(u8),
+    /** inclusive */
+    Range(u8, u8),
+    Set(Vec<u8>),
+}
+
+impl ARXParameterConstraint {
+    fn validate(&self, path: &str, max_value: u8) -> Result<(), StandardCipherError> {
+        match self {
+            ARXParameterConstraint::Fixed(value) => check_range(path, *value, 0..=max_value),
+            ARXParameterConstraint::Range(min, max) => {
+                check_range(path, *max, 0..=max_value)?;
+                if min > max {
+                    return Err(StandardCipherError::BadConfiguration { msg: format!("{path}: range minimum ({min}) must not be greater than maximum ({max})").into() });
+                }
+
+                Ok(())
+            },
+            ARXParameterConstraint::Set(values) => {
+                if values.len() == 0 {
+                    return Err(StandardCipherError::BadConfiguration { msg: format!("{path}: sets must not be empty").into() });
+                }
+
+                for value in values {
+                    check_range(path, *value, 0..=max_value)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+#[derive(Default, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ARXRoundConfig {
+    #[serde(default)]
+    add: Option<ARXParameterConstraint>,
+    #[serde(default)]
+    rot: Option<ARXParameterConstraint>,
+    #[serde(default)]
+    xor: Option<ARXParameterConstraint>,
+}
+
+/**
+ * Sorted values of each parameter of a round, after applying the round's
+ * constraints
+ */
+#[derive(Clone, Debug)]
+struct ARXRoundValues {
+    add: Box<[u8]>,
+    rot: Box<[u8]>,
+    xor: Box<[u8]>,
+}
+
+impl ARXRoundValues {
+    fn new(config: &ARXRoundConfig) -> Self {
+        let get_values = |constraint: &Option<ARXParameterConstraint>, max_value: u8| -> Box<[u8]> {
+            let mut values: Vec<u8> = match constraint {
+                None => (0..=max_value).collect(),
+                Some(ARXParameterConstraint::Fixed(value)) => vec![*value],
+                Some(ARXParameterConstraint::Range(min, max)) => (*min..=*max).collect(),
+                Some(ARXParameterConstraint::Set(values)) => values.clone(),
+            };
+
+            values.sort();
+            values.dedup();
+            values.into()
+        };
+
+        ARXRoundValues {
+            add: get_values(&config.add, 255),
+            rot: get_values(&config.rot, 7),
+            xor: get_values(&config.xor, 255),
+        }
+    }
+
+    /** at most 524288, when the round is unconstrained */
+    fn get_key_count(&self) -> u32 {
+        (self.add.len() * self.xor.len() * self.rot.len()) as u32
+    }
+
+    /**
+     * Set all round parameters from a single index in the range
+     * 0..get_key_count(), in the same order that they are permuted in (add,
+     * then xor, then rot)
+     */
+    #[inline(always)]
+    fn set_round(&self, round: &mut ARXRound, idx: u32) {
+        let idx = idx as usize;
+        round.rot = self.rot[idx % self.rot.len()];
+        let idx = idx / self.rot.len();
+        round.xor = self.xor[idx % self.xor.len()];
+        round.add = self.add[idx / self.xor.len()];
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ARXConfig {
+    round_count: usize,
+    /// Constraints of each round, in order of application. Can be shorter than
+    /// the round count, in which case the remaining rounds are unconstrained
+    #[serde(default)]
+    rounds: Vec<ARXRoundConfig>,
+}
+
+impl CipherConfig for ARXConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("round_count", self.round_count, 1..=MAX_ROUNDS)?;
+        if self.rounds.len() > self.round_count {
+            return Err(StandardCipherError::BadConfiguration { msg: format!("rounds: expected at most round_count ({}) rounds, got {}", self.round_count, self.rounds.len()).into() });
+        }
+
+        for (r, round) in self.rounds.iter().enumerate() {
+            if let Some(add) = &round.add { add.validate(&format!("rounds[{r}].add"), 255)? }
+            if let Some(rot) = &round.rot { rot.validate(&format!("rounds[{r}].rot"), 7)? }
+            if let Some(xor) = &round.xor { xor.validate(&format!("rounds[{r}].xor"), 255)? }
+        }
+
+        Ok(())
     }
 }
 
 #[derive(Debug)]
 pub struct ARXCipher {
-    round_count: usize,
+    /** one for each round */
+    rounds: Vec<ARXRoundValues>,
 }
 
 impl ARXCipher {
     pub fn new(config: Option<&str>) -> AnyErrorResult<ARXCipher> {
-        match config {
-            Some(s) => {
-                let round_count = s.parse::<usize>()?;
-                if round_count == 0 || round_count > MAX_ROUNDS {
-                    Err(StandardCipherError::BadConfiguration { msg: "Round count must be in the range 1..=8".into() }.into())
-                } else {
-                    Ok(ARXCipher { round_count })
-                }
+        // a bare round count was the only configuration format before, and is
+        // still accepted so that old key dumps and search states still work
+        let config: ARXConfig = match config.and_then(|s| s.trim().parse::<usize>().ok()) {
+            Some(round_count) => {
+                let config = ARXConfig { round_count, rounds: Vec::new() };
+                config.validate()?;
+                config
             },
-            None => Err(StandardCipherError::MissingConfiguration.into()),
-        }
+            None => parse_config(config)?,
+        };
+
+        let unconstrained = ARXRoundConfig::default();
+        let rounds = (0..config.round_count).map(|r| ARXRoundValues::new(config.rounds.get(r).unwrap_or(&unconstrained))).collect();
+        Ok(ARXCipher { rounds })
     }
 }
 
@@ -248,15 +511,52 @@ impl Cipher for ARXCipher {
     type Key = ARXKey;
     type Context = ARXWorkletContext;
 
-    fn get_max_parallelism(&self) -> u32 { 256 }
+    fn get_max_parallelism(&self) -> u32 {
+        self.rounds[0].get_key_count()
+    }
 
     fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> <ARXCipher as Cipher>::Context {
-        let (a_min, a_max) = get_worklet_slice::<u8>(255, worklet_id, worklet_total);
+        let (idx_min, idx_max) = get_worklet_slice::<usize>(self.rounds[0].get_key_count() as usize - 1, worklet_id, worklet_total);
 
         ARXWorkletContext {
-            round_count: self.round_count,
-            a_min,
-            a_max,
+            rounds: self.rounds.clone(),
+            first_round_range: (idx_min as u32, idx_max as u32 + 1),
         }
     }
+
+    fn mutate_key<R: Rng>(&self, key: &ARXKey, rng: &mut R) -> Option<ARXKey> {
+        // change a single parameter of a single round, within its constraints
+        let mut key = key.clone();
+        let r = rng.random_range(0..self.rounds.len());
+        let (round, values) = (&mut key.rounds[r], &self.rounds[r]);
+
+        match rng.random_range(0..3) {
+            0 => round.add = values.add[rng.random_range(0..values.add.len())],
+            1 => round.rot = values.rot[rng.random_range(0..values.rot.len())],
+            _ => round.xor = values.xor[rng.random_range(0..values.xor.len())],
+        }
+
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &ARXKey, b: &ARXKey, rng: &mut R) -> Option<ARXKey> {
+        let mut child = a.clone();
+        for r in 0..child.rounds.len() {
+            if rng.random_bool(0.5) {
+                child.rounds[r] = b.rounds[r].clone();
+            }
+        }
+
+        Some(child)
+    }
+}
+
+impl CipherFactory for ARXCipher {
+    const NAME: &'static str = "arx";
+    const DESCRIPTION: &'static str = "Add, rotate and xor rounds on each unit. Only a test bench, since it's equivalent to a substitution";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(round_count: 1..=8, rounds: [(add: constraint?, rot: constraint?, xor: constraint?)] = []), or just the round count. Constraints are Fixed(value), Range(min, max) or Set([value, ...])");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(ARXCipher::new(config)?))
+    }
 }
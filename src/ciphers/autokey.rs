@@ -0,0 +1,346 @@
+use std::{cell::RefCell, error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+use smallvec::SmallVec;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_min_max, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::MixedRadixKeySpace, vigenere::{crossover_shift_keys, format_shift_key, make_shift_key_segments, mutate_shift_key, parse_shift_key, validate_shift_key}}, data::message::{InterleavedMessageData, MessageDataList}, utils::{run::AnyErrorResult, stackvec::StackVec}};
+
+const MAX_PRIMER_LEN: usize = 32;
+
+/**
+ * Where the keystream comes from after the primer runs out. Units are
+ * ciphertext (c), plaintext (p) and primer length (L). Shifts are applied like
+ * a Vigenere cipher (c = p + k)
+ * - Plaintext: k[i] = p[i - L]
+ * - Ciphertext: k[i] = c[i - L]
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+pub enum AutokeyMode {
+    #[default]
+    Plaintext,
+    Ciphertext,
+}
+
+impl AutokeyMode {
+    pub const ALL: [AutokeyMode; 2] = [AutokeyMode::Plaintext, AutokeyMode::Ciphertext];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Plaintext => "plaintext-autokey",
+            Self::Ciphertext => "ciphertext-autokey",
+        }
+    }
+
+    /**
+     * Whether the keystream depends on the output of the codec, in which case
+     * units can't be computed without computing all previous units first
+     */
+    const fn is_keystream_from_output(&self, decrypt: bool) -> bool {
+        match self {
+            Self::Plaintext => decrypt,
+            Self::Ciphertext => !decrypt,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AutokeyConfig {
+    /// Size of the alphabet. Units greater or equal to this are left as-is
+    modulus: u16,
+    #[serde(default = "default_min_primer_length")]
+    min_primer_length: usize,
+    max_primer_length: usize,
+    #[serde(default)]
+    mode: AutokeyMode,
+}
+
+fn default_min_primer_length() -> usize { 1 }
+
+impl CipherConfig for AutokeyConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("modulus", self.modulus, 1..=256)?;
+        check_range("min_primer_length", self.min_primer_length, 1..=MAX_PRIMER_LEN)?;
+        check_range("max_primer_length", self.max_primer_length, 1..=MAX_PRIMER_LEN)?;
+        check_min_max("min_primer_length", self.min_primer_length, "max_primer_length", self.max_primer_length)
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedAutokeyKey {
+    /// Index in AutokeyMode::ALL
+    #[prost(uint32, tag = "1")]
+    pub mode: u32,
+    #[prost(uint32, tag = "2")]
+    pub modulus: u32,
+    #[prost(bytes = "vec", tag = "3")]
+    pub primer: Vec<u8>,
+}
+
+/**
+ * The mode and modulus are part of the key, since codec contexts only have
+ * access to the key
+ */
+#[derive(Clone)]
+pub struct AutokeyKey {
+    pub mode: AutokeyMode,
+    /** range: 1-256 */
+    pub modulus: u16,
+    /** shifts used before the keystream starts feeding itself, each in the range 0..modulus */
+    pub primer: StackVec<u8, MAX_PRIMER_LEN>,
+}
+
+impl ToString for AutokeyKey {
+    fn to_string(&self) -> String {
+        format_shift_key(self.mode.get_name(), self.modulus, self.primer.iter())
+    }
+}
+
+impl FromStr for AutokeyKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode_name, modulus, primer) = parse_shift_key(s)?;
+        let mode = *AutokeyMode::ALL.iter().find(|mode| mode.get_name() == mode_name).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: format!("Unknown mode \"{mode_name}\"").into() }
+        })?;
+
+        Ok(AutokeyKey { mode, modulus, primer })
+    }
+}
+
+impl CipherKey for AutokeyKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedAutokeyKey {
+            mode: AutokeyMode::ALL.iter().position(|mode| *mode == self.mode).unwrap() as u32,
+            modulus: self.modulus as u32,
+            primer: self.primer.iter().copied().collect(),
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedAutokeyKey::decode(buffer.iter().as_slice())?;
+        if enc_key.primer.len() > MAX_PRIMER_LEN {
+            return Err("Max primer length exceeded".into());
+        }
+
+        let mut key = AutokeyKey {
+            mode: *AutokeyMode::ALL.get(enc_key.mode as usize).ok_or("Unknown mode")?,
+            modulus: enc_key.modulus.try_into()?,
+            primer: StackVec::new(),
+        };
+
+        for shift in enc_key.primer {
+            key.primer.push(shift);
+        }
+
+        validate_shift_key(key.modulus, &key.primer)?;
+        Ok(key)
+    }
+}
+
+pub struct AutokeyCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec AutokeyKey,
+    input_messages: &'codec InterleavedMessageData,
+    /**
+     * Prefix of each output message computed so far. Only used if the
+     * keystream comes from the output, since computing a unit needs all
+     * previous units. Grows lazily, up to the furthest unit requested
+     */
+    output_cache: RefCell<MessageDataList>,
+}
+
+impl<'codec, const DECRYPT: bool> AutokeyCodecContext<'codec, DECRYPT> {
+    #[inline(always)]
+    fn apply_shift(&self, unit: u8, shift: u8) -> u8 {
+        let modulus = self.key.modulus;
+        let unit = unit as u16;
+        if unit >= modulus { return unit as u8 }
+
+        // both operands are less than the modulus, so adding the modulus
+        // before subtracting never underflows
+        let output = if DECRYPT {
+            unit + modulus - shift as u16
+        } else {
+            unit + shift as u16
+        };
+
+        (output % modulus) as u8
+    }
+
+    /**
+     * Shift for the unit at unit_index, given the unit that feeds the
+     * keystream (source_unit). source_unit is ignored if the unit is still
+     * within the primer. Units left as-is because they are outside the
+     * alphabet feed a shift of 0
+     */
+    #[inline(always)]
+    fn get_shift(&self, unit_index: usize, source_unit: u8) -> u8 {
+        let primer_len = self.key.primer.len();
+        if unit_index < primer_len {
+            // SAFETY: just checked that unit_index is in bounds
+            unsafe { *self.key.primer.get_unchecked(unit_index) }
+        } else if (source_unit as u16) < self.key.modulus {
+            source_unit
+        } else {
+            0
+        }
+    }
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, AutokeyKey> for AutokeyCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec AutokeyKey) -> Self {
+        let mut output_cache = MessageDataList::new();
+        if key.mode.is_keystream_from_output(DECRYPT) {
+            output_cache.resize_with(input_messages.get_message_count(), SmallVec::new);
+        }
+
+        AutokeyCodecContext { input_messages, key, output_cache: RefCell::new(output_cache) }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        let primer_len = self.key.primer.len();
+
+        if !self.key.mode.is_keystream_from_output(DECRYPT) {
+            // keystream comes from the input, so any unit can be computed
+            // directly
+            // SAFETY: bounds must be verified by caller
+            let unit = unsafe { *self.input_messages.get_unchecked(message_index, unit_index) };
+            let source_unit = if unit_index < primer_len {
+                0
+            } else {
+                // SAFETY: unit_index - primer_len is less than unit_index,
+                //         which is in bounds
+                unsafe { *self.input_messages.get_unchecked(message_index, unit_index - primer_len) }
+            };
+
+            return self.apply_shift(unit, self.get_shift(unit_index, source_unit));
+        }
+
+        let mut output_cache = self.output_cache.borrow_mut();
+        // SAFETY: the cache has one entry per input message, and message_index
+        //         bounds must be verified by caller
+        let outputs = unsafe { output_cache.get_unchecked_mut(message_index) };
+
+        for i in outputs.len()..=unit_index {
+            // SAFETY: i is at most unit_index, which must be in bounds
+            let unit = unsafe { *self.input_messages.get_unchecked(message_index, i) };
+            let source_unit = if i < primer_len { 0 } else { outputs[i - primer_len] };
+            let output = self.apply_shift(unit, self.get_shift(i, source_unit));
+            outputs.push(output);
+        }
+
+        // SAFETY: the cache was just extended to include unit_index
+        unsafe { *outputs.get_unchecked(unit_index) }
+    }
+}
+
+pub struct AutokeyWorkletContext {
+    mode: AutokeyMode,
+    modulus: u16,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<AutokeyKey> for AutokeyWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = AutokeyCodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&AutokeyKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let mut key = AutokeyKey { mode: self.mode, modulus: self.modulus, primer: StackVec::new() };
+
+        // each segment of the key space is a primer length, and each digit a
+        // shift
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            if key.primer.len() != digits.len() {
+                key.primer.resize_with(digits.len(), || 0);
+            }
+
+            for (i, digit) in digits.iter().enumerate() {
+                key.primer[i] = *digit as u8;
+            }
+
+            key_callback(&key);
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<AutokeyKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let mut key = AutokeyKey { mode: self.mode, modulus: self.modulus, primer: StackVec::new() };
+        for digit in digits {
+            key.primer.push(digit as u8);
+        }
+
+        Some(key)
+    }
+}
+
+pub struct AutokeyCipher {
+    mode: AutokeyMode,
+    modulus: u16,
+    key_space: MixedRadixKeySpace,
+}
+
+impl AutokeyCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<AutokeyCipher> {
+        let config: AutokeyConfig = parse_config(config)?;
+
+        Ok(AutokeyCipher {
+            mode: config.mode,
+            modulus: config.modulus,
+            key_space: MixedRadixKeySpace::new(make_shift_key_segments(config.modulus, config.min_primer_length, config.max_primer_length)),
+        })
+    }
+}
+
+impl Cipher for AutokeyCipher {
+    type Key = AutokeyKey;
+    type Context = AutokeyWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        AutokeyWorkletContext {
+            mode: self.mode,
+            modulus: self.modulus,
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &AutokeyKey, rng: &mut R) -> Option<AutokeyKey> {
+        let mut key = key.clone();
+        mutate_shift_key(self.modulus, &mut key.primer, rng);
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &AutokeyKey, b: &AutokeyKey, rng: &mut R) -> Option<AutokeyKey> {
+        Some(AutokeyKey { mode: a.mode, modulus: a.modulus, primer: crossover_shift_keys(&a.primer, &b.primer, rng) })
+    }
+}
+
+impl CipherFactory for AutokeyCipher {
+    const NAME: &'static str = "autokey";
+    const DESCRIPTION: &'static str = "Vigenere-style shifts with a primer, extended with the plaintext or ciphertext";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(modulus: 1..=256, min_primer_length: 1..=32 = 1, max_primer_length: 1..=32, mode: Plaintext | Ciphertext = Plaintext)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(AutokeyCipher::new(config)?))
+    }
+}
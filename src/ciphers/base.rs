@@ -1,5 +1,7 @@
 use std::fmt::{self, Debug};
 use std::error::Error;
+use std::str::FromStr;
+use rand::Rng;
 use rug::Integer;
 use smallvec::SmallVec;
 
@@ -11,6 +13,7 @@ pub enum StandardCipherError {
     NotConfigurable,
     MissingConfiguration,
     BadConfiguration { msg: Box<str> },
+    BadKey { msg: Box<str> },
 }
 
 impl fmt::Display for StandardCipherError {
@@ -20,13 +23,16 @@ impl fmt::Display for StandardCipherError {
             StandardCipherError::NotConfigurable => write!(f, "This cipher is not configurable"),
             StandardCipherError::MissingConfiguration => write!(f, "This cipher needs configuration"),
             StandardCipherError::BadConfiguration { msg } => write!(f, "Bad cipher configuration: {}", msg),
+            StandardCipherError::BadKey { msg } => write!(f, "Bad cipher key: {}", msg),
         }
     }
 }
 
 impl Error for StandardCipherError {}
 
-pub trait CipherKey: Sized + ToString {
+/// NOTE: from_str must accept the output of to_string. It's fine if the parsed
+///       key isn't identical to the original, as long as it's equivalent
+pub trait CipherKey: Sized + Clone + ToString + FromStr<Err = Box<dyn Error>> {
     fn encode_to_buffer(&self) -> Box<[u8]>;
     fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>>;
 }
@@ -67,6 +73,24 @@ pub trait CipherCodecContext<'codec, const DECRYPT: bool, Key: CipherKey> {
         data
     }
 
+    /**
+     * All outputs, in the same layout as the input messages. Useful for
+     * feeding the output into another codec context
+     */
+    fn get_output_interleaved(&self) -> InterleavedMessageData {
+        let mut data = self.get_input_messages().clone();
+        for m in 0..data.get_message_count() {
+            // SAFETY: m is in bounds, since it's in 0..get_message_count()
+            for u in 0..unsafe { data.get_unit_count(m) } {
+                // SAFETY: m and u are in bounds, and the output messages have
+                //         the same layout as the input messages
+                unsafe { *data.get_unchecked_mut(m, u) = self.get_output_unchecked(m, u) };
+            }
+        }
+
+        data
+    }
+
     fn get_output_messages(&self) -> MessageDataList {
         let mut messages = MessageDataList::default();
         for m in 0..self.get_input_messages().get_message_count() {
@@ -82,10 +106,44 @@ pub trait CipherWorkletContext<Key: CipherKey>: Send {
 
     fn get_total_keys(&self) -> Integer;
     /**
-     * key_callback must be called for each key
-     * chunk_callback must be called at least every u32::MAX keys
+     * Number of times chunk_callback is called when permuting all keys, or None
+     * if it can't be known without permuting the keys. Must be cheap, since
+     * ciphers that permute other ciphers' keys (like chains) need it to
+     * resume from a chunk
+     */
+    fn get_total_chunks(&self) -> Option<Integer>;
+
+    /**
+     * Number of times key_callback is called when permuting all keys, which is
+     * less than get_total_keys for ciphers that skip some keys (for example,
+     * non-invertible matrices), or None if it can't be known without
+     * permuting the keys. Must be cheap, like get_total_chunks
      */
-    fn permute_keys_interruptible<KC: FnMut(&Key), CC: FnMut(u32) -> bool>(&self, key_callback: KC, chunk_callback: CC);
+    fn get_permuted_keys(&self) -> Option<Integer> {
+        Some(self.get_total_keys())
+    }
+
+    /**
+     * key_callback must be called for each key, always in the same order
+     * chunk_callback must be called at least every u32::MAX keys, and marks the
+     * end of a chunk. Chunks are the resumable positions of a worklet, so
+     * permutation must begin at the chunk with index start_chunk (0-based),
+     * skipping all keys of the previous chunks
+     */
+    fn permute_keys_interruptible_from<KC: FnMut(&Key), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, key_callback: KC, chunk_callback: CC);
+
+    /**
+     * Draws a uniformly random key from the keys permuted by this worklet, for
+     * sampling key spaces that are too big to be searched exhaustively. Returns
+     * None if the worklet has no keys, or if the drawn key is one that is
+     * skipped when permuting (for example, non-invertible matrices), so that
+     * skipped keys are counted in the same way as when permuting
+     */
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<Key>;
+
+    fn permute_keys_interruptible<KC: FnMut(&Key), CC: FnMut(u32) -> bool>(&self, key_callback: KC, chunk_callback: CC) {
+        self.permute_keys_interruptible_from(&Integer::new(), key_callback, chunk_callback);
+    }
 
     fn permute_keys<KC: FnMut(&Key)>(&self, key_callback: KC) {
         self.permute_keys_interruptible(key_callback, |_| { true });
@@ -93,10 +151,13 @@ pub trait CipherWorkletContext<Key: CipherKey>: Send {
 }
 
 /**
- * XXX: Don't forget to register your new cipher in the deserialise_cipher
- *      function when implementing this trait, otherwise the CLI tools won't
- *      know that the new cipher exists (unless this is exactly what you want
- *      for weird reasons)
+ * XXX: Don't forget to implement CipherFactory and register your new cipher in
+ *      the visit_basic_cipher_factories function when implementing this trait,
+ *      otherwise the CLI tools won't know that the new cipher exists (unless
+ *      this is exactly what you want for weird reasons), and list it in the
+ *      chain_stages macro call of the chain module, so it can be a stage
+ * NOTE: ciphers are passed between threads, so they must be Send + Sync to be
+ *       registered, which they usually are since they only hold configuration
  */
 pub trait Cipher {
     type Key: CipherKey;
@@ -109,7 +170,67 @@ pub trait Cipher {
         Ok(Self::Key::from_buffer(net_key)?.to_string().into_boxed_str())
     }
 
+    fn str_key_to_net_key(&self, str_key: &str) -> Result<Box<[u8]>, Box<dyn Error>> {
+        Ok(str_key.parse::<Self::Key>()?.encode_to_buffer())
+    }
+
+    fn net_key_to_output_messages(&self, net_key: &Box<[u8]>, input_messages: &InterleavedMessageData, decrypt: bool) -> Result<MessageDataList, Box<dyn Error>> {
+        let key = Self::Key::from_buffer(net_key)?;
+
+        Ok(if decrypt {
+            <Self::Context as CipherWorkletContext<Self::Key>>::CodecContext::<'_, true>::new(input_messages, &key).get_output_messages()
+        } else {
+            <Self::Context as CipherWorkletContext<Self::Key>>::CodecContext::<'_, false>::new(input_messages, &key).get_output_messages()
+        })
+    }
+
     fn create_worklet_context(&self) -> Self::Context {
         self.create_worklet_context_parallel(0, 1)
     }
-}
\ No newline at end of file
+
+    /**
+     * Hook for metaheuristic searches (see the metaheuristic module), which
+     * start from keys drawn with CipherWorkletContext::random_key. Returns a
+     * copy of the key with a small random change, which must still be a key
+     * that would be permuted by this cipher. Returns None if the cipher
+     * doesn't support metaheuristic searches, which is the default
+     */
+    fn mutate_key<R: Rng>(&self, _key: &Self::Key, _rng: &mut R) -> Option<Self::Key> {
+        None
+    }
+
+    /**
+     * Hook for genetic algorithms. Returns a key made of random parts of both
+     * parents, which must still be a key that would be permuted by this cipher.
+     * Returns None if the cipher doesn't support crossover, which is the
+     * default
+     */
+    fn crossover_keys<R: Rng>(&self, _a: &Self::Key, _b: &Self::Key, _rng: &mut R) -> Option<Self::Key> {
+        None
+    }
+}
+
+/**
+ * Type-erased subset of Cipher, for tools that only handle keys in their
+ * network or string forms, and don't need to permute keys. Implemented for all
+ * ciphers
+ */
+pub trait DynCipher {
+    fn net_key_to_boxed_str(&self, net_key: &Box<[u8]>) -> Result<Box<str>, Box<dyn Error>>;
+    fn str_key_to_net_key(&self, str_key: &str) -> Result<Box<[u8]>, Box<dyn Error>>;
+    fn net_key_to_output_messages(&self, net_key: &Box<[u8]>, input_messages: &InterleavedMessageData, decrypt: bool) -> Result<MessageDataList, Box<dyn Error>>;
+}
+
+impl<C: Cipher> DynCipher for C {
+    fn net_key_to_boxed_str(&self, net_key: &Box<[u8]>) -> Result<Box<str>, Box<dyn Error>> {
+        Cipher::net_key_to_boxed_str(self, net_key)
+    }
+
+    fn str_key_to_net_key(&self, str_key: &str) -> Result<Box<[u8]>, Box<dyn Error>> {
+        Cipher::str_key_to_net_key(self, str_key)
+    }
+
+    fn net_key_to_output_messages(&self, net_key: &Box<[u8]>, input_messages: &InterleavedMessageData, decrypt: bool) -> Result<MessageDataList, Box<dyn Error>> {
+        Cipher::net_key_to_output_messages(self, net_key, input_messages, decrypt)
+    }
+}
@@ -0,0 +1,611 @@
+use std::{any::Any, cell::Cell, error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, affine::AffineCipher, arx::ARXCipher, autokey::AutokeyCipher, config::{CipherConfig, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, deserialise_basic_cipher, hill::HillCipher, substitution::SubstitutionCipher, transposition::TranspositionCipher, vigenere::VigenereCipher}, data::message::{InterleavedMessageData, MessageDataList}, utils::run::AnyErrorResult};
+
+/*
+ * Chains have any number of stages (at least 2), and each stage is a basic
+ * cipher; a stage can't be a chain itself, but its stages can be listed
+ * instead. The type of each stage is only known at runtime, so stages are
+ * wrapped in enums with a variant per basic cipher, instead of making chains
+ * generic over the types of their stages, which would need a separate
+ * monomorphised chain type (and search) for every combination of stages
+ */
+
+/**
+ * Declares the enums that wrap a stage, its keys and its worklet contexts, with
+ * a variant for each basic cipher that can be a stage
+ */
+macro_rules! chain_stages {
+    ($($variant:ident($cipher:ty)),* $(,)?) => {
+        enum ChainStage {
+            $($variant($cipher)),*
+        }
+
+        /// Key of a stage of a chain
+        #[derive(Clone)]
+        pub enum ChainStageKey {
+            $($variant(<$cipher as Cipher>::Key)),*
+        }
+
+        enum ChainStageWorklet {
+            $($variant(<$cipher as Cipher>::Context)),*
+        }
+
+        impl ChainStage {
+            /// Unwraps a deserialised basic cipher, or None if it's not a
+            /// cipher that can be a stage
+            fn from_any(cipher: Box<dyn Any>) -> Option<Self> {
+                $(
+                    let cipher = match cipher.downcast::<$cipher>() {
+                        Ok(cipher) => return Some(Self::$variant(*cipher)),
+                        Err(cipher) => cipher,
+                    };
+                )*
+
+                let _ = cipher;
+                None
+            }
+
+            fn get_max_parallelism(&self) -> u32 {
+                match self {
+                    $(Self::$variant(cipher) => cipher.get_max_parallelism()),*
+                }
+            }
+
+            fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> ChainStageWorklet {
+                match self {
+                    $(Self::$variant(cipher) => ChainStageWorklet::$variant(cipher.create_worklet_context_parallel(worklet_id, worklet_total))),*
+                }
+            }
+
+            fn is_stage_of(&self, key: &ChainStageKey) -> bool {
+                match (self, key) {
+                    $((Self::$variant(_), ChainStageKey::$variant(_)) => true,)*
+                    #[allow(unreachable_patterns)]
+                    _ => false,
+                }
+            }
+
+            fn mutate_key<R: Rng>(&self, key: &ChainStageKey, rng: &mut R) -> Option<ChainStageKey> {
+                match (self, key) {
+                    $((Self::$variant(cipher), ChainStageKey::$variant(key)) => cipher.mutate_key(key, rng).map(ChainStageKey::$variant),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+
+            fn crossover_keys<R: Rng>(&self, a: &ChainStageKey, b: &ChainStageKey, rng: &mut R) -> Option<ChainStageKey> {
+                match (self, a, b) {
+                    $((Self::$variant(cipher), ChainStageKey::$variant(a), ChainStageKey::$variant(b)) => cipher.crossover_keys(a, b, rng).map(ChainStageKey::$variant),)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+        }
+
+        impl ChainStageKey {
+            pub fn get_cipher_name(&self) -> &'static str {
+                match self {
+                    $(Self::$variant(_) => <$cipher as CipherFactory>::NAME),*
+                }
+            }
+
+            fn from_str_with_cipher(cipher_name: &str, s: &str) -> Result<Self, Box<dyn Error>> {
+                $(if cipher_name == <$cipher as CipherFactory>::NAME { return Ok(Self::$variant(s.parse()?)) })*
+                Err(StandardCipherError::BadKey { msg: format!("Unknown stage cipher \"{cipher_name}\"").into() }.into())
+            }
+
+            fn from_buffer_with_cipher(cipher_name: &str, buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+                $(if cipher_name == <$cipher as CipherFactory>::NAME { return Ok(Self::$variant(<$cipher as Cipher>::Key::from_buffer(buffer)?)) })*
+                Err(StandardCipherError::BadKey { msg: format!("Unknown stage cipher \"{cipher_name}\"").into() }.into())
+            }
+
+            fn to_key_string(&self) -> String {
+                match self {
+                    $(Self::$variant(key) => key.to_string()),*
+                }
+            }
+
+            fn encode_key_to_buffer(&self) -> Box<[u8]> {
+                match self {
+                    $(Self::$variant(key) => key.encode_to_buffer()),*
+                }
+            }
+
+            /// Output of the stage for all units, in the same layout as the
+            /// input messages
+            fn get_output_interleaved<const DECRYPT: bool>(&self, input_messages: &InterleavedMessageData) -> InterleavedMessageData {
+                match self {
+                    $(Self::$variant(key) => <<$cipher as Cipher>::Context as CipherWorkletContext<<$cipher as Cipher>::Key>>::CodecContext::<'_, DECRYPT>::new(input_messages, key).get_output_interleaved()),*
+                }
+            }
+        }
+
+        impl ChainStageWorklet {
+            fn get_total_keys(&self) -> Integer {
+                match self {
+                    $(Self::$variant(ctx) => ctx.get_total_keys()),*
+                }
+            }
+
+            fn get_total_chunks(&self) -> Option<Integer> {
+                match self {
+                    $(Self::$variant(ctx) => ctx.get_total_chunks()),*
+                }
+            }
+
+            fn get_permuted_keys(&self) -> Option<Integer> {
+                match self {
+                    $(Self::$variant(ctx) => ctx.get_permuted_keys()),*
+                }
+            }
+
+            fn permute_keys_interruptible_from(&self, start_chunk: &Integer, key_callback: &mut dyn FnMut(ChainStageKey), chunk_callback: &mut dyn FnMut(u32) -> bool) {
+                match self {
+                    $(Self::$variant(ctx) => ctx.permute_keys_interruptible_from(start_chunk, |key| key_callback(ChainStageKey::$variant(key.clone())), chunk_callback)),*
+                }
+            }
+
+            fn random_key<R: Rng>(&self, rng: &mut R) -> Option<ChainStageKey> {
+                match self {
+                    $(Self::$variant(ctx) => ctx.random_key(rng).map(ChainStageKey::$variant)),*
+                }
+            }
+        }
+    };
+}
+
+// XXX: new basic ciphers must be added here too, otherwise they can't be stages
+chain_stages! {
+    Affine(AffineCipher),
+    ARX(ARXCipher),
+    Autokey(AutokeyCipher),
+    Hill(HillCipher),
+    Substitution(SubstitutionCipher),
+    Transposition(TranspositionCipher),
+    Vigenere(VigenereCipher),
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainStageConfig {
+    /// Name of the cipher, like in the cipher name argument of the binaries
+    cipher: String,
+    /// Configuration of the cipher, as a string, like in the cipher
+    /// configuration argument of the binaries
+    #[serde(default)]
+    config: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChainConfig {
+    /// Stages in encryption order. Decryption goes through the stages in
+    /// reverse
+    stages: Vec<ChainStageConfig>,
+}
+
+impl CipherConfig for ChainConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        if self.stages.len() < 2 {
+            Err(StandardCipherError::BadConfiguration { msg: format!("stages: expected at least 2 stages, got {}", self.stages.len()).into() })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/**
+ * Prefixes an error of a stage with the path of the stage, so that errors in
+ * nested configurations are reported like errors in the chain's configuration
+ */
+fn stage_error(stage_index: usize, err: Box<dyn Error>) -> Box<dyn Error> {
+    match err.downcast::<StandardCipherError>() {
+        Ok(err) => match *err {
+            StandardCipherError::BadConfiguration { msg } => StandardCipherError::BadConfiguration { msg: format!("stages[{stage_index}].config: {msg}").into() },
+            StandardCipherError::UnknownCipher => StandardCipherError::BadConfiguration { msg: format!("stages[{stage_index}].cipher: unknown cipher (chains can't be stages, but their stages can be listed instead)").into() },
+            err => StandardCipherError::BadConfiguration { msg: format!("stages[{stage_index}]: {err}").into() },
+        }.into(),
+        Err(err) => err,
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedChainStageKey {
+    #[prost(string, tag = "1")]
+    pub cipher: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub key: Vec<u8>,
+}
+
+#[derive(prost::Message)]
+struct EncodedChainKey {
+    #[prost(message, repeated, tag = "1")]
+    pub stages: Vec<EncodedChainStageKey>,
+}
+
+/**
+ * A key for each stage of the chain, in encryption order. The string form is
+ * the cipher name and key of each stage, wrapped in square brackets, for
+ * example "[transposition:[columnar: 2,0,1] affine:[5x+8 mod 26]]"
+ */
+#[derive(Clone)]
+pub struct ChainKey {
+    pub stages: Vec<ChainStageKey>,
+}
+
+impl ToString for ChainKey {
+    fn to_string(&self) -> String {
+        let stages: Vec<String> = self.stages.iter().map(|key| format!("{}:{}", key.get_cipher_name(), key.to_key_string())).collect();
+        format!("[{}]", stages.join(" "))
+    }
+}
+
+impl FromStr for ChainKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_format = || StandardCipherError::BadKey { msg: "Expected a key in the format \"[cipher:[key] cipher:[key] ...]\"".into() };
+        let mut inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(bad_format)?.trim();
+
+        let mut stages = Vec::new();
+        while !inner.is_empty() {
+            let (cipher_name, rest) = inner.split_once(':').ok_or_else(bad_format)?;
+            let rest = rest.trim_start();
+
+            // stage keys are wrapped in square brackets too, so each key ends
+            // when its opening bracket is closed
+            if !rest.starts_with('[') { return Err(bad_format().into()) }
+            let mut depth = 0usize;
+            let mut key_end = None;
+            for (i, c) in rest.char_indices() {
+                match c {
+                    '[' => depth += 1,
+                    ']' => {
+                        depth = depth.checked_sub(1).ok_or_else(bad_format)?;
+                        if depth == 0 {
+                            key_end = Some(i + 1);
+                            break;
+                        }
+                    },
+                    _ => {},
+                }
+            }
+
+            let (key, rest) = rest.split_at(key_end.ok_or_else(bad_format)?);
+            stages.push(ChainStageKey::from_str_with_cipher(cipher_name.trim(), key)?);
+            inner = rest.trim_start();
+        }
+
+        if stages.is_empty() { return Err(bad_format().into()) }
+        Ok(ChainKey { stages })
+    }
+}
+
+impl CipherKey for ChainKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedChainKey {
+            stages: self.stages.iter().map(|key| EncodedChainStageKey {
+                cipher: key.get_cipher_name().into(),
+                key: key.encode_key_to_buffer().into(),
+            }).collect(),
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedChainKey::decode(buffer.iter().as_slice())?;
+        let mut stages = Vec::with_capacity(enc_key.stages.len());
+        for enc_stage in enc_key.stages {
+            stages.push(ChainStageKey::from_buffer_with_cipher(&enc_stage.cipher, &enc_stage.key.into_boxed_slice())?);
+        }
+
+        Ok(ChainKey { stages })
+    }
+}
+
+/**
+ * The output of each stage is the input of the next stage, and a codec context
+ * can't own the input of a codec context it also owns, so the output is
+ * computed for all units when the context is created instead of on demand
+ */
+pub struct ChainCodecContext<'codec, const DECRYPT: bool> {
+    input_messages: &'codec InterleavedMessageData,
+    output_messages: InterleavedMessageData,
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, ChainKey> for ChainCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec ChainKey) -> Self {
+        let stage_count = key.stages.len();
+        let mut output_messages: Option<InterleavedMessageData> = None;
+        for s in 0..stage_count {
+            let stage_key = if DECRYPT { &key.stages[stage_count - 1 - s] } else { &key.stages[s] };
+            output_messages = Some(stage_key.get_output_interleaved::<DECRYPT>(output_messages.as_ref().unwrap_or(input_messages)));
+        }
+
+        let output_messages = output_messages.unwrap_or_else(|| input_messages.clone());
+        ChainCodecContext { input_messages, output_messages }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        // SAFETY: bounds must be verified by caller. the output has the same
+        //         layout as the input
+        unsafe { *self.output_messages.get_unchecked(message_index, unit_index) }
+    }
+}
+
+pub struct ChainWorkletContext {
+    /** only the keys of the first stage are split between worklets */
+    stages: Vec<ChainStageWorklet>,
+}
+
+impl ChainWorkletContext {
+    /**
+     * Permutes the keys of stage s, and for each of them, the keys of the
+     * following stages. key must have the keys of the previous stages. The
+     * first permutation of each stage starts at start_positions[s], which is
+     * a chunk for the last stage, and a number of permuted keys to skip for
+     * the other stages. Returns false if interrupted by chunk_callback
+     */
+    fn permute_stage(&self, s: usize, start_positions: &mut [Integer], key: &mut ChainKey, key_callback: &mut dyn FnMut(&ChainKey), chunk_callback: &mut dyn FnMut(u32) -> bool) -> bool {
+        let stage = &self.stages[s];
+
+        if s == self.stages.len() - 1 {
+            let mut keep_going = true;
+            stage.permute_keys_interruptible_from(&std::mem::take(&mut start_positions[s]), &mut |stage_key| {
+                key.stages.truncate(s);
+                key.stages.push(stage_key);
+                key_callback(key);
+            }, &mut |keys| {
+                keep_going = chunk_callback(keys);
+                keep_going
+            });
+
+            keep_going
+        } else {
+            let mut skipped_keys = std::mem::take(&mut start_positions[s]);
+            let stopped = Cell::new(false);
+
+            stage.permute_keys_interruptible_from(&Integer::new(), &mut |stage_key| {
+                if stopped.get() { return }
+                if skipped_keys > 0 {
+                    skipped_keys -= 1;
+                    return;
+                }
+
+                key.stages.truncate(s);
+                key.stages.push(stage_key);
+                if !self.permute_stage(s + 1, start_positions, key, key_callback, chunk_callback) {
+                    stopped.set(true);
+                }
+            }, &mut |_| !stopped.get());
+
+            !stopped.get()
+        }
+    }
+
+    /// Keys permuted by each stage after the first, which are never split
+    /// between worklets, so they always know this (see ChainCipher::new)
+    fn get_stage_permuted_keys(&self, s: usize) -> Integer {
+        self.stages[s].get_permuted_keys().expect("expected chain stage to know its permuted key count")
+    }
+
+    fn get_last_stage_chunks(&self) -> Integer {
+        self.stages[self.stages.len() - 1].get_total_chunks().expect("expected chain stage to know its chunk count")
+    }
+}
+
+impl CipherWorkletContext<ChainKey> for ChainWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = ChainCodecContext<'codec, DECRYPT>;
+
+    /**
+     * Keys skipped by the first stage are never combined with the keys of the
+     * other stages, so they're left out if the first stage knows how many keys
+     * it skips. Otherwise (for example, for a slice of the matrices of a Hill
+     * cipher), the total includes them, and the search's progress never
+     * reaches 100%
+     */
+    fn get_total_keys(&self) -> Integer {
+        let stage_count = self.stages.len();
+        let first = &self.stages[0];
+        let mut total = first.get_permuted_keys().unwrap_or_else(|| first.get_total_keys());
+        for s in 1..stage_count - 1 {
+            total *= self.get_stage_permuted_keys(s);
+        }
+
+        total * self.stages[stage_count - 1].get_total_keys()
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        let mut total = self.stages[0].get_permuted_keys()?;
+        for s in 1..self.stages.len() - 1 {
+            total *= self.get_stage_permuted_keys(s);
+        }
+
+        Some(total * self.get_last_stage_chunks())
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&ChainKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, mut chunk_callback: CC) {
+        // the chunks of the chain are the chunks of the last stage, for each
+        // combination of keys of the other stages, so the start chunk is a
+        // mixed-radix number with a digit for each stage
+        let stage_count = self.stages.len();
+        let mut start_positions = vec![Integer::new(); stage_count];
+        if *start_chunk != 0 {
+            let last_chunks = self.get_last_stage_chunks();
+            if last_chunks == 0 { return }
+            let (mut rest, last_start_chunk) = start_chunk.clone().div_rem_floor(last_chunks);
+            start_positions[stage_count - 1] = last_start_chunk;
+
+            for s in (1..stage_count - 1).rev() {
+                let permuted_keys = self.get_stage_permuted_keys(s);
+                if permuted_keys == 0 { return }
+                let (div, rem) = rest.div_rem_floor(permuted_keys);
+                start_positions[s] = rem;
+                rest = div;
+            }
+
+            start_positions[0] = rest;
+        }
+
+        let mut key = ChainKey { stages: Vec::with_capacity(stage_count) };
+        self.permute_stage(0, &mut start_positions, &mut key, &mut key_callback, &mut chunk_callback);
+    }
+
+    /**
+     * Stages that get_total_keys counts without their skipped keys draw again
+     * until they get a key, so sampled matches scale by the same total. The
+     * last stage, and a first stage that doesn't know how many keys it skips,
+     * draw once, like their total does
+     */
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<ChainKey> {
+        let stage_count = self.stages.len();
+        let mut stages = Vec::with_capacity(stage_count);
+        for (s, stage) in self.stages.iter().enumerate() {
+            let stage_key = match stage.get_permuted_keys() {
+                Some(permuted_keys) if s < stage_count - 1 => {
+                    if permuted_keys == 0 { return None }
+                    loop {
+                        if let Some(stage_key) = stage.random_key(rng) { break stage_key }
+                    }
+                },
+                _ => stage.random_key(rng)?,
+            };
+
+            stages.push(stage_key);
+        }
+
+        Some(ChainKey { stages })
+    }
+}
+
+struct StageVisitor;
+
+impl CipherVisitor for StageVisitor {
+    type Output = Box<dyn Any>;
+
+    fn visit<C: Cipher + Send + Sync + 'static>(self, cipher: C) -> Self::Output {
+        Box::new(cipher)
+    }
+}
+
+/**
+ * Encrypts with each stage in turn, feeding the output of each stage into the
+ * next one. Each key of the chain is a list of keys, one for each stage, so the
+ * key space is the product of the key spaces of the stages
+ */
+pub struct ChainCipher {
+    stages: Vec<ChainStage>,
+}
+
+impl ChainCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<ChainCipher> {
+        let config: ChainConfig = parse_config(config)?;
+
+        let mut stages = Vec::with_capacity(config.stages.len());
+        for (s, stage_config) in config.stages.iter().enumerate() {
+            let cipher = deserialise_basic_cipher(&stage_config.cipher, stage_config.config.as_deref(), StageVisitor).map_err(|err| stage_error(s, err))?;
+            let stage = ChainStage::from_any(cipher).ok_or_else(|| {
+                StandardCipherError::BadConfiguration { msg: format!("stages[{s}].cipher: this cipher can't be a stage").into() }
+            })?;
+
+            // resuming needs these counts, which stages after the first know,
+            // since their keys are never split between worklets
+            let worklet_ctx = stage.create_worklet_context_parallel(0, 1);
+            if s > 0 && (worklet_ctx.get_permuted_keys().is_none() || worklet_ctx.get_total_chunks().is_none()) {
+                return Err(StandardCipherError::BadConfiguration { msg: format!("stages[{s}].cipher: this cipher can only be the first stage").into() }.into());
+            }
+
+            stages.push(stage);
+        }
+
+        Ok(ChainCipher { stages })
+    }
+
+    /// Checks that a key has a key for each stage, of the right cipher
+    fn check_key(&self, key: &ChainKey) -> Result<(), Box<dyn Error>> {
+        if key.stages.len() != self.stages.len() || !self.stages.iter().zip(&key.stages).all(|(stage, stage_key)| stage.is_stage_of(stage_key)) {
+            let key_stage_names: Vec<&str> = key.stages.iter().map(|stage_key| stage_key.get_cipher_name()).collect();
+            return Err(StandardCipherError::BadKey { msg: format!("Key stages ({}) don't match the stages of the chain", key_stage_names.join(", ")).into() }.into());
+        }
+
+        Ok(())
+    }
+}
+
+impl Cipher for ChainCipher {
+    type Key = ChainKey;
+    type Context = ChainWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.stages[0].get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        ChainWorkletContext {
+            stages: self.stages.iter().enumerate().map(|(s, stage)| {
+                if s == 0 {
+                    stage.create_worklet_context_parallel(worklet_id, worklet_total)
+                } else {
+                    stage.create_worklet_context_parallel(0, 1)
+                }
+            }).collect(),
+        }
+    }
+
+    fn str_key_to_net_key(&self, str_key: &str) -> Result<Box<[u8]>, Box<dyn Error>> {
+        let key: ChainKey = str_key.parse()?;
+        self.check_key(&key)?;
+        Ok(key.encode_to_buffer())
+    }
+
+    fn net_key_to_output_messages(&self, net_key: &Box<[u8]>, input_messages: &InterleavedMessageData, decrypt: bool) -> Result<MessageDataList, Box<dyn Error>> {
+        let key = ChainKey::from_buffer(net_key)?;
+        self.check_key(&key)?;
+
+        Ok(if decrypt {
+            ChainCodecContext::<'_, true>::new(input_messages, &key).get_output_messages()
+        } else {
+            ChainCodecContext::<'_, false>::new(input_messages, &key).get_output_messages()
+        })
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &Self::Key, rng: &mut R) -> Option<Self::Key> {
+        // only one stage is changed. Every basic cipher that can be a stage
+        // supports mutation, so this only fails when that stage's does
+        if key.stages.len() != self.stages.len() { return None }
+        let s = rng.random_range(0..self.stages.len());
+        let mut mutated = key.clone();
+        mutated.stages[s] = self.stages[s].mutate_key(&key.stages[s], rng)?;
+        Some(mutated)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &Self::Key, b: &Self::Key, rng: &mut R) -> Option<Self::Key> {
+        if a.stages.len() != self.stages.len() || b.stages.len() != self.stages.len() { return None }
+
+        let mut stages = Vec::with_capacity(self.stages.len());
+        for ((stage, a), b) in self.stages.iter().zip(&a.stages).zip(&b.stages) {
+            stages.push(stage.crossover_keys(a, b, rng)?);
+        }
+
+        Some(ChainKey { stages })
+    }
+}
+
+impl CipherFactory for ChainCipher {
+    const NAME: &'static str = "chain";
+    const DESCRIPTION: &'static str = "Encrypts with each of a list of ciphers in turn. Keys are lists of keys of each cipher";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(stages: [(cipher: string, config: string?)]), with at least 2 stages, which can't be chains");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(ChainCipher::new(config)?))
+    }
+}
@@ -0,0 +1,65 @@
+use std::{fmt::Display, ops::RangeInclusive};
+
+use serde::de::DeserializeOwned;
+
+use crate::ciphers::base::StandardCipherError;
+
+/**
+ * A typed cipher configuration, parsed from RON with parse_config
+ */
+pub trait CipherConfig: DeserializeOwned {
+    /**
+     * Checks the values that can't be checked by their types alone, like
+     * ranges. Errors should start with the path of the bad field, in the same
+     * format as parsing errors, for example "rounds[2].xor"
+     */
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        Ok(())
+    }
+}
+
+fn bad_configuration(msg: String) -> StandardCipherError {
+    StandardCipherError::BadConfiguration { msg: msg.into() }
+}
+
+/**
+ * Parses and validates a RON cipher configuration. Optional fields don't need
+ * to be wrapped in Some. Errors include the path of the bad field and where it
+ * is in the configuration string
+ */
+pub fn parse_config<T: CipherConfig>(config: Option<&str>) -> Result<T, StandardCipherError> {
+    let Some(config) = config else { return Err(StandardCipherError::MissingConfiguration) };
+
+    let options = ron::Options::default().with_default_extension(ron::extensions::Extensions::IMPLICIT_SOME);
+    let mut deserializer = ron::Deserializer::from_str_with_options(config, &options).map_err(|err| bad_configuration(err.to_string()))?;
+
+    let parsed: T = match serde_path_to_error::deserialize(&mut deserializer) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            // the root path is shown as ".", which isn't useful
+            let path = err.path().to_string();
+            let err = deserializer.span_error(err.into_inner());
+            return Err(bad_configuration(if path == "." { err.to_string() } else { format!("{path}: {err}") }));
+        },
+    };
+
+    deserializer.end().map_err(|err| bad_configuration(deserializer.span_error(err).to_string()))?;
+    parsed.validate()?;
+    Ok(parsed)
+}
+
+pub fn check_range<T: PartialOrd + Display>(path: &str, value: T, range: RangeInclusive<T>) -> Result<(), StandardCipherError> {
+    if range.contains(&value) {
+        Ok(())
+    } else {
+        Err(bad_configuration(format!("{path}: expected a value in the range {}..={}, got {value}", range.start(), range.end())))
+    }
+}
+
+pub fn check_min_max<T: PartialOrd + Display>(min_path: &str, min: T, max_path: &str, max: T) -> Result<(), StandardCipherError> {
+    if min <= max {
+        Ok(())
+    } else {
+        Err(bad_configuration(format!("{min_path}: must not be greater than {max_path} ({max}), got {min}")))
+    }
+}
@@ -0,0 +1,423 @@
+use std::{error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::{Integer, ops::Pow};
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_range, parse_config}, affine::{invert_mod, validate_modulus}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::MixedRadixKeySpace}, data::message::InterleavedMessageData, utils::run::AnyErrorResult};
+
+const MAX_BLOCK_SIZE: usize = 4;
+/// Random changes tried when mutating or crossing over keys, until one gives
+/// an invertible matrix. If none do, the first parent is returned unchanged
+const MAX_INVERTIBLE_ATTEMPTS: usize = 64;
+
+/// Square matrix of up to MAX_BLOCK_SIZE rows. Only the top-left block_size
+/// rows and columns are used
+type Matrix = [[u16; MAX_BLOCK_SIZE]; MAX_BLOCK_SIZE];
+
+fn get_minor(matrix: &Matrix, size: usize, skip_row: usize, skip_column: usize) -> Matrix {
+    let mut minor = Matrix::default();
+    for (mr, r) in (0..size).filter(|r| *r != skip_row).enumerate() {
+        for (mc, c) in (0..size).filter(|c| *c != skip_column).enumerate() {
+            minor[mr][mc] = matrix[r][c];
+        }
+    }
+
+    minor
+}
+
+/**
+ * Determinant by cofactor expansion. Not reduced by any modulus, but entries
+ * are at most 255, so it can't overflow for blocks of up to 4 units
+ */
+fn get_determinant(matrix: &Matrix, size: usize) -> i64 {
+    if size == 1 { return matrix[0][0] as i64 }
+
+    let mut determinant = 0i64;
+    for c in 0..size {
+        let cofactor = get_determinant(&get_minor(matrix, size, 0, c), size - 1);
+        let sign = if c % 2 == 0 { 1 } else { -1 };
+        determinant += sign * matrix[0][c] as i64 * cofactor;
+    }
+
+    determinant
+}
+
+/**
+ * Number of size x size matrices that are invertible modulo modulus. For each
+ * prime power p^k of the modulus, there are p^((k-1)size^2) matrices for each
+ * invertible matrix modulo p, and (p^size - 1)(p^size - p)...(p^size - p^(size-1))
+ * of those
+ */
+fn count_invertible_matrices(size: usize, modulus: u16) -> Integer {
+    let mut total = Integer::from(1);
+    let mut m = modulus as u32;
+    let mut p = 2u32;
+
+    while m > 1 {
+        if p * p > m { p = m } // what's left is prime
+
+        let mut k = 0u32;
+        while m.is_multiple_of(p) {
+            m /= p;
+            k += 1;
+        }
+
+        if k > 0 {
+            let p_size = Integer::from(p).pow(size as u32);
+            total *= Integer::from(p).pow((k - 1) * (size * size) as u32);
+            for i in 0..size as u32 {
+                total *= &p_size - Integer::from(p).pow(i);
+            }
+        }
+
+        p += 1;
+    }
+
+    total
+}
+
+/**
+ * Inverse of a matrix modulo modulus, or None if the matrix is not invertible
+ * (its determinant is not coprime with the modulus)
+ */
+fn invert_matrix(matrix: &Matrix, size: usize, modulus: u16) -> Option<Matrix> {
+    let determinant_inverse = invert_mod(get_determinant(matrix, size), modulus)? as i64;
+    if size == 1 { return Some([[determinant_inverse as u16; MAX_BLOCK_SIZE]; MAX_BLOCK_SIZE]) }
+
+    // inverse = determinant^-1 * adjugate, where the adjugate is the transpose
+    // of the cofactor matrix
+    let mut inverse = Matrix::default();
+    for r in 0..size {
+        for c in 0..size {
+            let sign = if (r + c) % 2 == 0 { 1 } else { -1 };
+            let cofactor = sign * get_determinant(&get_minor(matrix, size, c, r), size - 1);
+            inverse[r][c] = (cofactor.rem_euclid(modulus as i64) * determinant_inverse).rem_euclid(modulus as i64) as u16;
+        }
+    }
+
+    Some(inverse)
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HillConfig {
+    /// Size of the alphabet. Blocks with units greater or equal to this are
+    /// left as-is
+    modulus: u16,
+    /// Units per block, which is also the amount of rows and columns of the
+    /// key matrix
+    block_size: usize,
+}
+
+impl CipherConfig for HillConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("modulus", self.modulus, 2..=256)?;
+        check_range("block_size", self.block_size, 1..=MAX_BLOCK_SIZE)
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedHillKey {
+    #[prost(uint32, tag = "1")]
+    pub modulus: u32,
+    #[prost(uint32, tag = "2")]
+    pub block_size: u32,
+    /// Matrix entries, row by row
+    #[prost(bytes = "vec", tag = "3")]
+    pub matrix: Vec<u8>,
+}
+
+/**
+ * Encrypts each block of units (as a column vector) by multiplying it with the
+ * key matrix, modulo the modulus. The last block of a message is left as-is if
+ * it's incomplete. The modulus is part of the key, since codec contexts only
+ * have access to the key
+ */
+#[derive(Clone)]
+pub struct HillKey {
+    /** range: 2-256 */
+    pub modulus: u16,
+    /** range: 1-4 */
+    pub block_size: usize,
+    /** must be invertible modulo the modulus */
+    pub matrix: Matrix,
+    /** inverse of the matrix, for decryption. Derived from the matrix, so it's not encoded */
+    inverse: Matrix,
+}
+
+impl HillKey {
+    pub fn new(modulus: u16, block_size: usize, matrix: Matrix) -> Result<Self, Box<dyn Error>> {
+        validate_modulus(modulus)?;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(StandardCipherError::BadKey { msg: format!("Block size must be in the range 1..={MAX_BLOCK_SIZE}").into() }.into());
+        } else if matrix.iter().flatten().any(|x| *x >= modulus) {
+            return Err(StandardCipherError::BadKey { msg: "Matrix entries must be less than the modulus".into() }.into());
+        }
+
+        let inverse = invert_matrix(&matrix, block_size, modulus).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: format!("Matrix is not invertible modulo {modulus}").into() }
+        })?;
+
+        Ok(HillKey { modulus, block_size, matrix, inverse })
+    }
+}
+
+impl ToString for HillKey {
+    fn to_string(&self) -> String {
+        let rows: Vec<String> = self.matrix[..self.block_size].iter().map(|row| {
+            let row: Vec<String> = row[..self.block_size].iter().map(|x| x.to_string()).collect();
+            row.join(",")
+        }).collect();
+
+        format!("[{} mod {}]", rows.join(";"), self.modulus)
+    }
+}
+
+impl FromStr for HillKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_format = || StandardCipherError::BadKey { msg: "Expected a key in the format \"[x,x;x,x mod modulus]\", with rows separated by semicolons".into() };
+        let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(bad_format)?;
+        let (matrix_str, modulus) = inner.split_once(" mod ").ok_or_else(bad_format)?;
+        let modulus = modulus.trim().parse::<u16>().or(Err(StandardCipherError::BadKey { msg: "Invalid modulus".into() }))?;
+
+        let rows: Vec<&str> = matrix_str.split(';').collect();
+        let block_size = rows.len();
+        if block_size > MAX_BLOCK_SIZE {
+            return Err(StandardCipherError::BadKey { msg: format!("Max block size ({MAX_BLOCK_SIZE}) exceeded").into() }.into());
+        }
+
+        let mut matrix = Matrix::default();
+        for (r, row) in rows.iter().enumerate() {
+            let entries: Vec<&str> = row.split(',').collect();
+            if entries.len() != block_size {
+                return Err(StandardCipherError::BadKey { msg: "Matrix must be square".into() }.into());
+            }
+
+            for (c, entry) in entries.iter().enumerate() {
+                matrix[r][c] = entry.trim().parse::<u16>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid matrix entry \"{}\"", entry.trim()).into() }))?;
+            }
+        }
+
+        HillKey::new(modulus, block_size, matrix)
+    }
+}
+
+impl CipherKey for HillKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        let mut matrix = Vec::new();
+        for row in self.matrix[..self.block_size].iter() {
+            // entries are less than the modulus, so they fit in a byte
+            matrix.extend(row[..self.block_size].iter().map(|x| *x as u8));
+        }
+
+        EncodedHillKey {
+            modulus: self.modulus as u32,
+            block_size: self.block_size as u32,
+            matrix,
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedHillKey::decode(buffer.iter().as_slice())?;
+        let block_size = enc_key.block_size as usize;
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE || enc_key.matrix.len() != block_size * block_size {
+            return Err("Bad block size or matrix length".into());
+        }
+
+        let mut matrix = Matrix::default();
+        for (i, x) in enc_key.matrix.iter().enumerate() {
+            matrix[i / block_size][i % block_size] = *x as u16;
+        }
+
+        HillKey::new(enc_key.modulus.try_into()?, block_size, matrix)
+    }
+}
+
+pub struct HillCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec HillKey,
+    input_messages: &'codec InterleavedMessageData,
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, HillKey> for HillCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec HillKey) -> Self {
+        HillCodecContext { input_messages, key }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        let block_size = self.key.block_size;
+        let modulus = self.key.modulus as u32;
+        let row = unit_index % block_size;
+        let block_start = unit_index - row;
+
+        // SAFETY: bounds must be verified by caller
+        let unit = unsafe { *self.input_messages.get_unchecked(message_index, unit_index) };
+
+        // the last block is left as-is if it's incomplete
+        // SAFETY: message_index bounds must be verified by caller
+        if block_start + block_size > unsafe { self.input_messages.get_unit_count(message_index) } {
+            return unit;
+        }
+
+        let matrix = if DECRYPT { &self.key.inverse } else { &self.key.matrix };
+        let mut output = 0u32;
+        for c in 0..block_size {
+            // SAFETY: the block is complete, so all of its units are in bounds
+            let block_unit = unsafe { *self.input_messages.get_unchecked(message_index, block_start + c) } as u32;
+            if block_unit >= modulus { return unit }
+            output += matrix[row][c] as u32 * block_unit;
+        }
+
+        (output % modulus) as u8
+    }
+}
+
+pub struct HillWorkletContext {
+    modulus: u16,
+    block_size: usize,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<HillKey> for HillWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = HillCodecContext<'codec, DECRYPT>;
+
+    /**
+     * Includes non-invertible matrices, which are skipped when permuting, but
+     * still count as checked keys in chunk sizes
+     */
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    /**
+     * Only known for the whole key space, since invertible matrices aren't
+     * evenly spread between key indices
+     */
+    fn get_permuted_keys(&self) -> Option<Integer> {
+        if self.range.0 == 0 && self.range.1 == self.key_space.get_total() {
+            Some(count_invertible_matrices(self.block_size, self.modulus))
+        } else {
+            None
+        }
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&HillKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let block_size = self.block_size;
+        let mut key = HillKey { modulus: self.modulus, block_size, matrix: Matrix::default(), inverse: Matrix::default() };
+
+        // each digit is a matrix entry, row by row
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            for (i, digit) in digits.iter().enumerate() {
+                key.matrix[i / block_size][i % block_size] = *digit as u16;
+            }
+
+            if let Some(inverse) = invert_matrix(&key.matrix, block_size, self.modulus) {
+                key.inverse = inverse;
+                key_callback(&key);
+            }
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<HillKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let mut matrix = Matrix::default();
+        for (i, digit) in digits.iter().enumerate() {
+            matrix[i / self.block_size][i % self.block_size] = *digit as u16;
+        }
+
+        let inverse = invert_matrix(&matrix, self.block_size, self.modulus)?;
+        Some(HillKey { modulus: self.modulus, block_size: self.block_size, matrix, inverse })
+    }
+}
+
+pub struct HillCipher {
+    modulus: u16,
+    block_size: usize,
+    key_space: MixedRadixKeySpace,
+}
+
+impl HillCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<HillCipher> {
+        let config: HillConfig = parse_config(config)?;
+
+        let radices = vec![config.modulus as u32; config.block_size * config.block_size];
+        Ok(HillCipher {
+            modulus: config.modulus,
+            block_size: config.block_size,
+            key_space: MixedRadixKeySpace::new(vec![radices.into_boxed_slice()]),
+        })
+    }
+}
+
+impl Cipher for HillCipher {
+    type Key = HillKey;
+    type Context = HillWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        HillWorkletContext {
+            modulus: self.modulus,
+            block_size: self.block_size,
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &HillKey, rng: &mut R) -> Option<HillKey> {
+        for _ in 0..MAX_INVERTIBLE_ATTEMPTS {
+            let mut matrix = key.matrix;
+            let (r, c) = (rng.random_range(0..self.block_size), rng.random_range(0..self.block_size));
+            // add a non-zero offset, so that the entry always changes
+            matrix[r][c] = (matrix[r][c] + rng.random_range(1..self.modulus)) % self.modulus;
+
+            if let Some(inverse) = invert_matrix(&matrix, self.block_size, self.modulus) {
+                return Some(HillKey { modulus: self.modulus, block_size: self.block_size, matrix, inverse });
+            }
+        }
+
+        Some(key.clone())
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &HillKey, b: &HillKey, rng: &mut R) -> Option<HillKey> {
+        // each row of the child is a row of either parent
+        for _ in 0..MAX_INVERTIBLE_ATTEMPTS {
+            let mut matrix = a.matrix;
+            for r in 0..self.block_size {
+                if rng.random_bool(0.5) {
+                    matrix[r] = b.matrix[r];
+                }
+            }
+
+            if let Some(inverse) = invert_matrix(&matrix, self.block_size, self.modulus) {
+                return Some(HillKey { modulus: self.modulus, block_size: self.block_size, matrix, inverse });
+            }
+        }
+
+        Some(a.clone())
+    }
+}
+
+impl CipherFactory for HillCipher {
+    const NAME: &'static str = "hill";
+    const DESCRIPTION: &'static str = "Hill cipher over blocks of units, skipping non-invertible matrices";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(modulus: 2..=256, block_size: 1..=4)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(HillCipher::new(config)?))
+    }
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, marker::PhantomData, sync::Arc};
+
+use rand::Rng;
+use rug::Integer;
+
+use crate::ciphers::{base::{Cipher, CipherKey, CipherWorkletContext}, key_space::KEYS_PER_CHUNK};
+
+pub struct KeyListWorkletContext<Key: CipherKey, Context: CipherWorkletContext<Key>> {
+    net_keys: Arc<Vec<Box<[u8]>>>,
+    from: usize,
+    to: usize,
+    _phantom: PhantomData<fn() -> (Key, Context)>,
+}
+
+impl<Key: CipherKey, Context: CipherWorkletContext<Key>> CipherWorkletContext<Key> for KeyListWorkletContext<Key, Context> {
+    type CodecContext<'codec, const DECRYPT: bool> = Context::CodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(self.to - self.from)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(Integer::from((self.to - self.from).div_ceil(KEYS_PER_CHUNK as usize)))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&Key), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, mut chunk_callback: CC) {
+        let from = match start_chunk.to_usize().and_then(|x| x.checked_mul(KEYS_PER_CHUNK as usize)) {
+            Some(offset) if offset < self.to - self.from => self.from + offset,
+            _ => return, // already finished
+        };
+
+        for chunk_from in (from..self.to).step_by(KEYS_PER_CHUNK as usize) {
+            let chunk_to = (chunk_from + KEYS_PER_CHUNK as usize).min(self.to);
+
+            for net_key in &self.net_keys[chunk_from..chunk_to] {
+                // keys are validated when the KeyListCipher is created
+                key_callback(&Key::from_buffer(net_key).expect("expected key list to only have valid keys"));
+            }
+
+            if !chunk_callback((chunk_to - chunk_from) as u32) { return }
+        }
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<Key> {
+        if self.from == self.to { return None }
+        let net_key = &self.net_keys[rng.random_range(self.from..self.to)];
+        // keys are validated when the KeyListCipher is created
+        Some(Key::from_buffer(net_key).expect("expected key list to only have valid keys"))
+    }
+}
+
+/**
+ * Wraps a cipher, restricting its key space to a list of keys, such as the
+ * keys in a key dump. Useful for refining the matches of a previous search with
+ * a stricter condition, without permuting the whole key space again
+ */
+pub struct KeyListCipher<C: Cipher> {
+    cipher: C,
+    net_keys: Arc<Vec<Box<[u8]>>>,
+}
+
+impl<C: Cipher> KeyListCipher<C> {
+    pub fn new(cipher: C, net_keys: Vec<Box<[u8]>>) -> Result<Self, Box<dyn Error>> {
+        for net_key in &net_keys {
+            C::Key::from_buffer(net_key)?;
+        }
+
+        Ok(Self { cipher, net_keys: Arc::new(net_keys) })
+    }
+
+    pub fn get_inner(&self) -> &C {
+        &self.cipher
+    }
+}
+
+impl<C: Cipher> Cipher for KeyListCipher<C> {
+    type Key = C::Key;
+    type Context = KeyListWorkletContext<C::Key, C::Context>;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.net_keys.len().clamp(1, u32::MAX as usize) as u32
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        let len = self.net_keys.len();
+
+        KeyListWorkletContext {
+            net_keys: self.net_keys.clone(),
+            from: (worklet_id as usize * len) / worklet_total as usize,
+            to: ((worklet_id + 1) as usize * len) / worklet_total as usize,
+            _phantom: PhantomData,
+        }
+    }
+
+    fn net_key_to_boxed_str(&self, net_key: &Box<[u8]>) -> Result<Box<str>, Box<dyn Error>> {
+        self.cipher.net_key_to_boxed_str(net_key)
+    }
+}
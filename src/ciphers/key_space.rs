@@ -0,0 +1,231 @@
+use rand::Rng;
+use rug::Integer;
+use smallvec::{SmallVec, smallvec};
+
+/// Keys per chunk when permuting a key space. Must fit in a u32, since chunk
+/// sizes are reported via chunk_callback
+pub const KEYS_PER_CHUNK: u32 = 65536;
+
+pub type Digits = SmallVec<[u32; 16]>;
+
+/**
+ * A key space made of consecutive segments, where each segment has all the
+ * combinations of its digits, like a mixed-radix number where the last digit
+ * changes the fastest. Ciphers with keys made of independent parameters can
+ * use this for enumeration, which gives them parallel slicing and resumable
+ * chunks for free; worklets get a slice of the range of key indices, and
+ * chunks are runs of KEYS_PER_CHUNK key indices. For example, a periodic
+ * cipher can have a segment for each period, with a digit for each shift
+ */
+#[derive(Clone)]
+pub struct MixedRadixKeySpace {
+    segments: Vec<Box<[u32]>>,
+    segment_totals: Vec<Integer>,
+}
+
+impl MixedRadixKeySpace {
+    /**
+     * Each segment is a list of radices, one per digit. Radices must not be 0
+     */
+    pub fn new(segments: Vec<Box<[u32]>>) -> Self {
+        let mut segment_totals = Vec::with_capacity(segments.len());
+        for radices in segments.iter() {
+            assert!(radices.iter().all(|radix| *radix > 0), "radices must not be 0");
+
+            let mut total = Integer::from(1);
+            for radix in radices.iter() {
+                total *= *radix;
+            }
+
+            segment_totals.push(total);
+        }
+
+        Self { segments, segment_totals }
+    }
+
+    pub fn get_total(&self) -> Integer {
+        let mut total = Integer::new();
+        for segment_total in self.segment_totals.iter() {
+            total += segment_total;
+        }
+
+        total
+    }
+
+    pub fn get_max_parallelism(&self) -> u32 {
+        self.get_total().to_u32().unwrap_or(u32::MAX).max(1)
+    }
+
+    /**
+     * Range of key indices (start inclusive, end exclusive) permuted by a
+     * worklet
+     */
+    pub fn get_worklet_range(&self, worklet_id: u32, worklet_total: u32) -> (Integer, Integer) {
+        let total = self.get_total();
+        let from = Integer::from(&total * worklet_id) / worklet_total;
+        let to = Integer::from(&total * (worklet_id + 1)) / worklet_total;
+        (from, to)
+    }
+
+    /**
+     * Number of chunks permute_range calls chunk_callback for, for the given
+     * range of key indices
+     */
+    pub fn get_range_chunks(&self, range: &(Integer, Integer)) -> Integer {
+        let (from, to) = range;
+        if to <= from { return Integer::new() }
+        (Integer::from(to - from) + (KEYS_PER_CHUNK - 1)) / KEYS_PER_CHUNK
+    }
+
+    /**
+     * Segment index and digits of the key with the given index, or None if the
+     * index is out of bounds
+     */
+    pub fn decode(&self, index: &Integer) -> Option<(usize, Digits)> {
+        let mut index = index.clone();
+        for (segment, segment_total) in self.segment_totals.iter().enumerate() {
+            if index < *segment_total {
+                let radices = &self.segments[segment];
+                let mut digits: Digits = smallvec![0; radices.len()];
+                for d in (0..radices.len()).rev() {
+                    let (div, rem) = index.div_rem_euc(Integer::from(radices[d]));
+                    digits[d] = rem.to_u32().unwrap();
+                    index = div;
+                }
+
+                return Some((segment, digits));
+            }
+
+            index -= segment_total;
+        }
+
+        None
+    }
+
+    /**
+     * Segment index and digits of a uniformly random key in the given range of
+     * key indices, or None if the range is empty
+     */
+    pub fn random_in_range<R: Rng>(&self, range: &(Integer, Integer), rng: &mut R) -> Option<(usize, Digits)> {
+        let offset = random_integer_below(&Integer::from(&range.1 - &range.0), rng)?;
+        self.decode(&(offset + &range.0))
+    }
+
+    /**
+     * Calls key_callback with the segment index and digits of each key in the
+     * given range of key indices, starting at the chunk with index start_chunk.
+     * Follows the same rules as CipherWorkletContext::permute_keys_interruptible_from
+     */
+    pub fn permute_range<KC: FnMut(usize, &[u32]), CC: FnMut(u32) -> bool>(&self, range: &(Integer, Integer), start_chunk: &Integer, mut key_callback: KC, mut chunk_callback: CC) {
+        let (from, to) = range;
+        let start = Integer::from(start_chunk * KEYS_PER_CHUNK) + from;
+        if start >= *to { return } // already finished
+        let Some((mut segment, mut digits)) = self.decode(&start) else { return };
+        let mut keys_left = Integer::from(to - &start);
+
+        loop {
+            let chunk_keys = if keys_left > KEYS_PER_CHUNK { KEYS_PER_CHUNK } else { keys_left.to_u32().unwrap() };
+
+            for _ in 0..chunk_keys {
+                key_callback(segment, &digits);
+
+                // increment digits, moving to the next segment on overflow
+                let radices = &self.segments[segment];
+                let mut d = digits.len();
+                loop {
+                    if d == 0 {
+                        segment += 1;
+                        if segment < self.segments.len() {
+                            digits.clear();
+                            digits.resize(self.segments[segment].len(), 0);
+                        }
+
+                        break;
+                    }
+
+                    d -= 1;
+                    digits[d] += 1;
+                    if digits[d] < radices[d] { break }
+                    digits[d] = 0;
+                }
+            }
+
+            keys_left -= chunk_keys;
+            if !chunk_callback(chunk_keys) || keys_left == 0 { return }
+        }
+    }
+}
+
+/**
+ * Uniformly random integer in 0..bound, or None if bound is not positive
+ */
+pub fn random_integer_below<R: Rng>(bound: &Integer, rng: &mut R) -> Option<Integer> {
+    if *bound <= 0 { return None }
+    if let Some(bound) = bound.to_u64() {
+        return Some(Integer::from(rng.random_range(0..bound)));
+    }
+
+    // rejection sampling with just enough random bits for the bound, so that
+    // less than half of the candidates are rejected on average
+    let bits = bound.significant_bits();
+    let mut limbs = vec![0u64; bits.div_ceil(64) as usize];
+    loop {
+        rng.fill(limbs.as_mut_slice());
+        // SAFETY: bits > 64, since the bound didn't fit in a u64, so there's
+        //         at least one limb
+        *unsafe { limbs.last_mut().unwrap_unchecked() } >>= limbs.len() as u32 * 64 - bits;
+
+        let candidate = Integer::from_digits(&limbs, rug::integer::Order::Lsf);
+        if candidate < *bound { return Some(candidate) }
+    }
+}
+
+/**
+ * Radices of a Lehmer code for permutations of len items, so that a segment
+ * with these radices has one key per permutation. See decode_lehmer_code
+ */
+pub fn lehmer_code_radices(len: usize) -> Box<[u32]> {
+    (1..=len as u32).rev().collect()
+}
+
+/**
+ * Writes the permutation of 0..digits.len() encoded by a Lehmer code, where
+ * each digit is the index of the next item among the items that weren't picked
+ * yet. Permutations are in lexicographic order when enumerating digits in
+ * order. At most 256 items are supported
+ */
+pub fn decode_lehmer_code(digits: &[u32], permutation: &mut [u8]) {
+    assert!(digits.len() <= 256 && permutation.len() == digits.len());
+
+    let mut items: SmallVec<[u8; 256]> = (0..digits.len()).map(|i| i as u8).collect();
+    for (i, digit) in digits.iter().enumerate() {
+        permutation[i] = items.remove(*digit as usize);
+    }
+}
+
+/**
+ * Order crossover of two permutations of 0..a.len(), written to child. A
+ * random run of items is copied from a, and the rest are filled in the order
+ * they have in b, so that the child is still a permutation. At most 256 items
+ * are supported
+ */
+pub fn order_crossover<R: Rng>(a: &[u8], b: &[u8], child: &mut [u8], rng: &mut R) {
+    let len = a.len();
+    assert!(len <= 256 && b.len() == len && child.len() == len);
+    if len == 0 { return }
+
+    let start = rng.random_range(0..len);
+    let end = rng.random_range(start..len) + 1;
+    let mut taken = [false; 256];
+    for i in start..end {
+        child[i] = a[i];
+        taken[a[i] as usize] = true;
+    }
+
+    let mut fill = b.iter().filter(|item| !taken[**item as usize]);
+    for i in (0..start).chain(end..len) {
+        // can't fail, since b has exactly one item that wasn't taken from a
+        // for each position left to fill
+        child[i] = *fill.next().unwrap();
+    }
+}
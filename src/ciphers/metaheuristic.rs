@@ -0,0 +1,222 @@
+use std::{error::Error, fmt};
+
+use rand::Rng;
+
+use crate::ciphers::base::{Cipher, CipherWorkletContext};
+
+/*
+ * Metaheuristic searches maximise a fitness function over the keys of any
+ * cipher that implements the mutate_key (and, for genetic algorithms,
+ * crossover_keys) hooks of the Cipher trait, for key spaces that are too big
+ * to be searched exhaustively. Starting keys are drawn with
+ * CipherWorkletContext::random_key. Keys with a NaN fitness are treated as the
+ * worst possible keys.
+ */
+
+/// Random keys tried before giving up, since random_key may return None for
+/// keys that are skipped when permuting
+const MAX_RANDOM_KEY_ATTEMPTS: usize = 1024;
+
+#[derive(Debug)]
+pub enum MetaheuristicError {
+    NoRandomKey,
+    MutationUnsupported,
+    CrossoverUnsupported,
+    BadParameters { msg: Box<str> },
+}
+
+impl fmt::Display for MetaheuristicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoRandomKey => write!(f, "Couldn't draw a random key; the key space may be empty"),
+            Self::MutationUnsupported => write!(f, "This cipher doesn't support metaheuristic searches"),
+            Self::CrossoverUnsupported => write!(f, "This cipher doesn't support crossover, so it can't be used with genetic algorithms"),
+            Self::BadParameters { msg } => write!(f, "Bad metaheuristic parameters: {}", msg),
+        }
+    }
+}
+
+impl Error for MetaheuristicError {}
+
+pub enum MetaheuristicParameters {
+    /// Always moves to mutated keys that are at least as fit. Restarts from a
+    /// new random key after `patience` mutations in a row without an
+    /// improvement
+    HillClimbing { iterations: u64, patience: u64 },
+    /// Simulated annealing. The temperature cools down linearly to 0 by the
+    /// last iteration. Worse keys are accepted with a probability of
+    /// e^(fitness delta / temperature), so 0 is a plain hill climb without
+    /// restarts
+    Annealing { iterations: u64, temperature: f64 },
+    /// Generational genetic algorithm with tournament selection. The `elitism`
+    /// best keys of each generation are kept as-is, and the rest of the next
+    /// generation is made of crossovers of selected parents, which are then
+    /// mutated with a probability of `mutation_rate`
+    Genetic { generations: u64, population: usize, tournament_size: usize, mutation_rate: f64, elitism: usize },
+}
+
+impl MetaheuristicParameters {
+    fn validate(&self) -> Result<(), MetaheuristicError> {
+        let bad = |msg: &str| Err(MetaheuristicError::BadParameters { msg: msg.into() });
+
+        match self {
+            Self::HillClimbing { patience, .. } if *patience == 0 => bad("patience must be at least 1"),
+            Self::Annealing { temperature, .. } if !(*temperature >= 0.0) => bad("temperature must not be negative"),
+            Self::Genetic { population, .. } if *population < 2 => bad("population must be at least 2"),
+            Self::Genetic { population, tournament_size, .. } if *tournament_size == 0 || tournament_size > population => bad("tournament size must be in the range 1..=population"),
+            Self::Genetic { mutation_rate, .. } if !(0.0..=1.0).contains(mutation_rate) => bad("mutation rate must be in the range 0..=1"),
+            Self::Genetic { population, elitism, .. } if elitism >= population => bad("elitism must be less than the population"),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn random_key<C: Cipher, R: Rng>(worklet_ctx: &C::Context, rng: &mut R) -> Result<C::Key, MetaheuristicError> {
+    for _ in 0..MAX_RANDOM_KEY_ATTEMPTS {
+        if let Some(key) = worklet_ctx.random_key(rng) {
+            return Ok(key);
+        }
+    }
+
+    Err(MetaheuristicError::NoRandomKey)
+}
+
+fn hill_climb<C: Cipher, R: Rng, F: FnMut(&C::Key) -> f64>(cipher: &C, worklet_ctx: &C::Context, iterations: u64, patience: u64, rng: &mut R, mut fitness: F) -> Result<(C::Key, f64), MetaheuristicError> {
+    let mut key = random_key::<C, R>(worklet_ctx, rng)?;
+    let mut key_fitness = fitness(&key);
+    let mut best_key = key.clone();
+    let mut best_fitness = key_fitness;
+    let mut stale = 0u64;
+
+    for _ in 0..iterations {
+        if stale >= patience {
+            key = random_key::<C, R>(worklet_ctx, rng)?;
+            key_fitness = fitness(&key);
+            stale = 0;
+        } else {
+            let candidate = cipher.mutate_key(&key, rng).ok_or(MetaheuristicError::MutationUnsupported)?;
+            let candidate_fitness = fitness(&candidate);
+
+            // equally fit keys are accepted to move along plateaus, but they
+            // don't count as an improvement
+            if candidate_fitness > key_fitness {
+                stale = 0;
+            } else {
+                stale += 1;
+            }
+
+            if candidate_fitness >= key_fitness {
+                key = candidate;
+                key_fitness = candidate_fitness;
+            }
+        }
+
+        if key_fitness > best_fitness {
+            best_key = key.clone();
+            best_fitness = key_fitness;
+        }
+    }
+
+    Ok((best_key, best_fitness))
+}
+
+fn anneal<C: Cipher, R: Rng, F: FnMut(&C::Key) -> f64>(cipher: &C, worklet_ctx: &C::Context, iterations: u64, temperature: f64, rng: &mut R, mut fitness: F) -> Result<(C::Key, f64), MetaheuristicError> {
+    let mut key = random_key::<C, R>(worklet_ctx, rng)?;
+    let mut key_fitness = fitness(&key);
+    let mut best_key = key.clone();
+    let mut best_fitness = key_fitness;
+
+    for i in 0..iterations {
+        let candidate = cipher.mutate_key(&key, rng).ok_or(MetaheuristicError::MutationUnsupported)?;
+        let candidate_fitness = fitness(&candidate);
+        let delta = candidate_fitness - key_fitness;
+        let current_temperature = temperature * (1.0 - i as f64 / iterations as f64);
+
+        if delta >= 0.0 || (current_temperature > 0.0 && rng.random::<f64>() < (delta / current_temperature).exp()) {
+            key = candidate;
+            key_fitness = candidate_fitness;
+
+            if key_fitness > best_fitness {
+                best_key = key.clone();
+                best_fitness = key_fitness;
+            }
+        }
+    }
+
+    Ok((best_key, best_fitness))
+}
+
+/// Index of the fittest of tournament_size randomly picked keys
+fn select<K, R: Rng>(population: &[(K, f64)], tournament_size: usize, rng: &mut R) -> usize {
+    let mut winner = rng.random_range(0..population.len());
+    for _ in 1..tournament_size {
+        let contender = rng.random_range(0..population.len());
+        if population[contender].1 > population[winner].1 {
+            winner = contender;
+        }
+    }
+
+    winner
+}
+
+fn evolve<C: Cipher, R: Rng, F: FnMut(&C::Key) -> f64>(cipher: &C, worklet_ctx: &C::Context, generations: u64, population_size: usize, tournament_size: usize, mutation_rate: f64, elitism: usize, rng: &mut R, mut fitness: F) -> Result<(C::Key, f64), MetaheuristicError> {
+    let mut population: Vec<(C::Key, f64)> = Vec::with_capacity(population_size);
+    for _ in 0..population_size {
+        let key = random_key::<C, R>(worklet_ctx, rng)?;
+        let key_fitness = fitness(&key);
+        population.push((key, key_fitness));
+    }
+
+    // kept separately, since the best key is lost if there's no elitism
+    let mut best = population.iter().max_by(|(_, a), (_, b)| a.total_cmp(b)).unwrap().clone();
+
+    for _ in 0..generations {
+        // fittest first, so that the elite is at the start
+        population.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+        let mut next_population = Vec::with_capacity(population_size);
+        next_population.extend(population[..elitism].iter().cloned());
+
+        while next_population.len() < population_size {
+            let a = &population[select(&population, tournament_size, rng)].0;
+            let b = &population[select(&population, tournament_size, rng)].0;
+            let mut child = cipher.crossover_keys(a, b, rng).ok_or(MetaheuristicError::CrossoverUnsupported)?;
+
+            if rng.random_bool(mutation_rate) {
+                child = cipher.mutate_key(&child, rng).ok_or(MetaheuristicError::MutationUnsupported)?;
+            }
+
+            let child_fitness = fitness(&child);
+            if child_fitness > best.1 {
+                best = (child.clone(), child_fitness);
+            }
+
+            next_population.push((child, child_fitness));
+        }
+
+        population = next_population;
+    }
+
+    Ok(best)
+}
+
+/**
+ * Runs a metaheuristic search, maximising the given fitness function. Returns
+ * the best key found and its fitness. Results only depend on the state of the
+ * random number generator, so seeded generators give reproducible searches
+ */
+pub fn run_metaheuristic<C: Cipher, R: Rng, F: FnMut(&C::Key) -> f64>(cipher: &C, params: &MetaheuristicParameters, rng: &mut R, mut fitness: F) -> Result<(C::Key, f64), MetaheuristicError> {
+    params.validate()?;
+
+    let worklet_ctx = cipher.create_worklet_context();
+    let fitness = |key: &C::Key| {
+        let key_fitness = fitness(key);
+        if key_fitness.is_nan() { f64::NEG_INFINITY } else { key_fitness }
+    };
+
+    match *params {
+        MetaheuristicParameters::HillClimbing { iterations, patience } => hill_climb(cipher, &worklet_ctx, iterations, patience, rng, fitness),
+        MetaheuristicParameters::Annealing { iterations, temperature } => anneal(cipher, &worklet_ctx, iterations, temperature, rng, fitness),
+        MetaheuristicParameters::Genetic { generations, population, tournament_size, mutation_rate, elitism } => evolve(cipher, &worklet_ctx, generations, population, tournament_size, mutation_rate, elitism, rng, fitness),
+    }
+}
@@ -1,11 +1,161 @@
 use crate::utils::run::AnyErrorResult;
 
 pub mod base;
+pub mod affine;
 pub mod arx;
+pub mod autokey;
+pub mod chain;
+pub mod config;
+pub mod hill;
+pub mod key_list;
+pub mod key_space;
+pub mod metaheuristic;
+pub mod substitution;
+pub mod transposition;
+pub mod vigenere;
 
-pub fn deserialise_cipher(cipher_name: &str, config: Option<&str>) -> AnyErrorResult<impl base::Cipher> {
-    match cipher_name {
-        "arx" => arx::ARXCipher::new(config),
-        _ => Err(base::StandardCipherError::UnknownCipher.into()),
+/**
+ * Receives a deserialised cipher as its concrete type. Each cipher has a
+ * different type, so tools that need the concrete type (for example, to
+ * monomorphise a search loop) implement this instead of getting a return value
+ * from deserialise_cipher
+ */
+pub trait CipherVisitor {
+    type Output;
+
+    fn visit<C: base::Cipher + Send + Sync + 'static>(self, cipher: C) -> Self::Output;
+}
+
+/**
+ * A cipher that can be created by name, for example, by the CLI tools. Usually
+ * implemented by the cipher type itself, and registered by listing it in
+ * visit_basic_cipher_factories
+ */
+pub trait CipherFactory {
+    const NAME: &'static str;
+    /** one line description, shown when listing ciphers */
+    const DESCRIPTION: &'static str;
+    /** format of the configuration, or None if the cipher isn't configurable */
+    const CONFIG_SCHEMA: Option<&'static str>;
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output>;
+}
+
+/**
+ * Receives the type of each registered cipher factory, in the same way that
+ * CipherVisitor receives a deserialised cipher
+ */
+pub trait CipherFactoryVisitor {
+    /** return false to stop visiting factories */
+    fn visit<F: CipherFactory>(&mut self) -> bool;
+}
+
+/**
+ * Visits all registered ciphers, except chains, since chains are made of basic
+ * ciphers. Returns false if the visitor stopped early
+ */
+pub(crate) fn visit_basic_cipher_factories<FV: CipherFactoryVisitor>(factory_visitor: &mut FV) -> bool {
+    factory_visitor.visit::<affine::AffineCipher>()
+        && factory_visitor.visit::<arx::ARXCipher>()
+        && factory_visitor.visit::<autokey::AutokeyCipher>()
+        && factory_visitor.visit::<hill::HillCipher>()
+        && factory_visitor.visit::<substitution::SubstitutionCipher>()
+        && factory_visitor.visit::<transposition::TranspositionCipher>()
+        && factory_visitor.visit::<vigenere::VigenereCipher>()
+}
+
+/**
+ * Visits all registered ciphers. Returns false if the visitor stopped early
+ */
+pub fn visit_cipher_factories<FV: CipherFactoryVisitor>(factory_visitor: &mut FV) -> bool {
+    visit_basic_cipher_factories(factory_visitor) && factory_visitor.visit::<chain::ChainCipher>()
+}
+
+#[derive(Clone, Debug)]
+pub struct CipherInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub config_schema: Option<&'static str>,
+}
+
+struct CipherInfoVisitor {
+    infos: Vec<CipherInfo>,
+}
+
+impl CipherFactoryVisitor for CipherInfoVisitor {
+    fn visit<F: CipherFactory>(&mut self) -> bool {
+        self.infos.push(CipherInfo { name: F::NAME, description: F::DESCRIPTION, config_schema: F::CONFIG_SCHEMA });
+        true
     }
-}
\ No newline at end of file
+}
+
+/**
+ * Names, descriptions and configuration schemas of all registered ciphers
+ */
+pub fn get_cipher_infos() -> Vec<CipherInfo> {
+    let mut info_visitor = CipherInfoVisitor { infos: Vec::new() };
+    visit_cipher_factories(&mut info_visitor);
+    info_visitor.infos
+}
+
+struct DeserialiseVisitor<'a, V: CipherVisitor> {
+    cipher_name: &'a str,
+    config: Option<&'a str>,
+    /** taken when the factory with the wanted name is found */
+    visitor: Option<V>,
+    result: Option<AnyErrorResult<V::Output>>,
+}
+
+impl<'a, V: CipherVisitor> CipherFactoryVisitor for DeserialiseVisitor<'a, V> {
+    fn visit<F: CipherFactory>(&mut self) -> bool {
+        if F::NAME != self.cipher_name { return true }
+
+        // the visitor is only taken once, since visiting stops here
+        let visitor = self.visitor.take().expect("visitor already consumed");
+        self.result = Some(F::deserialise(self.config, visitor));
+        false
+    }
+}
+
+impl<'a, V: CipherVisitor> DeserialiseVisitor<'a, V> {
+    fn new(cipher_name: &'a str, config: Option<&'a str>, visitor: V) -> Self {
+        DeserialiseVisitor { cipher_name, config, visitor: Some(visitor), result: None }
+    }
+
+    fn into_result(self) -> AnyErrorResult<V::Output> {
+        self.result.unwrap_or_else(|| Err(base::StandardCipherError::UnknownCipher.into()))
+    }
+}
+
+pub fn deserialise_cipher<V: CipherVisitor>(cipher_name: &str, config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+    let mut deserialise_visitor = DeserialiseVisitor::new(cipher_name, config, visitor);
+    visit_cipher_factories(&mut deserialise_visitor);
+    deserialise_visitor.into_result()
+}
+
+/**
+ * Like deserialise_cipher, but without chains
+ */
+pub(crate) fn deserialise_basic_cipher<V: CipherVisitor>(cipher_name: &str, config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+    let mut deserialise_visitor = DeserialiseVisitor::new(cipher_name, config, visitor);
+    visit_basic_cipher_factories(&mut deserialise_visitor);
+    deserialise_visitor.into_result()
+}
+
+struct DynCipherVisitor;
+
+impl CipherVisitor for DynCipherVisitor {
+    type Output = Box<dyn base::DynCipher>;
+
+    fn visit<C: base::Cipher + Send + Sync + 'static>(self, cipher: C) -> Self::Output {
+        Box::new(cipher)
+    }
+}
+
+/**
+ * Like deserialise_cipher, but the cipher's type is erased. Enough for tools
+ * that only need to convert keys or get the outputs of a key
+ */
+pub fn deserialise_dyn_cipher(cipher_name: &str, config: Option<&str>) -> AnyErrorResult<Box<dyn base::DynCipher>> {
+    deserialise_cipher(cipher_name, config, DynCipherVisitor)
+}
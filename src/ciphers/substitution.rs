@@ -0,0 +1,352 @@
+use std::{error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::{MixedRadixKeySpace, decode_lehmer_code, lehmer_code_radices, order_crossover}}, data::message::{InterleavedMessageData, MessageDataList}, utils::run::AnyErrorResult};
+
+/*
+ * The key space of substitution ciphers is too big to be searched exhaustively
+ * for any real alphabet (83! keys for the eye messages), so although keys can
+ * be permuted like any other cipher, the intended way of finding keys is with
+ * a metaheuristic search (see the metaheuristic module and the optimise binary)
+ */
+
+const MAX_UNITS: usize = 256;
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubstitutionConfig {
+    /// Size of the ciphertext alphabet. Ciphertext units greater or equal to
+    /// this are left as-is
+    ciphertext_units: u16,
+    /// Size of the plaintext alphabet. Defaults to the size of the ciphertext
+    /// alphabet
+    #[serde(default)]
+    plaintext_units: Option<u16>,
+    /// Whether multiple ciphertext units can map to the same plaintext unit.
+    /// If false, the key is a permutation, and both alphabets must have the
+    /// same size
+    #[serde(default)]
+    homophonic: bool,
+}
+
+impl CipherConfig for SubstitutionConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("ciphertext_units", self.ciphertext_units, 1..=MAX_UNITS as u16)?;
+        if let Some(plaintext_units) = self.plaintext_units {
+            check_range("plaintext_units", plaintext_units, 1..=MAX_UNITS as u16)?;
+            if !self.homophonic && plaintext_units != self.ciphertext_units {
+                return Err(StandardCipherError::BadConfiguration { msg: "plaintext_units: must be equal to ciphertext_units if the substitution is not homophonic".into() });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedSubstitutionKey {
+    #[prost(bytes = "vec", tag = "1")]
+    pub mapping: Vec<u8>,
+}
+
+/**
+ * Maps each ciphertext unit (the index in the mapping) to a plaintext unit.
+ * Multiple ciphertext units may map to the same plaintext unit (homophones)
+ */
+#[derive(Clone)]
+pub struct SubstitutionKey {
+    pub mapping: Box<[u8]>,
+}
+
+impl ToString for SubstitutionKey {
+    fn to_string(&self) -> String {
+        let mapping: Vec<String> = self.mapping.iter().map(|unit| unit.to_string()).collect();
+        format!("[{}]", mapping.join(","))
+    }
+}
+
+impl FromStr for SubstitutionKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: "Key must be wrapped in square brackets".into() }
+        })?;
+
+        let mut mapping = Vec::new();
+        for unit in inner.split(',') {
+            mapping.push(unit.trim().parse::<u8>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid plaintext unit \"{}\"", unit.trim()).into() }))?);
+        }
+
+        let key = SubstitutionKey { mapping: mapping.into() };
+        key.validate()?;
+        Ok(key)
+    }
+}
+
+impl SubstitutionKey {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.mapping.len() == 0 || self.mapping.len() > MAX_UNITS {
+            Err(StandardCipherError::BadKey { msg: format!("Key must map between 1 and {MAX_UNITS} ciphertext units").into() }.into())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl CipherKey for SubstitutionKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedSubstitutionKey { mapping: self.mapping.to_vec() }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedSubstitutionKey::decode(buffer.iter().as_slice())?;
+        let key = SubstitutionKey { mapping: enc_key.mapping.into() };
+        key.validate()?;
+        Ok(key)
+    }
+}
+
+pub struct SubstitutionCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec SubstitutionKey,
+    input_messages: &'codec InterleavedMessageData,
+    /**
+     * Plaintext to ciphertext mapping, only used when encrypting. Homophones
+     * are always encrypted to the first ciphertext unit that maps to them, and
+     * plaintext units without a ciphertext unit are left as-is
+     */
+    inverse_mapping: [u8; MAX_UNITS],
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, SubstitutionKey> for SubstitutionCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec SubstitutionKey) -> Self {
+        let mut inverse_mapping = [0u8; MAX_UNITS];
+        if !DECRYPT {
+            for (u, unit) in inverse_mapping.iter_mut().enumerate() {
+                *unit = u as u8;
+            }
+
+            for (c, p) in key.mapping.iter().enumerate().rev() {
+                inverse_mapping[*p as usize] = c as u8;
+            }
+        }
+
+        SubstitutionCodecContext { input_messages, key, inverse_mapping }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        // SAFETY: bounds must be verified by caller
+        let unit = unsafe { *self.input_messages.get_unchecked(message_index, unit_index) };
+
+        if DECRYPT {
+            *self.key.mapping.get(unit as usize).unwrap_or(&unit)
+        } else {
+            self.inverse_mapping[unit as usize]
+        }
+    }
+}
+
+pub struct SubstitutionWorkletContext {
+    homophonic: bool,
+    ciphertext_units: u16,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<SubstitutionKey> for SubstitutionWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = SubstitutionCodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&SubstitutionKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let mut key = SubstitutionKey { mapping: vec![0; self.ciphertext_units as usize].into() };
+
+        // the key space has a single segment. for homophonic keys, each digit
+        // is the plaintext unit of a ciphertext unit, otherwise the digits are
+        // the Lehmer code of the permutation
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            if self.homophonic {
+                for (i, digit) in digits.iter().enumerate() {
+                    key.mapping[i] = *digit as u8;
+                }
+            } else {
+                decode_lehmer_code(digits, &mut key.mapping);
+            }
+
+            key_callback(&key);
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<SubstitutionKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let mut key = SubstitutionKey { mapping: vec![0; self.ciphertext_units as usize].into() };
+        if self.homophonic {
+            for (i, digit) in digits.iter().enumerate() {
+                key.mapping[i] = *digit as u8;
+            }
+        } else {
+            decode_lehmer_code(&digits, &mut key.mapping);
+        }
+
+        Some(key)
+    }
+}
+
+pub struct SubstitutionCipher {
+    homophonic: bool,
+    ciphertext_units: u16,
+    plaintext_units: u16,
+    key_space: MixedRadixKeySpace,
+}
+
+impl SubstitutionCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<SubstitutionCipher> {
+        let config: SubstitutionConfig = parse_config(config)?;
+        let plaintext_units = config.plaintext_units.unwrap_or(config.ciphertext_units);
+
+        let radices = if config.homophonic {
+            vec![plaintext_units as u32; config.ciphertext_units as usize].into_boxed_slice()
+        } else {
+            lehmer_code_radices(config.ciphertext_units as usize)
+        };
+
+        Ok(SubstitutionCipher {
+            homophonic: config.homophonic,
+            ciphertext_units: config.ciphertext_units,
+            plaintext_units,
+            key_space: MixedRadixKeySpace::new(vec![radices]),
+        })
+    }
+
+    /**
+     * Checks that a key maps every ciphertext unit to a plaintext unit, and that
+     * it's a permutation if the substitution is not homophonic
+     */
+    fn check_key(&self, key: &SubstitutionKey) -> Result<(), Box<dyn Error>> {
+        if key.mapping.len() != self.ciphertext_units as usize {
+            return Err(StandardCipherError::BadKey { msg: format!("Key maps {} ciphertext units, but the cipher has {}", key.mapping.len(), self.ciphertext_units).into() }.into());
+        }
+
+        if let Some(unit) = key.mapping.iter().find(|unit| **unit as u16 >= self.plaintext_units) {
+            return Err(StandardCipherError::BadKey { msg: format!("Plaintext unit {} is out of range; the cipher has {} plaintext units", unit, self.plaintext_units).into() }.into());
+        }
+
+        if !self.homophonic {
+            let mut mapped = [false; MAX_UNITS];
+            for unit in key.mapping.iter() {
+                if mapped[*unit as usize] {
+                    return Err(StandardCipherError::BadKey { msg: format!("Plaintext unit {unit} is mapped more than once, but the substitution is not homophonic").into() }.into());
+                }
+
+                mapped[*unit as usize] = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Randomly changes one or two ciphertext units of a key. Non-homophonic
+     * keys are only changed by swapping, so they stay permutations
+     */
+    fn change_key<R: Rng>(&self, key: &mut SubstitutionKey, rng: &mut R) {
+        let len = key.mapping.len();
+        let a = rng.random_range(0..len);
+
+        if (self.homophonic && rng.random_bool(0.5)) || len < 2 {
+            key.mapping[a] = rng.random_range(0..self.plaintext_units) as u8;
+        } else {
+            // pick a different unit to swap with
+            let b = (a + rng.random_range(1..len)) % len;
+            key.mapping.swap(a, b);
+        }
+    }
+}
+
+impl Cipher for SubstitutionCipher {
+    type Key = SubstitutionKey;
+    type Context = SubstitutionWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        SubstitutionWorkletContext {
+            homophonic: self.homophonic,
+            ciphertext_units: self.ciphertext_units,
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn net_key_to_boxed_str(&self, net_key: &Box<[u8]>) -> Result<Box<str>, Box<dyn Error>> {
+        let key = SubstitutionKey::from_buffer(net_key)?;
+        self.check_key(&key)?;
+        Ok(key.to_string().into_boxed_str())
+    }
+
+    fn str_key_to_net_key(&self, str_key: &str) -> Result<Box<[u8]>, Box<dyn Error>> {
+        let key: SubstitutionKey = str_key.parse()?;
+        self.check_key(&key)?;
+        Ok(key.encode_to_buffer())
+    }
+
+    fn net_key_to_output_messages(&self, net_key: &Box<[u8]>, input_messages: &InterleavedMessageData, decrypt: bool) -> Result<MessageDataList, Box<dyn Error>> {
+        let key = SubstitutionKey::from_buffer(net_key)?;
+        self.check_key(&key)?;
+
+        Ok(if decrypt {
+            SubstitutionCodecContext::<'_, true>::new(input_messages, &key).get_output_messages()
+        } else {
+            SubstitutionCodecContext::<'_, false>::new(input_messages, &key).get_output_messages()
+        })
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &SubstitutionKey, rng: &mut R) -> Option<SubstitutionKey> {
+        let mut key = key.clone();
+        self.change_key(&mut key, rng);
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &SubstitutionKey, b: &SubstitutionKey, rng: &mut R) -> Option<SubstitutionKey> {
+        if a.mapping.len() != b.mapping.len() { return None }
+        let mut child = a.clone();
+
+        if self.homophonic {
+            for (u, plaintext_unit) in child.mapping.iter_mut().enumerate() {
+                if rng.random_bool(0.5) {
+                    *plaintext_unit = b.mapping[u];
+                }
+            }
+        } else {
+            order_crossover(&a.mapping, &b.mapping, &mut child.mapping, rng);
+        }
+
+        Some(child)
+    }
+}
+
+impl CipherFactory for SubstitutionCipher {
+    const NAME: &'static str = "substitution";
+    const DESCRIPTION: &'static str = "Simple or homophonic substitution. Too big to search exhaustively; meant for climbing";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(ciphertext_units: 1..=256, plaintext_units: 1..=256 = ciphertext_units, homophonic: bool = false)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(SubstitutionCipher::new(config)?))
+    }
+}
@@ -0,0 +1,450 @@
+use std::{error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+use smallvec::SmallVec;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_min_max, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::{MixedRadixKeySpace, decode_lehmer_code, lehmer_code_radices, order_crossover}}, data::message::InterleavedMessageData, utils::{run::AnyErrorResult, stackvec::StackVec}};
+
+const MAX_WIDTH: usize = 16;
+
+/**
+ * How the plaintext is laid out into columns (or rails), which are then read
+ * in the order given by the key to produce the ciphertext
+ * - Columnar: plaintext is written in rows of width units, and each column is
+ *   read top to bottom
+ * - RailFence: plaintext is written in a zigzag across width rails, and each
+ *   rail is read left to right. Usually rails are read in order, but the key
+ *   can reorder them like columns
+ * - Route: like columnar, but every other column (in reading order) is read
+ *   bottom to top, so the reading route snakes through the grid
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+pub enum TranspositionKind {
+    #[default]
+    Columnar,
+    RailFence,
+    Route,
+}
+
+impl TranspositionKind {
+    pub const ALL: [TranspositionKind; 3] = [TranspositionKind::Columnar, TranspositionKind::RailFence, TranspositionKind::Route];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Columnar => "columnar",
+            Self::RailFence => "rail-fence",
+            Self::Route => "route",
+        }
+    }
+
+    /**
+     * Amount of units in a column (or rail) of a message with len units
+     */
+    #[inline(always)]
+    fn get_column_len(&self, width: usize, len: usize, column: usize) -> usize {
+        match self {
+            Self::Columnar | Self::Route => {
+                let rows = len.div_ceil(width);
+                let long_columns = if len % width == 0 { width } else { len % width };
+                if column < long_columns { rows } else { rows - 1 }
+            },
+            Self::RailFence => {
+                let cycle = 2 * (width - 1);
+                let (cycles, rem) = (len / cycle, len % cycle);
+                if column == 0 || column == width - 1 {
+                    cycles + (column < rem) as usize
+                } else {
+                    2 * cycles + (column < rem) as usize + (cycle - column < rem) as usize
+                }
+            },
+        }
+    }
+
+    /**
+     * Column (or rail) of a plaintext unit, and its index in that column
+     */
+    #[inline(always)]
+    fn split_index(&self, width: usize, unit_index: usize) -> (usize, usize) {
+        match self {
+            Self::Columnar | Self::Route => (unit_index % width, unit_index / width),
+            Self::RailFence => {
+                let cycle = 2 * (width - 1);
+                let t = unit_index % cycle;
+                let (rail, second) = if t < width { (t, false) } else { (cycle - t, true) };
+                if rail == 0 || rail == width - 1 {
+                    (rail, unit_index / cycle)
+                } else {
+                    (rail, 2 * (unit_index / cycle) + second as usize)
+                }
+            },
+        }
+    }
+
+    /**
+     * Inverse of split_index
+     */
+    #[inline(always)]
+    fn join_index(&self, width: usize, column: usize, index: usize) -> usize {
+        match self {
+            Self::Columnar | Self::Route => index * width + column,
+            Self::RailFence => {
+                let cycle = 2 * (width - 1);
+                if column == 0 || column == width - 1 {
+                    index * cycle + column
+                } else {
+                    (index / 2) * cycle + if index % 2 == 0 { column } else { cycle - column }
+                }
+            },
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TranspositionConfig {
+    #[serde(default)]
+    kind: TranspositionKind,
+    /// Width of the grid for columnar and route transpositions, or amount of
+    /// rails for rail fence transpositions
+    #[serde(default = "default_min_width")]
+    min_width: usize,
+    max_width: usize,
+}
+
+fn default_min_width() -> usize { 2 }
+
+impl CipherConfig for TranspositionConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("min_width", self.min_width, 2..=MAX_WIDTH)?;
+        check_range("max_width", self.max_width, 2..=MAX_WIDTH)?;
+        check_min_max("min_width", self.min_width, "max_width", self.max_width)
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedTranspositionKey {
+    /// Index in TranspositionKind::ALL
+    #[prost(uint32, tag = "1")]
+    pub kind: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub order: Vec<u8>,
+}
+
+/**
+ * The kind is part of the key, since codec contexts only have access to the
+ * key. The width is the length of the order
+ */
+#[derive(Clone)]
+pub struct TranspositionKey {
+    pub kind: TranspositionKind,
+    /** columns (or rails) in reading order; a permutation of 0..width */
+    pub order: StackVec<u8, MAX_WIDTH>,
+}
+
+impl TranspositionKey {
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        let width = self.order.len();
+        let mut seen = [false; MAX_WIDTH];
+
+        if width < 2 {
+            return Err(StandardCipherError::BadKey { msg: "Key must have at least 2 columns".into() }.into());
+        }
+
+        for column in self.order.iter() {
+            let column = *column as usize;
+            if column >= width || seen[column] {
+                return Err(StandardCipherError::BadKey { msg: format!("Order must be a permutation of the columns 0..{width}").into() }.into());
+            }
+
+            seen[column] = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl ToString for TranspositionKey {
+    fn to_string(&self) -> String {
+        let order: Vec<String> = self.order.iter().map(|column| column.to_string()).collect();
+        format!("[{}: {}]", self.kind.get_name(), order.join(","))
+    }
+}
+
+impl FromStr for TranspositionKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: "Key must be wrapped in square brackets".into() }
+        })?;
+
+        let (kind_name, order_str) = inner.split_once(':').ok_or_else(|| {
+            StandardCipherError::BadKey { msg: "Expected a key in the format \"[kind: column,column,...]\"".into() }
+        })?;
+
+        let kind = *TranspositionKind::ALL.iter().find(|kind| kind.get_name() == kind_name.trim()).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: format!("Unknown transposition kind \"{}\"", kind_name.trim()).into() }
+        })?;
+
+        let mut key = TranspositionKey { kind, order: StackVec::new() };
+        for column in order_str.split(',') {
+            if key.order.len() >= MAX_WIDTH {
+                return Err(StandardCipherError::BadKey { msg: format!("Max width ({MAX_WIDTH}) exceeded").into() }.into());
+            }
+
+            key.order.push(column.trim().parse::<u8>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid column \"{}\"", column.trim()).into() }))?);
+        }
+
+        key.validate()?;
+        Ok(key)
+    }
+}
+
+impl CipherKey for TranspositionKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedTranspositionKey {
+            kind: TranspositionKind::ALL.iter().position(|kind| *kind == self.kind).unwrap() as u32,
+            order: self.order.iter().copied().collect(),
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedTranspositionKey::decode(buffer.iter().as_slice())?;
+        if enc_key.order.len() > MAX_WIDTH {
+            return Err("Max width exceeded".into());
+        }
+
+        let mut key = TranspositionKey {
+            kind: *TranspositionKind::ALL.get(enc_key.kind as usize).ok_or("Unknown transposition kind")?,
+            order: StackVec::new(),
+        };
+
+        for column in enc_key.order {
+            key.order.push(column);
+        }
+
+        key.validate()?;
+        Ok(key)
+    }
+}
+
+pub struct TranspositionCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec TranspositionKey,
+    input_messages: &'codec InterleavedMessageData,
+    /// Position of each column in the reading order
+    ranks: [u8; MAX_WIDTH],
+    /**
+     * Index in the ciphertext of each message where each column starts, in
+     * reading order. The entry after the last column is the message length
+     */
+    column_starts: SmallVec<[[u32; MAX_WIDTH + 1]; 9]>,
+}
+
+impl<'codec, const DECRYPT: bool> TranspositionCodecContext<'codec, DECRYPT> {
+    /**
+     * Route transpositions read every other column backwards. Maps between
+     * an index in a column and an offset in the ciphertext of the column
+     */
+    #[inline(always)]
+    fn route_adjust(&self, rank: usize, column_len: usize, index: usize) -> usize {
+        if self.key.kind == TranspositionKind::Route && rank % 2 == 1 {
+            column_len - 1 - index
+        } else {
+            index
+        }
+    }
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, TranspositionKey> for TranspositionCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec TranspositionKey) -> Self {
+        let width = key.order.len();
+        let mut ranks = [0u8; MAX_WIDTH];
+        for (rank, column) in key.order.iter().enumerate() {
+            ranks[*column as usize] = rank as u8;
+        }
+
+        let mut column_starts = SmallVec::new();
+        for m in 0..input_messages.get_message_count() {
+            // SAFETY: m is in bounds, since it's in 0..get_message_count()
+            let len = unsafe { input_messages.get_unit_count(m) };
+            let mut starts = [0u32; MAX_WIDTH + 1];
+            for (rank, column) in key.order.iter().enumerate() {
+                starts[rank + 1] = starts[rank] + key.kind.get_column_len(width, len, *column as usize) as u32;
+            }
+
+            column_starts.push(starts);
+        }
+
+        TranspositionCodecContext { key, input_messages, ranks, column_starts }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        let width = self.key.order.len();
+        // SAFETY: message_index bounds must be verified by caller, and there's
+        //         an entry per message
+        let starts = unsafe { self.column_starts.get_unchecked(message_index) };
+
+        let source_index = if DECRYPT {
+            // output is plaintext, find where its unit is in the ciphertext
+            let (column, index) = self.key.kind.split_index(width, unit_index);
+            let rank = self.ranks[column] as usize;
+            let column_len = (starts[rank + 1] - starts[rank]) as usize;
+            starts[rank] as usize + self.route_adjust(rank, column_len, index)
+        } else {
+            // output is ciphertext, find the column it was read from
+            let rank = starts[1..=width].partition_point(|start| *start as usize <= unit_index);
+            let column_len = (starts[rank + 1] - starts[rank]) as usize;
+            let index = self.route_adjust(rank, column_len, unit_index - starts[rank] as usize);
+            self.key.kind.join_index(width, self.key.order[rank] as usize, index)
+        };
+
+        // SAFETY: transpositions are permutations of the units of a message,
+        //         so the source index is in bounds if unit_index is
+        unsafe { *self.input_messages.get_unchecked(message_index, source_index) }
+    }
+}
+
+pub struct TranspositionWorkletContext {
+    kind: TranspositionKind,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<TranspositionKey> for TranspositionWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = TranspositionCodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&TranspositionKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let mut key = TranspositionKey { kind: self.kind, order: StackVec::new() };
+        let mut order = [0u8; MAX_WIDTH];
+
+        // each segment of the key space is a width, and the digits are the
+        // Lehmer code of the column order
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            let width = digits.len();
+            decode_lehmer_code(digits, &mut order[..width]);
+
+            if key.order.len() != width {
+                key.order.resize_with(width, || 0);
+            }
+
+            for (i, column) in order[..width].iter().enumerate() {
+                key.order[i] = *column;
+            }
+
+            key_callback(&key);
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<TranspositionKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let mut order = [0u8; MAX_WIDTH];
+        decode_lehmer_code(&digits, &mut order[..digits.len()]);
+
+        let mut key = TranspositionKey { kind: self.kind, order: StackVec::new() };
+        for column in order[..digits.len()].iter() {
+            key.order.push(*column);
+        }
+
+        Some(key)
+    }
+}
+
+pub struct TranspositionCipher {
+    kind: TranspositionKind,
+    key_space: MixedRadixKeySpace,
+}
+
+impl TranspositionCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<TranspositionCipher> {
+        let config: TranspositionConfig = parse_config(config)?;
+
+        let mut segments = Vec::new();
+        for width in config.min_width..=config.max_width {
+            segments.push(lehmer_code_radices(width));
+        }
+
+        Ok(TranspositionCipher {
+            kind: config.kind,
+            key_space: MixedRadixKeySpace::new(segments),
+        })
+    }
+}
+
+impl Cipher for TranspositionCipher {
+    type Key = TranspositionKey;
+    type Context = TranspositionWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        TranspositionWorkletContext {
+            kind: self.kind,
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &TranspositionKey, rng: &mut R) -> Option<TranspositionKey> {
+        // swap two different columns, so that the order stays a permutation.
+        // keys always have at least 2 columns
+        let mut key = key.clone();
+        let width = key.order.len();
+        let a = rng.random_range(0..width);
+        let b = (a + rng.random_range(1..width)) % width;
+        let column_a = key.order[a];
+        key.order[a] = key.order[b];
+        key.order[b] = column_a;
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &TranspositionKey, b: &TranspositionKey, rng: &mut R) -> Option<TranspositionKey> {
+        // orders of different widths can't be mixed
+        let width = a.order.len();
+        if b.order.len() != width {
+            return Some(a.clone());
+        }
+
+        let mut a_order = [0u8; MAX_WIDTH];
+        let mut b_order = [0u8; MAX_WIDTH];
+        let mut order = [0u8; MAX_WIDTH];
+        for i in 0..width {
+            (a_order[i], b_order[i]) = (a.order[i], b.order[i]);
+        }
+
+        order_crossover(&a_order[..width], &b_order[..width], &mut order[..width], rng);
+
+        let mut child = TranspositionKey { kind: a.kind, order: StackVec::new() };
+        for column in order[..width].iter() {
+            child.order.push(*column);
+        }
+
+        Some(child)
+    }
+}
+
+impl CipherFactory for TranspositionCipher {
+    const NAME: &'static str = "transposition";
+    const DESCRIPTION: &'static str = "Columnar, rail fence or route transposition";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(kind: Columnar | RailFence | Route = Columnar, min_width: 2..=16 = 2, max_width: 2..=16)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(TranspositionCipher::new(config)?))
+    }
+}
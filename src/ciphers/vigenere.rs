@@ -0,0 +1,364 @@
+use std::{error::Error, str::FromStr};
+
+use prost::Message;
+use rand::Rng;
+use rug::Integer;
+
+use crate::{ciphers::{CipherFactory, CipherVisitor, config::{CipherConfig, check_min_max, check_range, parse_config}, base::{Cipher, CipherCodecContext, CipherKey, CipherWorkletContext, StandardCipherError}, key_space::MixedRadixKeySpace}, data::message::InterleavedMessageData, utils::{run::AnyErrorResult, stackvec::StackVec}};
+
+const MAX_PERIOD: usize = 32;
+
+/**
+ * How shifts are applied. Units are ciphertext (c), plaintext (p) and shift (k)
+ * - Vigenere: c = p + k
+ * - Beaufort: c = k - p (reciprocal; decryption is the same operation)
+ * - VariantBeaufort: c = p - k
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize)]
+pub enum VigenereMode {
+    #[default]
+    Vigenere,
+    Beaufort,
+    VariantBeaufort,
+}
+
+impl VigenereMode {
+    pub const ALL: [VigenereMode; 3] = [VigenereMode::Vigenere, VigenereMode::Beaufort, VigenereMode::VariantBeaufort];
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Self::Vigenere => "vigenere",
+            Self::Beaufort => "beaufort",
+            Self::VariantBeaufort => "variant-beaufort",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VigenereConfig {
+    /// Size of the alphabet. Units greater or equal to this are left as-is
+    modulus: u16,
+    #[serde(default = "default_min_period")]
+    min_period: usize,
+    max_period: usize,
+    #[serde(default)]
+    mode: VigenereMode,
+}
+
+fn default_min_period() -> usize { 1 }
+
+impl CipherConfig for VigenereConfig {
+    fn validate(&self) -> Result<(), StandardCipherError> {
+        check_range("modulus", self.modulus, 1..=256)?;
+        check_range("min_period", self.min_period, 1..=MAX_PERIOD)?;
+        check_range("max_period", self.max_period, 1..=MAX_PERIOD)?;
+        check_min_max("min_period", self.min_period, "max_period", self.max_period)
+    }
+}
+
+#[derive(prost::Message)]
+struct EncodedVigenereKey {
+    /// Index in VigenereMode::ALL
+    #[prost(uint32, tag = "1")]
+    pub mode: u32,
+    #[prost(uint32, tag = "2")]
+    pub modulus: u32,
+    #[prost(bytes = "vec", tag = "3")]
+    pub shifts: Vec<u8>,
+}
+
+/**
+ * The mode and modulus are part of the key, since codec contexts only have
+ * access to the key
+ */
+#[derive(Clone)]
+pub struct VigenereKey {
+    pub mode: VigenereMode,
+    /** range: 1-256 */
+    pub modulus: u16,
+    /** one per unit in the period, each in the range 0..modulus */
+    pub shifts: StackVec<u8, MAX_PERIOD>,
+}
+
+/**
+ * Formats keys made of a list of shifts as "[name mod modulus: shift,...]".
+ * Shared by all ciphers of the Vigenere family
+ */
+pub(crate) fn format_shift_key<'a, I: Iterator<Item = &'a u8>>(name: &str, modulus: u16, shifts: I) -> String {
+    let shifts: Vec<String> = shifts.map(|shift| shift.to_string()).collect();
+    format!("[{} mod {}: {}]", name, modulus, shifts.join(","))
+}
+
+/**
+ * Parses keys in the format of format_shift_key, returning the name, modulus
+ * and shifts. Shifts are validated with validate_shift_key
+ */
+pub(crate) fn parse_shift_key<const MAX_LEN: usize>(s: &str) -> Result<(&str, u16, StackVec<u8, MAX_LEN>), Box<dyn Error>> {
+    let inner = s.trim().strip_prefix('[').and_then(|x| x.strip_suffix(']')).ok_or_else(|| {
+        StandardCipherError::BadKey { msg: "Key must be wrapped in square brackets".into() }
+    })?;
+
+    let (header, shifts_str) = inner.split_once(':').ok_or_else(|| {
+        StandardCipherError::BadKey { msg: "Expected a key in the format \"[name mod modulus: shift,shift,...]\"".into() }
+    })?;
+
+    let (name, modulus) = header.trim().split_once(" mod ").ok_or_else(|| {
+        StandardCipherError::BadKey { msg: "Expected name and modulus, separated by \" mod \"".into() }
+    })?;
+
+    let modulus = modulus.trim().parse::<u16>().or(Err(StandardCipherError::BadKey { msg: "Invalid modulus".into() }))?;
+
+    let mut shifts = StackVec::new();
+    for shift in shifts_str.split(',') {
+        if shifts.len() >= MAX_LEN {
+            return Err(StandardCipherError::BadKey { msg: format!("Max shift count ({MAX_LEN}) exceeded").into() }.into());
+        }
+
+        shifts.push(shift.trim().parse::<u8>().or(Err(StandardCipherError::BadKey { msg: format!("Invalid shift \"{}\"", shift.trim()).into() }))?);
+    }
+
+    validate_shift_key(modulus, &shifts)?;
+    Ok((name.trim(), modulus, shifts))
+}
+
+pub(crate) fn validate_shift_key<const MAX_LEN: usize>(modulus: u16, shifts: &StackVec<u8, MAX_LEN>) -> Result<(), Box<dyn Error>> {
+    if modulus == 0 || modulus > 256 {
+        Err(StandardCipherError::BadKey { msg: "Modulus must be in the range 1..=256".into() }.into())
+    } else if shifts.len() == 0 {
+        Err(StandardCipherError::BadKey { msg: "Key must have at least one shift".into() }.into())
+    } else if shifts.iter().any(|shift| *shift as u16 >= modulus) {
+        Err(StandardCipherError::BadKey { msg: "Shifts must be less than the modulus".into() }.into())
+    } else {
+        Ok(())
+    }
+}
+
+/**
+ * Segments for a MixedRadixKeySpace of shift keys, with one segment per key
+ * length, and one digit per shift
+ */
+pub(crate) fn make_shift_key_segments(modulus: u16, min_len: usize, max_len: usize) -> Vec<Box<[u32]>> {
+    let mut segments = Vec::new();
+    for len in min_len..=max_len {
+        segments.push(vec![modulus as u32; len].into_boxed_slice());
+    }
+
+    segments
+}
+
+/**
+ * Changes a random shift to a different value. Ciphers of the Vigenere family
+ * only mutate shifts, so the key length never changes when mutating
+ */
+pub(crate) fn mutate_shift_key<R: Rng, const MAX_LEN: usize>(modulus: u16, shifts: &mut StackVec<u8, MAX_LEN>, rng: &mut R) {
+    if shifts.len() == 0 || modulus < 2 { return }
+
+    let i = rng.random_range(0..shifts.len());
+    // add a non-zero offset, so that the shift always changes
+    shifts[i] = ((shifts[i] as u16 + rng.random_range(1..modulus)) % modulus) as u8;
+}
+
+/**
+ * Uniform crossover of two shift keys. The child has the length of the first
+ * parent, so shifts past the end of the second parent are always taken from
+ * the first parent
+ */
+pub(crate) fn crossover_shift_keys<R: Rng, const MAX_LEN: usize>(a: &StackVec<u8, MAX_LEN>, b: &StackVec<u8, MAX_LEN>, rng: &mut R) -> StackVec<u8, MAX_LEN> {
+    let mut child = a.clone();
+    for i in 0..a.len().min(b.len()) {
+        if rng.random_bool(0.5) {
+            child[i] = b[i];
+        }
+    }
+
+    child
+}
+
+impl ToString for VigenereKey {
+    fn to_string(&self) -> String {
+        format_shift_key(self.mode.get_name(), self.modulus, self.shifts.iter())
+    }
+}
+
+impl FromStr for VigenereKey {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode_name, modulus, shifts) = parse_shift_key(s)?;
+        let mode = *VigenereMode::ALL.iter().find(|mode| mode.get_name() == mode_name).ok_or_else(|| {
+            StandardCipherError::BadKey { msg: format!("Unknown mode \"{mode_name}\"").into() }
+        })?;
+
+        Ok(VigenereKey { mode, modulus, shifts })
+    }
+}
+
+impl CipherKey for VigenereKey {
+    fn encode_to_buffer(&self) -> Box<[u8]> {
+        EncodedVigenereKey {
+            mode: VigenereMode::ALL.iter().position(|mode| *mode == self.mode).unwrap() as u32,
+            modulus: self.modulus as u32,
+            shifts: self.shifts.iter().copied().collect(),
+        }.encode_to_vec().into()
+    }
+
+    fn from_buffer(buffer: &Box<[u8]>) -> Result<Self, Box<dyn Error>> {
+        let enc_key = EncodedVigenereKey::decode(buffer.iter().as_slice())?;
+        if enc_key.shifts.len() > MAX_PERIOD {
+            return Err("Max period exceeded".into());
+        }
+
+        let mut key = VigenereKey {
+            mode: *VigenereMode::ALL.get(enc_key.mode as usize).ok_or("Unknown mode")?,
+            modulus: enc_key.modulus.try_into()?,
+            shifts: StackVec::new(),
+        };
+
+        for shift in enc_key.shifts {
+            key.shifts.push(shift);
+        }
+
+        validate_shift_key(key.modulus, &key.shifts)?;
+        Ok(key)
+    }
+}
+
+pub struct VigenereCodecContext<'codec, const DECRYPT: bool> {
+    key: &'codec VigenereKey,
+    input_messages: &'codec InterleavedMessageData,
+}
+
+impl<'codec, const DECRYPT: bool> CipherCodecContext<'codec, DECRYPT, VigenereKey> for VigenereCodecContext<'codec, DECRYPT> {
+    fn new(input_messages: &'codec InterleavedMessageData, key: &'codec VigenereKey) -> Self {
+        VigenereCodecContext { input_messages, key }
+    }
+
+    fn get_input_messages(&self) -> &InterleavedMessageData {
+        self.input_messages
+    }
+
+    #[inline(always)]
+    unsafe fn get_output_unchecked(&self, message_index: usize, unit_index: usize) -> u8 {
+        // SAFETY: bounds must be verified by caller
+        let unit = unsafe { *self.input_messages.get_unchecked(message_index, unit_index) } as u16;
+        let modulus = self.key.modulus;
+        if unit >= modulus { return unit as u8 }
+
+        // SAFETY: keys always have at least one shift, so the modulo is always
+        //         in-bounds
+        let shift = unsafe { *self.key.shifts.get_unchecked(unit_index % self.key.shifts.len()) } as u16;
+
+        // all operands are less than the modulus, so adding the modulus
+        // before subtracting never underflows
+        let output = match (self.key.mode, DECRYPT) {
+            (VigenereMode::Vigenere, false) | (VigenereMode::VariantBeaufort, true) => unit + shift,
+            (VigenereMode::Vigenere, true) | (VigenereMode::VariantBeaufort, false) => unit + modulus - shift,
+            (VigenereMode::Beaufort, _) => shift + modulus - unit,
+        };
+
+        (output % modulus) as u8
+    }
+}
+
+pub struct VigenereWorkletContext {
+    mode: VigenereMode,
+    modulus: u16,
+    key_space: MixedRadixKeySpace,
+    range: (Integer, Integer),
+}
+
+impl CipherWorkletContext<VigenereKey> for VigenereWorkletContext {
+    type CodecContext<'codec, const DECRYPT: bool> = VigenereCodecContext<'codec, DECRYPT>;
+
+    fn get_total_keys(&self) -> Integer {
+        Integer::from(&self.range.1 - &self.range.0)
+    }
+
+    fn get_total_chunks(&self) -> Option<Integer> {
+        Some(self.key_space.get_range_chunks(&self.range))
+    }
+
+    fn permute_keys_interruptible_from<KC: FnMut(&VigenereKey), CC: FnMut(u32) -> bool>(&self, start_chunk: &Integer, mut key_callback: KC, chunk_callback: CC) {
+        let mut key = VigenereKey { mode: self.mode, modulus: self.modulus, shifts: StackVec::new() };
+
+        // each segment of the key space is a period, and each digit a shift
+        self.key_space.permute_range(&self.range, start_chunk, |_, digits| {
+            if key.shifts.len() != digits.len() {
+                key.shifts.resize_with(digits.len(), || 0);
+            }
+
+            for (i, digit) in digits.iter().enumerate() {
+                key.shifts[i] = *digit as u8;
+            }
+
+            key_callback(&key);
+        }, chunk_callback);
+    }
+
+    fn random_key<R: Rng>(&self, rng: &mut R) -> Option<VigenereKey> {
+        let (_, digits) = self.key_space.random_in_range(&self.range, rng)?;
+        let mut key = VigenereKey { mode: self.mode, modulus: self.modulus, shifts: StackVec::new() };
+        for digit in digits {
+            key.shifts.push(digit as u8);
+        }
+
+        Some(key)
+    }
+}
+
+pub struct VigenereCipher {
+    mode: VigenereMode,
+    modulus: u16,
+    key_space: MixedRadixKeySpace,
+}
+
+impl VigenereCipher {
+    pub fn new(config: Option<&str>) -> AnyErrorResult<VigenereCipher> {
+        let config: VigenereConfig = parse_config(config)?;
+
+        Ok(VigenereCipher {
+            mode: config.mode,
+            modulus: config.modulus,
+            key_space: MixedRadixKeySpace::new(make_shift_key_segments(config.modulus, config.min_period, config.max_period)),
+        })
+    }
+}
+
+impl Cipher for VigenereCipher {
+    type Key = VigenereKey;
+    type Context = VigenereWorkletContext;
+
+    fn get_max_parallelism(&self) -> u32 {
+        self.key_space.get_max_parallelism()
+    }
+
+    fn create_worklet_context_parallel(&self, worklet_id: u32, worklet_total: u32) -> Self::Context {
+        VigenereWorkletContext {
+            mode: self.mode,
+            modulus: self.modulus,
+            key_space: self.key_space.clone(),
+            range: self.key_space.get_worklet_range(worklet_id, worklet_total),
+        }
+    }
+
+    fn mutate_key<R: Rng>(&self, key: &VigenereKey, rng: &mut R) -> Option<VigenereKey> {
+        let mut key = key.clone();
+        mutate_shift_key(self.modulus, &mut key.shifts, rng);
+        Some(key)
+    }
+
+    fn crossover_keys<R: Rng>(&self, a: &VigenereKey, b: &VigenereKey, rng: &mut R) -> Option<VigenereKey> {
+        Some(VigenereKey { mode: a.mode, modulus: a.modulus, shifts: crossover_shift_keys(&a.shifts, &b.shifts, rng) })
+    }
+}
+
+impl CipherFactory for VigenereCipher {
+    const NAME: &'static str = "vigenere";
+    const DESCRIPTION: &'static str = "Vigenere, Beaufort or variant Beaufort cipher over an arbitrary modulus";
+    const CONFIG_SCHEMA: Option<&'static str> = Some("(modulus: 1..=256, min_period: 1..=32 = 1, max_period: 1..=32, mode: Vigenere | Beaufort | VariantBeaufort = Vigenere)");
+
+    fn deserialise<V: CipherVisitor>(config: Option<&str>, visitor: V) -> AnyErrorResult<V::Output> {
+        Ok(visitor.visit(VigenereCipher::new(config)?))
+    }
+}
@@ -1,6 +1,6 @@
-use std::path::PathBuf;
+use std::{io::Write, path::PathBuf};
 
-use crate::{analysis::alphabet::Alphabet, utils::run::AnyErrorResult};
+use crate::{analysis::alphabet::Alphabet, utils::run::{AnyErrorResult, UnitResult}};
 
 use super::format_error::{InvalidFormatError, InvalidFormatErrorKind};
 
@@ -53,4 +53,20 @@ pub fn import_csv_alphabet_or_default(path: &Option<PathBuf>) -> AnyErrorResult<
         Some(p) => import_csv_alphabet(p)?,
         None => Alphabet::default(),
     })
+}
+
+/**
+ * Exports an alphabet in the same format as import_csv_alphabet. Units without
+ * a grapheme can't be represented in the format, so they're skipped
+ */
+pub fn export_csv_alphabet(path: &PathBuf, alphabet: &Alphabet) -> UnitResult {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(alphabet.get_name().as_bytes())?;
+
+    for (u, alpha_unit) in alphabet.iter_units() {
+        if !alpha_unit.is_printable() { continue }
+        file.write_all(format!("\n{},{},{}", u, alpha_unit.grapheme, alpha_unit.weight).as_bytes())?;
+    }
+
+    Ok(())
 }
\ No newline at end of file
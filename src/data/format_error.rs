@@ -11,6 +11,11 @@ pub enum InvalidFormatErrorKind {
     MissingAlphabetWeight,
     EmptyAlphabet,
     NoMessages,
+    MissingModelName,
+    InvalidNgramLength,
+    MissingNgramLogProb,
+    EmptyModel,
+    UnpairedGlyphRow,
 }
 
 #[derive(Debug)]
@@ -32,6 +37,11 @@ impl fmt::Display for InvalidFormatError {
             InvalidFormatErrorKind::MissingAlphabetWeight => "missing weight for alphabet unit",
             InvalidFormatErrorKind::EmptyAlphabet => "empty alphabet",
             InvalidFormatErrorKind::NoMessages => "no messages",
+            InvalidFormatErrorKind::MissingModelName => "missing language model name",
+            InvalidFormatErrorKind::InvalidNgramLength => "n-gram is empty or too long",
+            InvalidFormatErrorKind::MissingNgramLogProb => "missing log-probability for n-gram",
+            InvalidFormatErrorKind::EmptyModel => "empty language model (or no n-grams in the alphabet)",
+            InvalidFormatErrorKind::UnpairedGlyphRow => "glyph row without a pair (glyphs are read in pairs of rows)",
         }, self.row + 1, self.col + 1)
     }
 }
@@ -0,0 +1,179 @@
+use std::{error::Error, fmt};
+
+use super::message::MessageData;
+
+/*
+ * Eye glyphs are stored as grids of eye directions (0 to 4), which are read in
+ * pairs of rows. Each pair of rows is a strip of triangles that alternate
+ * between pointing down (2 eyes on the top row, 1 on the bottom row) and
+ * pointing up (1 eye on the top row, 2 on the bottom row):
+ *
+ *   T0 T1 T2 T3 T4 T5
+ *   B0 B1 B2 B3 B4 B5
+ *
+ * is read as the trigrams (T0 T1 B0), (T2 B1 B2), (T3 T4 B3), (T5 B4 B5), where
+ * the eyes of each trigram are listed top row first, left to right. Readings
+ * can mirror or flip the triangles before listing their eyes, which changes
+ * both the order of the triangles and the position of the eyes in each of
+ * them, since the eyes of a triangle always stay together. A reading then
+ * decides how each trigram is turned into a unit.
+ */
+
+pub const GLYPH_DIRECTIONS: usize = 5;
+
+#[derive(Debug)]
+pub enum GlyphReadingError {
+    BadOrder,
+    UnitOverflow,
+}
+
+impl fmt::Display for GlyphReadingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadOrder => write!(f, "Trigram read order must be a permutation of 0, 1 and 2"),
+            Self::UnitOverflow => write!(f, "Some trigrams would be read as units greater than 255; use a smaller base or smaller direction values"),
+        }
+    }
+}
+
+impl Error for GlyphReadingError {}
+
+/// Eye directions of a message, one row per vector
+pub struct GlyphGrid {
+    pub name: Box<str>,
+    pub rows: Vec<Vec<u8>>,
+}
+
+/**
+ * How trigrams are turned into units. The unit of a trigram is
+ * v0 * base^2 + v1 * base + v2, where v0 to v2 are the values of the eyes'
+ * directions, in the read order. The default reading is the usual base-5
+ * reading, where each direction is its own value
+ */
+#[derive(Clone, Debug)]
+pub struct GlyphReading {
+    /// indices of the eyes of a trigram (top row first, left to right) in the
+    /// order they're read
+    pub order: [u8; 3],
+    /// mirror each pair of rows horizontally, so that its triangles are read
+    /// from right to left, and the left and right eyes of each triangle swap
+    pub mirror: bool,
+    /// flip each pair of rows vertically, so that triangles pointing down point
+    /// up, and vice versa
+    pub flip: bool,
+    /// value of each eye direction. Useful for rotated orientations, where a
+    /// direction means a different direction
+    pub direction_values: [u8; GLYPH_DIRECTIONS],
+    pub base: u8,
+}
+
+impl Default for GlyphReading {
+    fn default() -> Self {
+        Self { order: [0, 1, 2], mirror: false, flip: false, direction_values: [0, 1, 2, 3, 4], base: 5 }
+    }
+}
+
+impl fmt::Display for GlyphReading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [o0, o1, o2] = self.order;
+        let [v0, v1, v2, v3, v4] = self.direction_values;
+        write!(f, "order {}{}{}, base {}, values {}{}{}{}{}", o0, o1, o2, self.base, v0, v1, v2, v3, v4)?;
+        if self.mirror { write!(f, ", mirrored")?; }
+        if self.flip { write!(f, ", flipped")?; }
+        Ok(())
+    }
+}
+
+impl GlyphReading {
+    pub fn validate(&self) -> Result<(), GlyphReadingError> {
+        let mut sorted_order = self.order;
+        sorted_order.sort_unstable();
+        if sorted_order != [0, 1, 2] {
+            return Err(GlyphReadingError::BadOrder);
+        }
+
+        let max_value = *self.direction_values.iter().max().unwrap() as u32;
+        let base = self.base as u32;
+        if max_value * (base * base + base + 1) > u8::MAX as u32 {
+            return Err(GlyphReadingError::UnitOverflow);
+        }
+
+        Ok(())
+    }
+
+    /// Readings with the same settings as this one, but with every read order
+    pub fn with_all_orders(&self) -> Vec<GlyphReading> {
+        [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]].into_iter()
+            .map(|order| GlyphReading { order, ..self.clone() })
+            .collect()
+    }
+
+    /**
+     * Eyes of each trigram of a grid, top row first, left to right, after
+     * mirroring and flipping. Trigrams are read until one of them is
+     * incomplete, which happens at the end of a pair of rows, or if the rows
+     * of a pair have different lengths. A trailing row without a pair is
+     * ignored
+     */
+    pub fn get_trigrams(&self, grid: &GlyphGrid) -> Vec<[u8; 3]> {
+        let mut trigrams = Vec::new();
+
+        for pair in grid.rows.chunks_exact(2) {
+            let (top, bottom) = (&pair[0], &pair[1]);
+
+            // triangles are split before transforming them, since mirroring or
+            // flipping the rows first would group eyes of different triangles
+            let mut triangles = Vec::new();
+            let mut c = 0;
+            loop {
+                let (Some(&t0), Some(&t1), Some(&b0)) = (top.get(c), top.get(c + 1), bottom.get(c)) else { break };
+                triangles.push((true, [t0, t1, b0]));
+
+                let (Some(&t2), Some(&b1), Some(&b2)) = (top.get(c + 2), bottom.get(c + 1), bottom.get(c + 2)) else { break };
+                triangles.push((false, [t2, b1, b2]));
+
+                c += 3;
+            }
+
+            if self.mirror {
+                triangles.reverse();
+            }
+
+            for (pointing_down, [e0, e1, e2]) in triangles {
+                let eyes = match (pointing_down, self.mirror, self.flip) {
+                    (_, false, false) => [e0, e1, e2],
+                    // left and right eyes are on the top row when pointing
+                    // down, and on the bottom row when pointing up
+                    (true, true, false) => [e1, e0, e2],
+                    (false, true, false) => [e0, e2, e1],
+                    // flipping moves the lone eye to the other row, keeping
+                    // the left and right eyes in place
+                    (true, false, true) => [e2, e0, e1],
+                    (false, false, true) => [e1, e2, e0],
+                    // mirroring and flipping is a half turn
+                    (_, true, true) => [e2, e1, e0],
+                };
+
+                trigrams.push(eyes);
+            }
+        }
+
+        trigrams
+    }
+
+    /// Unit of a trigram. The reading must be valid, and the eyes must be
+    /// valid directions
+    pub fn get_trigram_unit(&self, eyes: [u8; 3]) -> u8 {
+        let base = self.base as u32;
+        let mut unit = 0u32;
+        for i in self.order {
+            unit = unit * base + self.direction_values[eyes[i as usize] as usize] as u32;
+        }
+
+        unit as u8
+    }
+
+    pub fn read_grid(&self, grid: &GlyphGrid) -> MessageData {
+        self.get_trigrams(grid).into_iter().map(|eyes| self.get_trigram_unit(eyes)).collect()
+    }
+}
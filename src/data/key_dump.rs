@@ -1,3 +1,43 @@
+use std::{error::Error, fmt, fs::{File, OpenOptions}, io::{BufReader, Read, Seek, SeekFrom, Write}, path::PathBuf};
+
+use prost::Message;
+
+use crate::{data::length_delimited::read_length_delimited_message, utils::run::{AnyErrorResult, UnitResult}};
+
+/*
+ * Key dump file format:
+ * - KEY_DUMP_MAGIC
+ * - format version (u32, little endian)
+ * - length-delimited KeyDumpMeta
+ * - length-delimited KeyDumpRecord, repeated until the end of the file
+ */
+
+pub const KEY_DUMP_MAGIC: &[u8; 8] = b"NEMKDUMP";
+pub const KEY_DUMP_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum KeyDumpError {
+    BadMagic,
+    UnsupportedVersion { version: u32 },
+    TruncatedRecord,
+    MetaMismatch { field: &'static str },
+    BadResumeLength { len: u64, header_len: u64, file_len: u64 },
+}
+
+impl fmt::Display for KeyDumpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "Not a key dump file (bad magic header)"),
+            Self::UnsupportedVersion { version } => write!(f, "Unsupported key dump version {} (expected {})", version, KEY_DUMP_VERSION),
+            Self::TruncatedRecord => write!(f, "Truncated record in key dump"),
+            Self::MetaMismatch { field } => write!(f, "Key dump belongs to a different search ({} doesn't match)", field),
+            Self::BadResumeLength { len, header_len, file_len } => write!(f, "Can't resume key dump at byte {} (expected a length from {} to {})", len, header_len, file_len),
+        }
+    }
+}
+
+impl Error for KeyDumpError {}
+
 #[derive(prost::Message)]
 pub struct KeyDumpMeta {
     #[prost(string, tag = "1")]
@@ -6,4 +46,183 @@ pub struct KeyDumpMeta {
     pub cipher_name: String,
     #[prost(string, optional, tag = "3")]
     pub cipher_config: Option<String>,
-}
\ No newline at end of file
+    #[prost(string, tag = "4")]
+    pub condition: String,
+    /// See hash_message_list
+    #[prost(uint64, tag = "5")]
+    pub data_hash: u64,
+    #[prost(string, tag = "6")]
+    pub alphabet_name: String,
+    /// Seconds since the unix epoch when the key dump was created
+    #[prost(uint64, tag = "7")]
+    pub timestamp: u64,
+    #[prost(bool, tag = "8")]
+    pub decrypt: bool,
+}
+
+#[derive(prost::Message)]
+pub struct KeyDumpRecord {
+    /// See CipherKey::encode_to_buffer
+    #[prost(bytes = "vec", tag = "1")]
+    pub net_key: Vec<u8>,
+    /// Only set by searches that rank keys by a score, like top-K searches
+    #[prost(double, optional, tag = "2")]
+    pub score: Option<f64>,
+}
+
+pub struct KeyDumpWriter {
+    file: File,
+    len: u64,
+}
+
+impl KeyDumpWriter {
+    /**
+     * Creates a new key dump file. Fails if the file already exists
+     */
+    pub fn create(path: &PathBuf, meta: &KeyDumpMeta) -> AnyErrorResult<Self> {
+        let mut file = File::create_new(path)?;
+        let mut buffer = Vec::from(KEY_DUMP_MAGIC.as_slice());
+        buffer.extend_from_slice(&KEY_DUMP_VERSION.to_le_bytes());
+        meta.encode_length_delimited(&mut buffer)?;
+        file.write_all(buffer.as_slice())?;
+
+        Ok(Self { file, len: buffer.len() as u64 })
+    }
+
+    /**
+     * Opens an existing key dump file for appending more records, discarding
+     * everything after the first len bytes. len must be a value previously
+     * returned by sync, otherwise the file will be corrupted. The stored
+     * metadata must match meta (except for the build hash and timestamp), so
+     * that key dumps of other searches aren't cut
+     */
+    pub fn resume(path: &PathBuf, len: u64, meta: &KeyDumpMeta) -> AnyErrorResult<Self> {
+        let (stored_meta, header_len, file_len) = {
+            let mut reader = KeyDumpReader::open(path)?;
+            let header_len = reader.reader.stream_position()?;
+            (reader.meta, header_len, reader.file_len)
+        };
+
+        let mismatched_field = if stored_meta.cipher_name != meta.cipher_name || stored_meta.cipher_config != meta.cipher_config {
+            Some("cipher")
+        } else if stored_meta.condition != meta.condition {
+            Some("condition")
+        } else if stored_meta.data_hash != meta.data_hash {
+            Some("message data")
+        } else if stored_meta.alphabet_name != meta.alphabet_name {
+            Some("alphabet")
+        } else if stored_meta.decrypt != meta.decrypt {
+            Some("mode")
+        } else {
+            None
+        };
+
+        if let Some(field) = mismatched_field {
+            return Err(KeyDumpError::MetaMismatch { field }.into());
+        } else if len < header_len || len > file_len {
+            return Err(KeyDumpError::BadResumeLength { len, header_len, file_len }.into());
+        }
+
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        Ok(Self { file, len })
+    }
+
+    pub fn write_key(&mut self, net_key: &[u8]) -> UnitResult {
+        self.write_record(&KeyDumpRecord { net_key: net_key.into(), score: None })
+    }
+
+    pub fn write_scored_key(&mut self, net_key: &[u8], score: f64) -> UnitResult {
+        self.write_record(&KeyDumpRecord { net_key: net_key.into(), score: Some(score) })
+    }
+
+    fn write_record(&mut self, record: &KeyDumpRecord) -> UnitResult {
+        let buffer = record.encode_length_delimited_to_vec();
+        self.file.write_all(buffer.as_slice())?;
+        self.len += buffer.len() as u64;
+        Ok(())
+    }
+
+    /**
+     * Makes sure all records are written to the disk. Returns the length of
+     * the file, which can later be passed to resume
+     */
+    pub fn sync(&mut self) -> AnyErrorResult<u64> {
+        self.file.sync_data()?;
+        Ok(self.len)
+    }
+}
+
+pub struct KeyDumpReader {
+    reader: BufReader<File>,
+    meta: KeyDumpMeta,
+    /// no record can be longer than the file, so this bounds the length of
+    /// records in corrupted files
+    file_len: u64,
+}
+
+impl KeyDumpReader {
+    pub fn open(path: &PathBuf) -> AnyErrorResult<Self> {
+        let file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; KEY_DUMP_MAGIC.len()];
+        reader.read_exact(&mut magic).or(Err(KeyDumpError::BadMagic))?;
+        if magic != *KEY_DUMP_MAGIC {
+            return Err(KeyDumpError::BadMagic.into());
+        }
+
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version).or(Err(KeyDumpError::TruncatedRecord))?;
+        let version = u32::from_le_bytes(version);
+        if version != KEY_DUMP_VERSION {
+            return Err(KeyDumpError::UnsupportedVersion { version }.into());
+        }
+
+        let meta = match read_length_delimited_message(&mut reader, file_len)? {
+            Some(meta) => meta,
+            None => return Err(KeyDumpError::TruncatedRecord.into()),
+        };
+
+        Ok(Self { reader, meta, file_len })
+    }
+
+    pub fn get_meta(&self) -> &KeyDumpMeta {
+        &self.meta
+    }
+
+    /**
+     * Reads the next record, or None if the end of the file was reached
+     */
+    pub fn read_record(&mut self) -> AnyErrorResult<Option<KeyDumpRecord>> {
+        read_length_delimited_message::<_, KeyDumpRecord>(&mut self.reader, self.file_len)
+    }
+
+    /**
+     * Reads the next net key, or None if the end of the file was reached
+     */
+    pub fn read_key(&mut self) -> AnyErrorResult<Option<Box<[u8]>>> {
+        Ok(self.read_record()?.map(|record| record.net_key.into()))
+    }
+
+    pub fn read_all_records(&mut self) -> AnyErrorResult<Vec<KeyDumpRecord>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.read_record()? {
+            records.push(record);
+        }
+
+        Ok(records)
+    }
+
+    pub fn read_all_keys(&mut self) -> AnyErrorResult<Vec<Box<[u8]>>> {
+        let mut net_keys = Vec::new();
+        while let Some(net_key) = self.read_key()? {
+            net_keys.push(net_key);
+        }
+
+        Ok(net_keys)
+    }
+}
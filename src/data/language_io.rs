@@ -1,4 +1,10 @@
-use crate::{analysis::unit_freq::UnitFrequency, data::alphabet_io::import_csv_alphabet, utils::run::AnyErrorResult};
+use std::{io::Write, path::PathBuf};
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{analysis::{alphabet::Alphabet, ngram::{MAX_NGRAM_LEN, NgramCounts, NgramModel}, unit_freq::UnitFrequency}, data::alphabet_io::import_csv_alphabet, utils::run::{AnyErrorResult, UnitResult}};
+
+use super::format_error::{InvalidFormatError, InvalidFormatErrorKind};
 
 pub fn import_csv_languages(paths: &Vec<std::path::PathBuf>) -> AnyErrorResult<Vec<UnitFrequency>> {
     let mut freqs: Vec<UnitFrequency> = Vec::new();
@@ -8,4 +14,95 @@ pub fn import_csv_languages(paths: &Vec<std::path::PathBuf>) -> AnyErrorResult<V
     }
 
     Ok(freqs)
-}
\ No newline at end of file
+}
+
+/**
+ * Imports an n-gram language model. The format is similar to alphabet files: a
+ * line with the model's name, followed by "ngram,log_prob" lines, where ngram
+ * is a sequence of 1 to 4 graphemes, and log_prob is its log-probability (base
+ * 10). N-grams of different lengths can be mixed in the same file. Graphemes
+ * are mapped to units with the given alphabet, and n-grams with graphemes that
+ * aren't in the alphabet are skipped, so that a model can be used with
+ * alphabets that only have a subset of its letters
+ */
+pub fn import_csv_ngram_model(path: &PathBuf, alphabet: &Alphabet) -> AnyErrorResult<NgramModel> {
+    let csv = std::fs::read_to_string(path)?;
+    let mut name: Option<Box<str>> = None;
+    let mut ngrams: Vec<(Box<[u8]>, f64)> = Vec::new();
+    let mut r = 0;
+
+    for row in csv.split('\n') {
+        let row_trim = row.trim();
+        if !row_trim.is_empty() {
+            if name.is_none() {
+                name = Some(row_trim.into());
+            } else {
+                let cols: Vec<&str> = row.split(',').collect();
+                if cols.len() > 2 {
+                    return Err(InvalidFormatError { kind: InvalidFormatErrorKind::UnexpectedDatum, row: r, col: 2 }.into());
+                } else if cols.len() < 2 {
+                    return Err(InvalidFormatError { kind: InvalidFormatErrorKind::MissingNgramLogProb, row: r, col: cols.len() }.into());
+                }
+
+                let graphemes: Vec<&str> = cols[0].graphemes(true).collect();
+                if graphemes.is_empty() || graphemes.len() > MAX_NGRAM_LEN {
+                    return Err(InvalidFormatError { kind: InvalidFormatErrorKind::InvalidNgramLength, row: r, col: 0 }.into());
+                }
+
+                let log_prob = cols[1].trim().parse::<f64>().ok().filter(|x| x.is_finite()).ok_or(InvalidFormatError { kind: InvalidFormatErrorKind::InvalidDatum, row: r, col: 1 })?;
+                let units: Option<Box<[u8]>> = graphemes.iter().map(|g| alphabet.get_unit_idx(&(*g).into())).collect();
+                if let Some(units) = units {
+                    ngrams.push((units, log_prob));
+                }
+            }
+        }
+
+        r += 1;
+    }
+
+    let Some(name) = name else {
+        return Err(InvalidFormatError { kind: InvalidFormatErrorKind::MissingModelName, row: r, col: 0 }.into());
+    };
+
+    if ngrams.is_empty() {
+        return Err(InvalidFormatError { kind: InvalidFormatErrorKind::EmptyModel, row: r, col: 0 }.into());
+    }
+
+    Ok(NgramModel::new(name, &ngrams)?)
+}
+
+pub fn import_csv_ngram_models(paths: &Vec<PathBuf>, alphabet: &Alphabet) -> AnyErrorResult<Vec<NgramModel>> {
+    let mut models: Vec<NgramModel> = Vec::new();
+
+    for path in paths {
+        models.push(import_csv_ngram_model(path, alphabet)?);
+    }
+
+    Ok(models)
+}
+
+/**
+ * Exports n-gram counts as a language model, in the format read by
+ * import_csv_ngram_model. Units are written as their graphemes in the given
+ * alphabet, and n-grams that occurred less than min_count times are omitted
+ */
+pub fn export_csv_ngram_model(path: &PathBuf, name: &str, alphabet: &Alphabet, counts: &[NgramCounts], min_count: u64) -> UnitResult {
+    let mut file = std::fs::File::create(path)?;
+    file.write_all(name.as_bytes())?;
+
+    for ngram_counts in counts {
+        for (ngram, log_prob) in ngram_counts.get_log_probs(min_count) {
+            let mut graphemes = String::new();
+            for u in ngram {
+                match alphabet.get_unit(*u) {
+                    Some(alpha_unit) if alpha_unit.is_printable() => graphemes.push_str(&alpha_unit.grapheme),
+                    _ => return Err(format!("Unit {} has no grapheme in the alphabet, so it can't be exported", u).into()),
+                }
+            }
+
+            file.write_all(format!("\n{},{}", graphemes, log_prob).as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::{error::Error, fmt, io::{Read, Write}};
+
+use prost::Message;
+
+use crate::utils::run::{AnyErrorResult, UnitResult};
+
+#[derive(Debug)]
+pub enum LengthDelimitedError {
+    Truncated,
+    TooLong { len: u64, max_len: u64 },
+}
+
+impl fmt::Display for LengthDelimitedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "Truncated length-delimited record"),
+            Self::TooLong { len, max_len } => write!(f, "Length-delimited record is too long ({} bytes, maximum is {})", len, max_len),
+        }
+    }
+}
+
+impl Error for LengthDelimitedError {}
+
+/**
+ * Reads a varint length delimiter followed by that many bytes. Returns None if
+ * the reader is already at the end. Fails if the length is greater than
+ * max_len, so that corrupted or malicious inputs can't exhaust memory
+ */
+pub fn read_length_delimited<R: Read>(reader: &mut R, max_len: u64) -> AnyErrorResult<Option<Vec<u8>>> {
+    let mut len = 0u64;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            } else {
+                return Err(LengthDelimitedError::Truncated.into());
+            }
+        }
+
+        if shift >= 64 {
+            return Err(LengthDelimitedError::Truncated.into());
+        }
+
+        len |= ((byte[0] & 0x7f) as u64) << shift;
+        shift += 7;
+
+        if byte[0] & 0x80 == 0 { break }
+    }
+
+    if len > max_len {
+        return Err(LengthDelimitedError::TooLong { len, max_len }.into());
+    }
+
+    let mut buffer = vec![0u8; len.try_into()?];
+    reader.read_exact(&mut buffer).or(Err(LengthDelimitedError::Truncated))?;
+    Ok(Some(buffer))
+}
+
+pub fn read_length_delimited_message<R: Read, M: Message + Default>(reader: &mut R, max_len: u64) -> AnyErrorResult<Option<M>> {
+    Ok(match read_length_delimited(reader, max_len)? {
+        Some(buffer) => Some(M::decode(buffer.as_slice())?),
+        None => None,
+    })
+}
+
+pub fn write_length_delimited_message<W: Write, M: Message>(writer: &mut W, message: &M) -> UnitResult {
+    writer.write_all(message.encode_length_delimited_to_vec().as_slice())?;
+    Ok(())
+}
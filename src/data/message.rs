@@ -115,4 +115,37 @@ impl AcceleratedMessageList {
 
         Self { data: InterleavedMessageData { message_count, inner, unit_counts }, names }
     }
-}
\ No newline at end of file
+}
+
+/**
+ * FNV-1a hash of the names and units of all messages in a list. Used to detect
+ * if the message data changed between runs, so it must stay stable between
+ * builds (unlike std's DefaultHasher)
+ */
+pub fn hash_message_list(messages: &MessageList) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const FNV_PRIME: u64 = 0x100000001b3;
+
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut hash_byte = |b: u8| {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    };
+
+    for message in messages.iter() {
+        for b in message.name.as_bytes() {
+            hash_byte(*b);
+        }
+
+        // separator, so that names and units can't be confused
+        hash_byte(0xff);
+
+        for u in message.data.iter() {
+            hash_byte(*u);
+        }
+
+        hash_byte(0xff);
+    }
+
+    hash
+}
@@ -4,7 +4,7 @@ use unicode_segmentation::UnicodeSegmentation;
 
 use crate::{analysis::alphabet::Alphabet, utils::run::{AnyErrorResult, UnitResult}};
 
-use super::{format_error::{InvalidFormatError, InvalidFormatErrorKind}, message::{Message, MessageList}, render_message::{MessageRenderMap, RenderMessage, RenderMessageBuilder}};
+use super::{format_error::{InvalidFormatError, InvalidFormatErrorKind}, glyph::{GlyphGrid, GlyphReading}, message::{Message, MessageList}, render_message::{MessageRenderMap, RenderMessage, RenderMessageBuilder}};
 
 pub fn export_csv_messages(path: &std::path::PathBuf, messages: &MessageList) -> UnitResult {
     let mut file = std::fs::File::create(path)?;
@@ -117,10 +117,110 @@ pub fn import_txt_messages(path: &std::path::PathBuf, alphabet: &Alphabet) -> An
     Ok(MessageRenderMap::new(messages, render_messages))
 }
 
+/**
+ * Imports eye glyph grids. Each message starts with a line with its name,
+ * followed by lines with the eye directions (digits 0 to 4), one line per row
+ * of eyes. Lines starting with a digit are rows of eyes, so names must not
+ * start with a digit. Empty lines are ignored. See data::glyph for how rows
+ * are read, and data/ciphertext/sample.eyes for an example
+ */
+pub fn import_glyph_grids(path: &std::path::PathBuf) -> AnyErrorResult<Vec<GlyphGrid>> {
+    let txt = std::fs::read_to_string(path)?;
+
+    let mut grids = Vec::<GlyphGrid>::new();
+    let mut r = 0;
+    // rows of the last message name and the last row of eyes, for errors
+    let mut name_r = 0;
+    let mut eyes_r = 0;
+    let check_grid = |grids: &Vec<GlyphGrid>, name_r: usize, eyes_r: usize| -> UnitResult {
+        if let Some(grid) = grids.last() {
+            if grid.rows.is_empty() {
+                return Err(InvalidFormatError { kind: InvalidFormatErrorKind::EmptyMessage, row: name_r, col: 0 }.into());
+            } else if grid.rows.len() % 2 != 0 {
+                return Err(InvalidFormatError { kind: InvalidFormatErrorKind::UnpairedGlyphRow, row: eyes_r, col: 0 }.into());
+            }
+        }
+
+        Ok(())
+    };
+
+    for row in txt.split('\n') {
+        let row_trim = row.trim();
+        if !row_trim.is_empty() {
+            if row_trim.starts_with(|c: char| c.is_ascii_digit()) {
+                let Some(grid) = grids.last_mut() else {
+                    return Err(InvalidFormatError { kind: InvalidFormatErrorKind::EmptyMessageName, row: r, col: 0 }.into());
+                };
+
+                let mut eyes = Vec::new();
+                for (c, eye) in row_trim.chars().enumerate() {
+                    match eye.to_digit(10) {
+                        Some(direction) if direction < 5 => eyes.push(direction as u8),
+                        _ => return Err(InvalidFormatError { kind: InvalidFormatErrorKind::InvalidDatum, row: r, col: c }.into()),
+                    }
+                }
+
+                grid.rows.push(eyes);
+                eyes_r = r;
+            } else {
+                check_grid(&grids, name_r, eyes_r)?;
+                name_r = r;
+                grids.push(GlyphGrid { name: row_trim.into(), rows: Vec::new() });
+            }
+        }
+
+        r += 1;
+    }
+
+    check_grid(&grids, name_r, eyes_r)?;
+    if grids.is_empty() {
+        return Err(InvalidFormatError { kind: InvalidFormatErrorKind::NoMessages, row: r, col: 0 }.into());
+    }
+
+    Ok(grids)
+}
+
+/// Reads eye glyph grids into messages. Units that aren't in the alphabet are
+/// shown, but not included in the messages
+pub fn read_glyph_grids(grids: &[GlyphGrid], alphabet: &Alphabet, reading: &GlyphReading) -> AnyErrorResult<MessageRenderMap> {
+    reading.validate()?;
+
+    let mut messages = MessageList::default();
+    let mut render_messages = Vec::<RenderMessage>::new();
+    for grid in grids {
+        let mut message = Message::from_name(grid.name.clone());
+        let mut render_msg_builder = RenderMessageBuilder::new();
+
+        for unit in reading.read_grid(grid) {
+            if alphabet.get_unit(unit).is_some() {
+                message.data.push(unit);
+                render_msg_builder.push_unit(message.data.len() - 1);
+            } else {
+                render_msg_builder.push_non_unit_byte(unit);
+            }
+        }
+
+        if message.data.is_empty() {
+            return Err(format!("Message \"{}\" has no units with reading {}", grid.name, reading).into());
+        }
+
+        messages.push(message);
+        render_messages.push(render_msg_builder.done());
+    }
+
+    Ok(MessageRenderMap::new(messages, render_messages))
+}
+
+pub fn import_glyph_messages(path: &std::path::PathBuf, alphabet: &Alphabet, reading: &GlyphReading) -> AnyErrorResult<MessageRenderMap> {
+    read_glyph_grids(&import_glyph_grids(path)?, alphabet, reading)
+}
+
 pub fn import_messages(data_path: &std::path::PathBuf, alphabet: &Alphabet) -> AnyErrorResult<MessageRenderMap> {
     let ext = data_path.extension();
     if let Some(ext) = ext && ext.to_ascii_lowercase() == "txt" {
         import_txt_messages(data_path, alphabet)
+    } else if let Some(ext) = ext && ext.to_ascii_lowercase() == "eyes" {
+        import_glyph_messages(data_path, alphabet, &GlyphReading::default())
     } else {
         import_csv_messages(data_path, alphabet)
     }
@@ -1,7 +1,11 @@
 pub mod message;
 pub mod key_dump;
 pub mod message_io;
+pub mod glyph;
 pub mod format_error;
 pub mod language_io;
 pub mod alphabet_io;
-pub mod render_message;
\ No newline at end of file
+pub mod render_message;
+pub mod search_state;
+pub mod length_delimited;
+pub mod search_protocol;
\ No newline at end of file
@@ -1,11 +1,13 @@
-use super::message::MessageList;
+use super::message::{MessageDataList, MessageList};
 
+#[derive(Clone)]
 pub enum MessageRenderGroup {
     NonUnitText { grapheme: Box<str> },
     NonUnitByte { byte: u8 },
     UnitIndexRange { from: usize, to: usize },
 }
 
+#[derive(Clone)]
 pub struct RenderMessage {
     render_groups: Vec<MessageRenderGroup>,
     msg_len: usize,
@@ -55,6 +57,23 @@ impl MessageRenderMap {
     pub fn len(&self) -> usize {
         self.render_messages.len()
     }
+
+    /**
+     * Create a copy of this map with different unit data, but with the same
+     * message names and render groups. Useful for rendering cipher outputs. The
+     * data of each message must have the same amount of units as the original
+     * message
+     */
+    pub fn with_message_data(&self, message_data_list: MessageDataList) -> Self {
+        debug_assert!(message_data_list.len() == self.messages.len());
+        let mut messages = self.messages.clone();
+        for (message, data) in messages.iter_mut().zip(message_data_list) {
+            debug_assert!(message.data.len() == data.len());
+            message.data = data;
+        }
+
+        Self { messages, render_messages: self.render_messages.clone() }
+    }
 }
 
 pub struct RenderMessageBuilder {
@@ -97,7 +116,7 @@ impl RenderMessageBuilder {
 
     pub fn push_unit(&mut self, unit_idx: usize) {
         if let Some(range) = &mut self.next_unit_range {
-            debug_assert_eq!(unit_idx, range.1 + 1);
+            debug_assert_eq!(unit_idx, range.1);
             range.1 = unit_idx + 1;
         } else {
             self.flush();
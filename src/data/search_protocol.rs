@@ -0,0 +1,171 @@
+use std::io::{Read, Write};
+
+use crate::{data::length_delimited::{read_length_delimited_message, write_length_delimited_message}, utils::run::{AnyErrorResult, UnitResult}};
+
+/*
+ * Distributed search protocol. Every packet is a length-delimited protobuf
+ * message sent over TCP:
+ * - worker connects and sends Hello
+ * - coordinator replies with Welcome, or Rejected and closes the connection
+ * - worker sends RequestWork, coordinator replies with Assign when a range of
+ *   the key space is available, or Done when the whole search is finished
+ * - while searching an assigned range, the worker sends a Progress for each
+ *   fully checked chunk, and Finished or Error when the range is done
+ * - worker sends Heartbeat periodically, so that hung workers can be detected
+ * Each connection searches at most one range at a time. Worker processes open
+ * one connection per local worklet
+ */
+
+/// Maximum size of a single packet. Progress packets include matches, so this is generous
+pub const MAX_PACKET_LEN: u64 = 256 * 1024 * 1024;
+
+/**
+ * Sent by a worker right after connecting. The coordinator rejects workers
+ * that aren't searching the exact same thing as the coordinator
+ */
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WorkerHello {
+    #[prost(string, tag = "1")]
+    pub build_hash: String,
+    #[prost(string, tag = "2")]
+    pub cipher_name: String,
+    #[prost(string, optional, tag = "3")]
+    pub cipher_config: Option<String>,
+    #[prost(string, tag = "4")]
+    pub condition: String,
+    /// See hash_message_list
+    #[prost(uint64, tag = "5")]
+    pub data_hash: u64,
+    #[prost(bool, tag = "6")]
+    pub decrypt: bool,
+    /// Total keys of the cipher as a decimal string. Catches differences in the key space that aren't visible in the configuration, such as refining different key dumps
+    #[prost(string, tag = "7")]
+    pub keys_total: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Signal {}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Rejected {
+    #[prost(string, tag = "1")]
+    pub reason: String,
+}
+
+/**
+ * A range of the key space, as the worklet slice worklet_id/worklet_total, to
+ * be searched starting at the chunk with index start_chunk (a decimal string)
+ */
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Assign {
+    #[prost(uint32, tag = "1")]
+    pub worklet_id: u32,
+    #[prost(uint32, tag = "2")]
+    pub worklet_total: u32,
+    #[prost(string, tag = "3")]
+    pub start_chunk: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Progress {
+    #[prost(uint32, tag = "1")]
+    pub worklet_id: u32,
+    #[prost(uint32, tag = "2")]
+    pub keys: u32,
+    /// Matches in the chunk. See CipherKey::encode_to_buffer
+    #[prost(bytes = "vec", repeated, tag = "3")]
+    pub net_keys: Vec<Vec<u8>>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Finished {
+    #[prost(uint32, tag = "1")]
+    pub worklet_id: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WorkError {
+    #[prost(uint32, tag = "1")]
+    pub worklet_id: u32,
+    #[prost(string, tag = "2")]
+    pub message: String,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum WorkerPacketKind {
+    #[prost(message, tag = "1")]
+    Hello(WorkerHello),
+    #[prost(message, tag = "2")]
+    RequestWork(Signal),
+    #[prost(message, tag = "3")]
+    Progress(Progress),
+    #[prost(message, tag = "4")]
+    Finished(Finished),
+    #[prost(message, tag = "5")]
+    Error(WorkError),
+    #[prost(message, tag = "6")]
+    Heartbeat(Signal),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct WorkerPacket {
+    #[prost(oneof = "WorkerPacketKind", tags = "1, 2, 3, 4, 5, 6")]
+    pub kind: Option<WorkerPacketKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum CoordinatorPacketKind {
+    #[prost(message, tag = "1")]
+    Welcome(Signal),
+    #[prost(message, tag = "2")]
+    Rejected(Rejected),
+    #[prost(message, tag = "3")]
+    Assign(Assign),
+    #[prost(message, tag = "4")]
+    Done(Signal),
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CoordinatorPacket {
+    #[prost(oneof = "CoordinatorPacketKind", tags = "1, 2, 3, 4")]
+    pub kind: Option<CoordinatorPacketKind>,
+}
+
+pub fn send_worker_packet<W: Write>(writer: &mut W, kind: WorkerPacketKind) -> UnitResult {
+    write_length_delimited_message(writer, &WorkerPacket { kind: Some(kind) })?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn send_coordinator_packet<W: Write>(writer: &mut W, kind: CoordinatorPacketKind) -> UnitResult {
+    write_length_delimited_message(writer, &CoordinatorPacket { kind: Some(kind) })?;
+    writer.flush()?;
+    Ok(())
+}
+
+/**
+ * Reads the next packet, or None if the connection was closed. Packets with an
+ * unknown kind are skipped
+ */
+pub fn receive_worker_packet<R: Read>(reader: &mut R) -> AnyErrorResult<Option<WorkerPacketKind>> {
+    loop {
+        match read_length_delimited_message::<_, WorkerPacket>(reader, MAX_PACKET_LEN)? {
+            Some(WorkerPacket { kind: Some(kind) }) => return Ok(Some(kind)),
+            Some(WorkerPacket { kind: None }) => continue,
+            None => return Ok(None),
+        }
+    }
+}
+
+/**
+ * See receive_worker_packet
+ */
+pub fn receive_coordinator_packet<R: Read>(reader: &mut R) -> AnyErrorResult<Option<CoordinatorPacketKind>> {
+    loop {
+        match read_length_delimited_message::<_, CoordinatorPacket>(reader, MAX_PACKET_LEN)? {
+            Some(CoordinatorPacket { kind: Some(kind) }) => return Ok(Some(kind)),
+            Some(CoordinatorPacket { kind: None }) => continue,
+            None => return Ok(None),
+        }
+    }
+}
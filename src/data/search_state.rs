@@ -0,0 +1,69 @@
+use std::{io::Write, path::PathBuf};
+
+use prost::Message;
+
+use crate::utils::run::{AnyErrorResult, UnitResult};
+
+/**
+ * Progress of a single worklet. Big integers are stored as decimal strings,
+ * since protobuf has no big integer type
+ */
+#[derive(prost::Message)]
+pub struct WorkletState {
+    /// Amount of chunks fully checked, which is also the index of the chunk to resume from
+    #[prost(string, tag = "1")]
+    pub chunks_done: String,
+    /// Amount of keys in all fully checked chunks
+    #[prost(string, tag = "2")]
+    pub keys_checked: String,
+    #[prost(bool, tag = "3")]
+    pub finished: bool,
+}
+
+#[derive(prost::Message)]
+pub struct SearchState {
+    #[prost(string, tag = "1")]
+    pub build_hash: String,
+    #[prost(string, tag = "2")]
+    pub cipher_name: String,
+    #[prost(string, optional, tag = "3")]
+    pub cipher_config: Option<String>,
+    #[prost(string, tag = "4")]
+    pub condition: String,
+    /// See hash_message_list
+    #[prost(uint64, tag = "5")]
+    pub data_hash: u64,
+    #[prost(bool, tag = "6")]
+    pub decrypt: bool,
+    /// Length of the key dump file when the state was saved. Anything after this is discarded when resuming, since it belongs to chunks that weren't fully checked
+    #[prost(uint64, optional, tag = "7")]
+    pub key_dump_len: Option<u64>,
+    /// One per worklet. The worklet total can't change when resuming, since it affects how the key space is sliced
+    #[prost(message, repeated, tag = "8")]
+    pub worklets: Vec<WorkletState>,
+    /// Path to the key dump being refined, if any. See KeyListCipher
+    #[prost(string, optional, tag = "9")]
+    pub refine_key_dump_path: Option<String>,
+}
+
+/**
+ * Writes to a temporary file first and then renames it, so that the previous
+ * state is never lost if the process is killed while saving
+ */
+pub fn export_search_state(path: &PathBuf, state: &SearchState) -> UnitResult {
+    let mut tmp_path = path.clone().into_os_string();
+    tmp_path.push(".tmp");
+
+    let mut file = std::fs::File::create(&tmp_path)?;
+    file.write_all(state.encode_to_vec().as_slice())?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+pub fn import_search_state(path: &PathBuf) -> AnyErrorResult<SearchState> {
+    let buffer = std::fs::read(path)?;
+    Ok(SearchState::decode(buffer.as_slice())?)
+}
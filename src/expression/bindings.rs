@@ -0,0 +1,369 @@
+use std::{cell::OnceCell, error::Error, marker::PhantomData};
+
+use hot_eval::codegen::compiled_expression::Slab;
+use hot_eval::common::binding::{Binding, FnPointer, FnSpecCallArg, FnSpecChoice};
+use hot_eval::common::ir_const::IRConst;
+use hot_eval::common::table::Table;
+use hot_eval::common::value::Value;
+use hot_eval::common::value_type::ValueType;
+
+use crate::{analysis::{ngram::{MAX_NGRAM_LEN, NgramModel}, unit_freq::UnitFrequency}, ciphers::base::{CipherCodecContext, CipherKey, CipherWorkletContext}, data::message::InterleavedMessageData};
+
+fn eval_in(messages: &InterleavedMessageData, m: usize, u: usize) -> u8 {
+    messages[(m, u)]
+}
+
+fn eval_in_freq_dist_error(in_freq_dist_errors: &Box<[f64]>, l: usize) -> f64 {
+    in_freq_dist_errors[l]
+}
+
+fn eval_out<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, m: usize, u: usize) -> u8
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    codec_ctx.get_output(m, u)
+}
+
+unsafe fn eval_out_unchecked<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, m: usize, u: usize) -> u8
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    // SAFETY: caller must verify bounds
+    unsafe { codec_ctx.get_output_unchecked(m, u) }
+}
+
+fn eval_out_freq_dist_error_specific<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, out_freq_dist: &OnceCell<UnitFrequency>, language: &UnitFrequency) -> f64
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    language.get_error(out_freq_dist.get_or_init(|| {
+        UnitFrequency::from_message_data_list(&codec_ctx.get_output_messages())
+    }))
+}
+
+fn eval_out_freq_dist_error<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, out_freq_dist: &OnceCell<UnitFrequency>, languages: &Vec<UnitFrequency>, l: usize) -> f64
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    eval_out_freq_dist_error_specific::<DECRYPT, K, W>(codec_ctx, out_freq_dist, &languages[l])
+}
+
+fn eval_in_ngram_score(in_ngram_scores: &Box<[[f64; MAX_NGRAM_LEN]]>, l: usize, n: usize) -> f64 {
+    match n {
+        1..=MAX_NGRAM_LEN => in_ngram_scores[l][n - 1],
+        _ => f64::NAN,
+    }
+}
+
+fn eval_out_ngram_score_specific<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, model: &NgramModel, n: usize) -> f64
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    let in_msgs = codec_ctx.get_input_messages();
+    model.get_mean_log_prob(n, (0..in_msgs.get_message_count()).map(|m| {
+        // SAFETY: m is in bounds, since it's in 0..get_message_count(), and u
+        //         is in bounds, since it's in 0..get_unit_count(m)
+        (0..unsafe { in_msgs.get_unit_count(m) }).map(move |u| unsafe { codec_ctx.get_output_unchecked(m, u) })
+    }))
+}
+
+fn eval_out_ngram_score<const DECRYPT: bool, K, W>(codec_ctx: &W::CodecContext<'_, DECRYPT>, ngram_models: &Vec<NgramModel>, l: usize, n: usize) -> f64
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    eval_out_ngram_score_specific::<DECRYPT, K, W>(codec_ctx, &ngram_models[l], n)
+}
+
+/**
+ * Bindings for expressions that inspect the inputs and outputs of a cipher
+ * (in, out, in_freq_dist_error, out_freq_dist_error, in_ngram_score,
+ * out_ngram_score). Used by search conditions and any other expression
+ * evaluated once per key. Compiled expressions keep pointers to this, so it's
+ * always boxed, and must outlive the compiled expression
+ */
+pub struct CodecBindings<const DECRYPT: bool, K, W> {
+    out_freq_dist: OnceCell<UnitFrequency>,
+    codec_ctx_hsi: usize,
+    _phantom: PhantomData<fn() -> (K, W)>,
+}
+
+impl<const DECRYPT: bool, K, W> CodecBindings<DECRYPT, K, W>
+where
+    K: CipherKey,
+    W: CipherWorkletContext<K>,
+{
+    pub fn add_to_table<'inputs>(table: &mut Table<'inputs>, messages: &'inputs InterleavedMessageData, languages: &'inputs Vec<UnitFrequency>, ngram_models: &'inputs Vec<NgramModel>) -> Result<Box<Self>, Box<dyn Error>> {
+        let codec_ctx_hsi = table.add_hidden_state(ValueType::USize);
+        let bindings = Box::new(Self { out_freq_dist: OnceCell::new(), codec_ctx_hsi, _phantom: PhantomData });
+        let out_freq_dist_ptr = &bindings.out_freq_dist as *const OnceCell<UnitFrequency>;
+        let languages_ptr = languages as *const Vec<UnitFrequency>;
+        let ngram_models_ptr = ngram_models as *const Vec<NgramModel>;
+
+        let in_freq_dist_errors: Box<[f64]> = {
+            let mut errors = Vec::<f64>::new();
+            for language in languages {
+                errors.push(language.get_error(
+                    &UnitFrequency::from_interleaved_message_data(messages)
+                ));
+            }
+
+            errors.into()
+        };
+
+        let in_ngram_scores: Box<[[f64; MAX_NGRAM_LEN]]> = ngram_models.iter().map(|model| {
+            std::array::from_fn(|i| model.get_mean_log_prob(i + 1, (0..messages.get_message_count()).map(|m| {
+                // SAFETY: m is in bounds, since it's in 0..get_message_count()
+                (0..unsafe { messages.get_unit_count(m) }).map(move |u| messages[(m, u)])
+            })))
+        }).collect();
+
+        // SAFETY: all specialization closures only return an unchecked function's
+        //         pointer if it can prove the inputs are always in-bounds, and have
+        //         correctly mapped parameters
+
+        unsafe { table.add_binding("in".into(), Binding::Function {
+            ret_type: ValueType::U8,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+                // param 1: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                let args = [
+                    // messages: &InterleavedMessageData
+                    FnSpecCallArg::from((messages as *const InterleavedMessageData).addr()),
+                    // m: usize (param 0)
+                    FnSpecCallArg::MappedArgument { param_idx: 0 },
+                    // u: usize (param 1)
+                    FnSpecCallArg::MappedArgument { param_idx: 1 },
+                ].into();
+
+                if let [Some(IRConst::Uint { inner: m }), Some(IRConst::Uint { inner: u })] = *hints.consts {
+                    let m = m as usize;
+                    let u = u as usize;
+                    if m < messages.get_message_count() && u < messages.get_unit_count(m) {
+                        Ok(FnSpecChoice::Const { value: Value::U8 { inner: messages[(m, u)] } })
+                    } else {
+                        Err("in() call in expression is always out of bounds".into())
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call { fn_ptr: eval_in as FnPointer, args })
+                }
+            }),
+        })? };
+
+        unsafe { table.add_binding("in_freq_dist_error".into(), Binding::Function {
+            ret_type: ValueType::F64,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                if let [Some(IRConst::Uint { inner: l })] = *hints.consts {
+                    let l = l as usize;
+                    if l < languages.len() {
+                        Ok(FnSpecChoice::Const { value: Value::F64 { inner: in_freq_dist_errors[l] } })
+                    } else {
+                        Err("in_freq_dist_error() call in expression is always out of bounds".into())
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call {
+                        fn_ptr: eval_in_freq_dist_error as FnPointer,
+                        args: [
+                            // in_freq_dist_errors: &Box<[f64]>
+                            FnSpecCallArg::from((&in_freq_dist_errors as *const Box<[f64]>).addr()),
+                            // l: usize (param 0)
+                            FnSpecCallArg::MappedArgument { param_idx: 0 },
+                        ].into(),
+                    })
+                }
+            }),
+        })? };
+
+        unsafe { table.add_binding("out".into(), Binding::Function {
+            ret_type: ValueType::U8,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+                // param 1: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                let args = [
+                    // codec_ctx: &W::CodecContext<'_, DECRYPT>
+                    FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
+                    // m: usize (param 0)
+                    FnSpecCallArg::MappedArgument { param_idx: 0 },
+                    // u: usize (param 1)
+                    FnSpecCallArg::MappedArgument { param_idx: 1 },
+                ].into();
+
+                if let [Some(IRConst::Uint { inner: m }), Some(IRConst::Uint { inner: u })] = *hints.consts {
+                    let m = m as usize;
+                    if m < messages.get_message_count() && (u as usize) < messages.get_unit_count(m) {
+                        Ok(FnSpecChoice::Call { fn_ptr: eval_out_unchecked::<DECRYPT, K, W> as FnPointer, args })
+                    } else {
+                        Err("out() call in expression is always out of bounds".into())
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call { fn_ptr: eval_out::<DECRYPT, K, W> as FnPointer, args })
+                }
+            }),
+        })? };
+
+        unsafe { table.add_binding("out_freq_dist_error".into(), Binding::Function {
+            ret_type: ValueType::F64,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                if let [Some(IRConst::Uint { inner: l })] = *hints.consts {
+                    let l = l as usize;
+                    if l < languages.len() {
+                        Ok(FnSpecChoice::Call {
+                            fn_ptr: eval_out_freq_dist_error_specific::<DECRYPT, K, W> as FnPointer,
+                            args: [
+                                // codec_ctx: &W::CodecContext<'_, DECRYPT>
+                                FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
+                                // out_freq_dist: &OnceCell<UnitFrequency>
+                                FnSpecCallArg::from(out_freq_dist_ptr.addr()),
+                                // language: &UnitFrequency
+                                FnSpecCallArg::from((&languages[l] as *const UnitFrequency).addr()),
+                            ].into(),
+                        })
+                    } else {
+                        Err("out_freq_dist_error() call in expression is always out of bounds".into())
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call {
+                        fn_ptr: eval_out_freq_dist_error::<DECRYPT, K, W> as FnPointer,
+                        args: [
+                            // codec_ctx: &W::CodecContext<'_, DECRYPT>
+                            FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
+                            // out_freq_dist: &OnceCell<UnitFrequency>
+                            FnSpecCallArg::from(out_freq_dist_ptr.addr()),
+                            // languages: &Vec<UnitFrequency>
+                            FnSpecCallArg::from(languages_ptr.addr()),
+                            // l: usize (param 0)
+                            FnSpecCallArg::MappedArgument { param_idx: 0 },
+                        ].into(),
+                    })
+                }
+            }),
+        })? };
+
+        unsafe { table.add_binding("in_ngram_score".into(), Binding::Function {
+            ret_type: ValueType::F64,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+                // param 1: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                if let [Some(IRConst::Uint { inner: l }), Some(IRConst::Uint { inner: n })] = *hints.consts {
+                    let l = l as usize;
+                    let n = n as usize;
+                    if l >= ngram_models.len() {
+                        Err("in_ngram_score() call in expression is always out of bounds".into())
+                    } else if !ngram_models[l].has_len(n) {
+                        Err(format!("in_ngram_score() call in expression uses {}-grams, but language model {} has none", n, l).into())
+                    } else {
+                        Ok(FnSpecChoice::Const { value: Value::F64 { inner: in_ngram_scores[l][n - 1] } })
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call {
+                        fn_ptr: eval_in_ngram_score as FnPointer,
+                        args: [
+                            // in_ngram_scores: &Box<[[f64; MAX_NGRAM_LEN]]>
+                            FnSpecCallArg::from((&in_ngram_scores as *const Box<[[f64; MAX_NGRAM_LEN]]>).addr()),
+                            // l: usize (param 0)
+                            FnSpecCallArg::MappedArgument { param_idx: 0 },
+                            // n: usize (param 1)
+                            FnSpecCallArg::MappedArgument { param_idx: 1 },
+                        ].into(),
+                    })
+                }
+            }),
+        })? };
+
+        unsafe { table.add_binding("out_ngram_score".into(), Binding::Function {
+            ret_type: ValueType::F64,
+            params: [
+                // param 0: usize
+                ValueType::USize,
+                // param 1: usize
+                ValueType::USize,
+            ].into(),
+            fn_spec: Box::new(move |hints| {
+                if let [Some(IRConst::Uint { inner: l }), Some(IRConst::Uint { inner: n })] = *hints.consts {
+                    let l = l as usize;
+                    let n = n as usize;
+                    if l >= ngram_models.len() {
+                        Err("out_ngram_score() call in expression is always out of bounds".into())
+                    } else if !ngram_models[l].has_len(n) {
+                        Err(format!("out_ngram_score() call in expression uses {}-grams, but language model {} has none", n, l).into())
+                    } else {
+                        Ok(FnSpecChoice::Call {
+                            fn_ptr: eval_out_ngram_score_specific::<DECRYPT, K, W> as FnPointer,
+                            args: [
+                                // codec_ctx: &W::CodecContext<'_, DECRYPT>
+                                FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
+                                // model: &NgramModel
+                                FnSpecCallArg::from((&ngram_models[l] as *const NgramModel).addr()),
+                                // n: usize
+                                FnSpecCallArg::from(n),
+                            ].into(),
+                        })
+                    }
+                } else {
+                    Ok(FnSpecChoice::Call {
+                        fn_ptr: eval_out_ngram_score::<DECRYPT, K, W> as FnPointer,
+                        args: [
+                            // codec_ctx: &W::CodecContext<'_, DECRYPT>
+                            FnSpecCallArg::from_hidden_state(codec_ctx_hsi),
+                            // ngram_models: &Vec<NgramModel>
+                            FnSpecCallArg::from(ngram_models_ptr.addr()),
+                            // l: usize (param 0)
+                            FnSpecCallArg::MappedArgument { param_idx: 0 },
+                            // n: usize (param 1)
+                            FnSpecCallArg::MappedArgument { param_idx: 1 },
+                        ].into(),
+                    })
+                }
+            }),
+        })? };
+
+        Ok(bindings)
+    }
+
+    /**
+     * Points the bindings to the codec context of the next key to evaluate,
+     * clearing all caches of the previous key. Must be called before every
+     * evaluation of the compiled expression
+     *
+     * SAFETY: slab must be the slab of an expression compiled with the table
+     *         that these bindings were added to, and codec_ctx must outlive
+     *         all evaluations until the next call
+     */
+    pub unsafe fn set_codec_context(&mut self, slab: &mut Slab, codec_ctx: &W::CodecContext<'_, DECRYPT>) {
+        // TODO clearing the cache results in a 5% slowdown. hot-eval should
+        //      support pure functions, so that it reuses outputs when possible,
+        //      otherwise we have to unnecessarily clear a cache and manage our
+        //      own lazy cell, even when there's only a single call in the
+        //      expression
+        self.out_freq_dist.take(); // clear cache
+
+        // SAFETY: caller must guarantee that codec_ctx outlives the
+        //         evaluations it's used in
+        unsafe { slab.set_ptr_value_unchecked(self.codec_ctx_hsi, codec_ctx); }
+    }
+}
@@ -1,4 +1,5 @@
 pub mod utils;
 pub mod data;
 pub mod analysis;
-pub mod ciphers;
\ No newline at end of file
+pub mod ciphers;
+pub mod expression;
\ No newline at end of file
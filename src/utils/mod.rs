@@ -2,4 +2,5 @@ pub mod threading;
 pub mod compare;
 pub mod print;
 pub mod stackvec;
-pub mod run;
\ No newline at end of file
+pub mod run;
+pub mod top_k;
\ No newline at end of file
@@ -1,4 +1,4 @@
-use crate::{analysis::alphabet::Alphabet, data::{message::Message, render_message::{MessageRenderGroup, MessageRenderMap, RenderMessage}}};
+use crate::{analysis::{alphabet::Alphabet, plot::hsv_to_rgb}, data::{message::{Message, MessageList}, render_message::{MessageRenderGroup, MessageRenderMap, RenderMessage}}};
 use colored::Colorize;
 use rug::Integer;
 
@@ -15,6 +15,17 @@ pub struct MessagePrintConfig {
 pub struct MessagesPrintConfig {
     pub max_len: u32,
     pub multiview: bool,
+    /// Offset added to all units for presentation purposes. If None, the offset
+    /// is computed from the printed messages (see get_presentation_offset).
+    /// Useful for printing parts of messages in the same way as the whole
+    /// messages
+    pub presentation_offset: Option<u8>,
+    /// Print messages stacked in columns, one column per unit index, under a
+    /// ruler with the unit indices. Columns where all messages have the same
+    /// unit are highlighted, and the other columns are coloured from yellow to
+    /// red depending on how many different units they have. Text between
+    /// units isn't printed, so that all messages stay aligned
+    pub aligned: bool,
 }
 
 pub struct UnitPrintConfig {
@@ -201,18 +212,17 @@ pub fn print_message(msg: &Message, render_message: &RenderMessage, alphabet: &A
     println!();
 }
 
-pub fn print_messages(title: &str, message_render_map: &MessageRenderMap, alphabet: &Alphabet, config: &MessagesPrintConfig) {
+/**
+ * Offset that must be added to all units so that the messages can be printed
+ * with the alphabet, or None if all units are already printable. Messages
+ * stored as numbers (like the original messages) usually don't start at the
+ * first printable unit of the alphabet
+ */
+pub fn get_presentation_offset(messages: &MessageList, alphabet: &Alphabet) -> Option<u8> {
     let min_unit_alphabet = alphabet.get_unit_min();
     let mut min_unprintable_unit: Option<u8> = None;
-    let messages = message_render_map.get_messages();
-    let render_messages = message_render_map.get_render_messages();
-    let mut max_unit_count = 0usize;
-    let mut max_msg_len = 0usize;
-    let mut max_name_len = 0usize;
-
-    for m in 0..messages.len() {
-        let message = &messages[m];
 
+    for message in messages.iter() {
         for u in message.data.iter() {
             let u = *u;
             if !alphabet.get_unit(u).is_some_and(|x| x.is_printable()) {
@@ -225,6 +235,97 @@ pub fn print_messages(title: &str, message_render_map: &MessageRenderMap, alphab
                 }
             }
         }
+    }
+
+    min_unprintable_unit.map(|min_u| min_unit_alphabet.wrapping_sub(min_u))
+}
+
+/// Ruler with the unit index of every 10th column, and a tick line below it
+fn print_ruler(indent: usize, columns: usize) {
+    let mut labels = String::new();
+    let mut ticks = String::new();
+
+    for i in 0..columns {
+        if i % 10 == 0 {
+            // labels are left-aligned with their column, and never overlap
+            // since they're 10 columns apart
+            let label = i.to_string();
+            if labels.len() < i + indent {
+                labels.push_str(&" ".repeat(i + indent - labels.len()));
+            }
+
+            labels.push_str(&label);
+        }
+
+        ticks.push(if i % 10 == 0 { '|' } else if i % 5 == 0 { ':' } else { '.' });
+    }
+
+    println!("{}", labels.bright_black());
+    println!("{}{}", " ".repeat(indent), ticks.bright_black());
+}
+
+fn print_messages_aligned(messages: &MessageList, alphabet: &Alphabet, max_name_len: usize, add: u8, config: &MessagesPrintConfig) {
+    let mut columns = messages.iter().map(|message| message.data.len()).max().unwrap_or(0);
+    if config.max_len != 0 {
+        columns = columns.min(config.max_len as usize);
+    }
+
+    let indent = max_name_len + 2;
+    print_ruler(indent, columns);
+
+    // how many messages have each column, and how many different units
+    let mut column_colours = Vec::with_capacity(columns);
+    for i in 0..columns {
+        let mut units: Vec<u8> = messages.iter().filter_map(|message| message.data.get(i).copied()).collect();
+        let present = units.len();
+        units.sort_unstable();
+        units.dedup();
+        let distinct = units.len();
+
+        column_colours.push(if present < 2 {
+            None
+        } else if distinct == 1 {
+            Some(None)
+        } else {
+            // yellow for 2 different units, red if all units are different
+            let fraction = (distinct - 1) as f64 / (present - 1) as f64;
+            Some(Some(hsv_to_rgb(0.16 * (1.0 - fraction), 1.0, 1.0)))
+        });
+    }
+
+    for message in messages.iter() {
+        print!("{}", format!("{: >max_name_len$}: ", message.name).bright_black());
+
+        for (i, u) in message.data.iter().take(columns).enumerate() {
+            let cell = match alphabet.get_unit(u.wrapping_add(add)) {
+                Some(alpha_unit) if alpha_unit.is_printable() => alpha_unit.grapheme.to_string(),
+                _ => String::from("#"),
+            };
+
+            match column_colours[i] {
+                None => print!("{}", cell),
+                Some(None) => print!("{}", cell.black().on_bright_green()),
+                Some(Some([r, g, b])) => print!("{}", cell.truecolor(r, g, b)),
+            }
+        }
+
+        if message.data.len() > columns {
+            print!("{}", "...".bright_black());
+        }
+
+        println!();
+    }
+}
+
+pub fn print_messages(title: &str, message_render_map: &MessageRenderMap, alphabet: &Alphabet, config: &MessagesPrintConfig) {
+    let messages = message_render_map.get_messages();
+    let render_messages = message_render_map.get_render_messages();
+    let mut max_unit_count = 0usize;
+    let mut max_msg_len = 0usize;
+    let mut max_name_len = 0usize;
+
+    for m in 0..messages.len() {
+        let message = &messages[m];
 
         max_unit_count = max_unit_count.max(message.data.len());
         max_msg_len = max_msg_len.max(message_render_map.get_render_messages()[m].get_msg_len());
@@ -239,9 +340,16 @@ pub fn print_messages(title: &str, message_render_map: &MessageRenderMap, alphab
         msg_name_len_hint: Some(max_name_len),
     };
 
-    if let Some(min_u) = min_unprintable_unit {
-        let add = min_unit_alphabet.wrapping_sub(min_u);
+    let add = config.presentation_offset.or_else(|| get_presentation_offset(messages, alphabet));
+
+    if config.aligned {
+        match add {
+            Some(add) => println!("{title} [transformed for presentation purposes: (unit + {add}) % 256]:"),
+            None => println!("{title}:"),
+        }
 
+        print_messages_aligned(messages, alphabet, max_name_len, add.unwrap_or(0), config);
+    } else if let Some(add) = add {
         println!("{title} [transformed for presentation purposes: (unit + {add}) % 256]:");
 
         for m in 0..messages.len() {
@@ -0,0 +1,92 @@
+use std::{cmp::{Ordering, Reverse}, collections::BinaryHeap};
+
+struct Scored<T> {
+    score: f64,
+    item: T,
+}
+
+impl<T> PartialEq for Scored<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.score.total_cmp(&other.score) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Scored<T> {}
+
+impl<T> PartialOrd for Scored<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scored<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.score.total_cmp(&other.score)
+    }
+}
+
+/// Keeps the items with the highest scores, up to a fixed capacity. Items
+/// with NaN scores are never kept. When scores are tied, the item that was
+/// pushed first is kept
+pub struct TopK<T> {
+    capacity: usize,
+    // min-heap, so that the worst kept item can be replaced quickly
+    heap: BinaryHeap<Reverse<Scored<T>>>,
+}
+
+impl<T> TopK<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /**
+     * Whether an item with this score would be kept if pushed. Useful for
+     * skipping expensive work, like encoding keys, for items that would be
+     * discarded anyway
+     */
+    pub fn accepts(&self, score: f64) -> bool {
+        if score.is_nan() {
+            false
+        } else if self.heap.len() < self.capacity {
+            true
+        } else {
+            match self.heap.peek() {
+                Some(Reverse(worst)) => score > worst.score,
+                None => false,
+            }
+        }
+    }
+
+    /// Returns whether the item was kept
+    pub fn push(&mut self, score: f64, item: T) -> bool {
+        if !self.accepts(score) {
+            return false;
+        }
+
+        if self.heap.len() >= self.capacity {
+            self.heap.pop();
+        }
+
+        self.heap.push(Reverse(Scored { score, item }));
+        true
+    }
+
+    pub fn merge(&mut self, other: TopK<T>) {
+        for Reverse(scored) in other.heap {
+            self.push(scored.score, scored.item);
+        }
+    }
+
+    /// Kept items with their scores, best first
+    pub fn into_sorted_vec(self) -> Vec<(f64, T)> {
+        // sorted by Reverse, so ascending order of Reverse is best first
+        self.heap.into_sorted_vec().into_iter().map(|Reverse(scored)| (scored.score, scored.item)).collect()
+    }
+}